@@ -0,0 +1,387 @@
+use crate::models::OptionChain;
+use crate::others::{connect_with_timeout, get_new_york_time, load_json};
+use crate::MONSTER_SIZE;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Write};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum AlertRule {
+    /// Any recognised spread or single leg whose absolute net value is above the amount.
+    SpreadNetValueOver(f64),
+    /// A sweep print of at least this many contracts.
+    SweepAbove(i64),
+    BiasFlip,
+    DealerGammaFlip,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum AlertSink {
+    Stdout,
+    /// Appends one JSON alert per line.
+    File(String),
+    /// POSTs each alert as JSON.
+    Webhook(String),
+    /// Sends a plain text mail through an SMTP server without authentication, such as a local
+    /// relay or a capture server used for testing.
+    Email {
+        server: String,
+        from: String,
+        to: String,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AlertConfig {
+    pub rules: Vec<AlertRule>,
+    pub sinks: Vec<AlertSink>,
+}
+impl Default for AlertConfig {
+    fn default() -> Self {
+        Self {
+            rules: vec![
+                AlertRule::SpreadNetValueOver(MONSTER_SIZE),
+                AlertRule::SweepAbove(1000),
+                AlertRule::BiasFlip,
+                AlertRule::DealerGammaFlip,
+            ],
+            sinks: vec![AlertSink::Stdout],
+        }
+    }
+}
+impl AlertConfig {
+    /// The default rules print to stdout when there is no config, but a config that doesn't
+    /// parse is an error rather than silently losing its sinks.
+    pub fn load(filename: &str) -> Result<Self, String> {
+        Ok(load_json(filename)?.unwrap_or_default())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Alert {
+    pub symbol: String,
+    pub rule: String,
+    pub message: String,
+    pub value: f64,
+    pub fired_at: String,
+    #[serde(skip)]
+    pub key: String,
+}
+impl Alert {
+    fn new(symbol: &str, rule: &str, message: String, value: f64, key: String) -> Self {
+        Self {
+            symbol: symbol.to_string(),
+            rule: rule.to_string(),
+            message,
+            value,
+            fired_at: get_new_york_time().format("%F %T").to_string(),
+            key,
+        }
+    }
+    pub fn line(&self) -> String {
+        format!(
+            "[{}] {} {}: {}",
+            self.fired_at, self.symbol, self.rule, self.message
+        )
+    }
+}
+
+/// Checks rebuilt chains against the configured rules. Trade rules fire once per print and flip
+/// rules fire when the sign changes from the last chain seen for that symbol.
+#[derive(Debug, Default)]
+pub struct AlertEngine {
+    pub config: AlertConfig,
+    fired: HashSet<String>,
+    previous: HashMap<String, (i64, f64)>,
+}
+impl AlertEngine {
+    pub fn new(config: AlertConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+    pub fn evaluate(&mut self, chain: &OptionChain) -> Vec<Alert> {
+        let mut alerts: Vec<Alert> = Vec::new();
+        let previous = self.previous.get(&chain.symbol).cloned();
+        for rule in &self.config.rules {
+            match *rule {
+                AlertRule::SpreadNetValueOver(amount) => {
+                    for spread in chain
+                        .spreads
+                        .iter()
+                        .filter(|spread| spread.net_value.abs() > amount)
+                    {
+                        alerts.push(Alert::new(
+                            &chain.symbol,
+                            "Large Spread",
+                            format!(
+                                "{:?} {} for {} ({})",
+                                spread.spread_name,
+                                spread.expiration_date,
+                                spread.net_value,
+                                spread.summary
+                            ),
+                            spread.net_value,
+                            format!("spread|{}|{}", chain.symbol, spread.sequence_numbers),
+                        ))
+                    }
+                }
+                AlertRule::SweepAbove(contracts) => {
+                    for trade in chain.trades.iter().filter(|trade| {
+                        trade.condition_id.is_sweep() && trade.option_trade_size >= contracts
+                    }) {
+                        alerts.push(Alert::new(
+                            &chain.symbol,
+                            "Large Sweep",
+                            format!(
+                                "{} x {} at {} {:?}",
+                                trade.option_trade_size,
+                                trade.symbol,
+                                trade.option_trade_price.unwrap_or(0.0),
+                                trade.order_action
+                            ),
+                            trade.notional_value,
+                            format!("sweep|{}|{}", chain.symbol, trade.seq_no),
+                        ))
+                    }
+                }
+                AlertRule::BiasFlip => {
+                    if let Some((bias, _)) = previous {
+                        if bias.signum() != 0 && bias.signum() == -chain.bias.signum() {
+                            alerts.push(Alert::new(
+                                &chain.symbol,
+                                "Bias Flip",
+                                format!("bias went from {} to {}", bias, chain.bias),
+                                chain.bias as f64,
+                                format!("bias|{}|{}", chain.symbol, chain.data_timestamp),
+                            ))
+                        }
+                    }
+                }
+                AlertRule::DealerGammaFlip => {
+                    if let Some((_, gamma)) = previous {
+                        if gamma * chain.dealer_gamma < 0.0 {
+                            alerts.push(Alert::new(
+                                &chain.symbol,
+                                "Dealer Gamma Flip",
+                                format!(
+                                    "dealer gamma went from {:.2} to {:.2}",
+                                    gamma, chain.dealer_gamma
+                                ),
+                                chain.dealer_gamma,
+                                format!("gamma|{}|{}", chain.symbol, chain.data_timestamp),
+                            ))
+                        }
+                    }
+                }
+            }
+        }
+        self.previous
+            .insert(chain.symbol.clone(), (chain.bias, chain.dealer_gamma));
+        alerts
+            .into_iter()
+            .filter(|alert| self.fired.insert(alert.key.clone()))
+            .collect()
+    }
+    pub async fn dispatch(&self, alerts: &[Alert]) {
+        for alert in alerts {
+            for sink in &self.config.sinks {
+                if let Err(e) = send(sink, alert).await {
+                    println!("could not send alert to {:?}: {}", sink, e)
+                }
+            }
+        }
+    }
+}
+
+async fn send(sink: &AlertSink, alert: &Alert) -> Result<(), Box<dyn std::error::Error>> {
+    match sink {
+        AlertSink::Stdout => println!("{}", alert.line()),
+        AlertSink::File(filename) => {
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(filename)?;
+            writeln!(file, "{}", serde_json::to_string(alert)?)?;
+        }
+        AlertSink::Webhook(url) => {
+            reqwest::Client::new()
+                .post(url)
+                .json(alert)
+                .send()
+                .await?
+                .error_for_status()?;
+        }
+        AlertSink::Email { server, from, to } => {
+            let (server, from, to, alert) =
+                (server.clone(), from.clone(), to.clone(), alert.clone());
+            tokio::task::spawn_blocking(move || send_mail(&server, &from, &to, &alert)).await??
+        }
+    }
+    Ok(())
+}
+
+fn send_mail(server: &str, from: &str, to: &str, alert: &Alert) -> std::io::Result<()> {
+    let stream = connect_with_timeout(server)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    let mut expect = |code: &str| -> std::io::Result<()> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            reader.read_line(&mut line)?;
+            // multi line replies continue with a dash after the code
+            if line.len() < 4 || line.as_bytes()[3] != b'-' {
+                break;
+            }
+        }
+        if line.starts_with(code) {
+            Ok(())
+        } else {
+            Err(std::io::Error::other(format!(
+                "smtp replied {}",
+                line.trim()
+            )))
+        }
+    };
+    expect("220")?;
+    let subject = format!("{} {}", alert.symbol, alert.rule);
+    for (command, code) in [
+        ("HELO options-scan".to_string(), "250"),
+        (format!("MAIL FROM:<{}>", from), "250"),
+        (format!("RCPT TO:<{}>", to), "250"),
+        ("DATA".to_string(), "354"),
+        (
+            format!(
+                "From: {}\r\nTo: {}\r\nSubject: {}\r\n\r\n{}\r\n.",
+                from,
+                to,
+                subject,
+                dot_stuff(&alert.line())
+            ),
+            "250",
+        ),
+        ("QUIT".to_string(), "221"),
+    ] {
+        write!(writer, "{}\r\n", command)?;
+        expect(code)?;
+    }
+    Ok(())
+}
+
+/// Body lines in CRLF with a leading dot doubled, so no line of the alert can end the DATA
+/// section early.
+fn dot_stuff(body: &str) -> String {
+    body.lines()
+        .map(|line| {
+            if line.starts_with('.') {
+                format!(".{}", line)
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\r\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixture_chain;
+
+    #[test]
+    fn fires_large_spread_once() {
+        let chain = fixture_chain("SPY");
+        let mut engine = AlertEngine::new(AlertConfig {
+            rules: vec![AlertRule::SpreadNetValueOver(200000.0)],
+            sinks: vec![],
+        });
+        let alerts = engine.evaluate(&chain);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].rule, "Large Spread");
+        assert!(engine.evaluate(&chain).is_empty());
+    }
+
+    #[test]
+    fn fires_on_bias_flip() {
+        let chain = fixture_chain("SPY");
+        let mut engine = AlertEngine::new(AlertConfig {
+            rules: vec![AlertRule::BiasFlip],
+            sinks: vec![],
+        });
+        let before = OptionChain {
+            bias: 3,
+            data_timestamp: "10:30".to_string(),
+            ..chain.clone()
+        };
+        let after = OptionChain {
+            bias: -2,
+            data_timestamp: "10:31".to_string(),
+            ..chain
+        };
+        assert!(engine.evaluate(&before).is_empty());
+        assert!(engine.evaluate(&before).is_empty());
+        let alerts = engine.evaluate(&after);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].message, "bias went from 3 to -2");
+    }
+
+    #[test]
+    fn refuses_a_config_it_cannot_read() {
+        let filename = std::env::temp_dir().join("options-scan-alert-config-test.json");
+        let filename = filename.to_str().unwrap();
+        std::fs::write(filename, r#"{"rules": [], "sinks": [{"Slack": "desk"}]}"#).unwrap();
+        assert!(AlertConfig::load(filename).is_err());
+        std::fs::remove_file(filename).unwrap();
+        assert_eq!(AlertConfig::load(filename).unwrap(), AlertConfig::default());
+    }
+
+    #[test]
+    fn mails_through_local_smtp() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        let relay = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut received = String::new();
+            let mut in_data = false;
+            writer.write_all(b"220 ready\r\n").unwrap();
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                if in_data {
+                    if line == ".\r\n" {
+                        in_data = false;
+                        writer.write_all(b"250 queued\r\n").unwrap();
+                    } else {
+                        received.push_str(&line);
+                    }
+                } else if line.starts_with("DATA") {
+                    in_data = true;
+                    writer.write_all(b"354 go ahead\r\n").unwrap();
+                } else if line.starts_with("QUIT") {
+                    writer.write_all(b"221 bye\r\n").unwrap();
+                    break;
+                } else {
+                    writer.write_all(b"250 ok\r\n").unwrap();
+                }
+            }
+            received
+        });
+        let alert = Alert::new(
+            "SPY",
+            "Bias Flip",
+            "bias went from 3 to -2\n.\nafter the dot".to_string(),
+            -2.0,
+            String::new(),
+        );
+        send_mail(&server, "scanner@localhost", "desk@localhost", &alert).unwrap();
+        let received = relay.join().unwrap();
+        assert!(received.contains("Subject: SPY Bias Flip"));
+        assert!(received.contains("bias went from 3 to -2\r\n..\r\nafter the dot"));
+    }
+}
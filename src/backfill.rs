@@ -0,0 +1,291 @@
+use crate::models::{ChainSnapshot, OptionChain, ServerResponse, ShortStockInfo};
+use crate::others::{load_json, open_json};
+use crate::requests::{get_auth, get_options, get_trades_for_date};
+use crate::scoring::{BiasModel, FactorHistory, ScoringConfig};
+use crate::trades::OptionTrade;
+use crate::BACKFILL_DIRECTORY;
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+
+impl ChainSnapshot {
+    /// Reads `{SYMBOL}-snapshot.json` and, when present, `{SYMBOL}-trades.json` from a
+    /// directory. Both files hold the responses exactly as LiveVol sent them. `Ok(None)` when
+    /// there is no usable snapshot to build on, and an error when the trades don't parse, so a
+    /// day is never scored as if nothing traded.
+    pub fn load(directory: &str, symbol: &str, as_of: NaiveDate) -> Result<Option<Self>, String> {
+        let Some(data) = open_json(&format!("{}/{}-snapshot.json", directory, symbol))
+            .ok()
+            .and_then(|value| serde_json::from_value::<ServerResponse>(value).ok())
+        else {
+            return Ok(None);
+        };
+        let trades: Vec<OptionTrade> =
+            load_json(&format!("{}/{}-trades.json", directory, symbol))?.unwrap_or_default();
+        Ok(Some(Self {
+            data,
+            trades,
+            dividend_info: None,
+            insiders: 0.0,
+            short_interest_percent: 0.0,
+            market_cap: 0.0,
+            beta: 0.0,
+            as_of,
+        }))
+    }
+}
+
+/// Downloads quotes and trades for one symbol and date into `{BACKFILL_DIRECTORY}/{date}`,
+/// keeping the raw responses so later runs don't spend request points again. Requests are spaced
+/// at least a second apart, and nothing is written unless both responses parse.
+pub async fn fetch_history(symbol: &str, token: &str, date: NaiveDate) -> Result<(), String> {
+    let directory = history_directory(date);
+    let date = date.format("%F").to_string();
+    std::fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+    let start_time = tokio::time::Instant::now();
+    let options = get_options(symbol, token, &date)
+        .await
+        .map_err(|e| e.to_string())?
+        .text()
+        .await
+        .map_err(|e| e.to_string())?;
+    wait_out_second(start_time).await;
+    let start_time = tokio::time::Instant::now();
+    let trades = get_trades_for_date(symbol, token, &date)
+        .await
+        .map_err(|e| e.to_string())?
+        .text()
+        .await
+        .map_err(|e| e.to_string())?;
+    wait_out_second(start_time).await;
+    if serde_json::from_str::<ServerResponse>(&options).is_err() {
+        return Err(format!(
+            "unexpected options response for {} {}",
+            symbol, date
+        ));
+    }
+    if serde_json::from_str::<Vec<OptionTrade>>(&trades).is_err() {
+        return Err(format!(
+            "unexpected trades response for {} {}",
+            symbol, date
+        ));
+    }
+    std::fs::write(format!("{}/{}-snapshot.json", directory, symbol), options)
+        .map_err(|e| e.to_string())?;
+    std::fs::write(format!("{}/{}-trades.json", directory, symbol), trades)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+async fn wait_out_second(start_time: tokio::time::Instant) {
+    let one_thousand: u64 = 1000;
+    if start_time.elapsed().as_millis() < 1000 {
+        let time_to_wait = one_thousand - start_time.elapsed().as_millis() as u64;
+        tokio::time::sleep(tokio::time::Duration::from_millis(time_to_wait)).await
+    }
+}
+
+/// Builds a chain for every symbol on every weekday from `start` to `end`, loading saved days
+/// and fetching the ones that are missing. Scoring starts from an empty history under `config`,
+/// so each day is scored against the backfilled days before it and never against live history
+/// recorded after it.
+pub async fn backfill(
+    symbol_list: &[String],
+    start: NaiveDate,
+    end: NaiveDate,
+    short_fees: &ShortStockInfo,
+    config: &ScoringConfig,
+) -> Vec<OptionChain> {
+    let mut bias_model = BiasModel {
+        config: config.clone(),
+        history: FactorHistory::default(),
+    };
+    let mut chains: Vec<OptionChain> = Vec::new();
+    for date in trading_days(start, end) {
+        let directory = history_directory(date);
+        let mut snapshots: Vec<(String, ChainSnapshot)> = Vec::new();
+        for symbol in symbol_list {
+            let mut snapshot = ChainSnapshot::load(&directory, symbol, date);
+            if let Ok(None) = snapshot {
+                match get_auth().await {
+                    Ok(token) => match fetch_history(symbol, &token, date).await {
+                        Ok(()) => snapshot = ChainSnapshot::load(&directory, symbol, date),
+                        Err(e) => println!("could not backfill {} {}: {}", symbol, date, e),
+                    },
+                    Err(e) => println!("could not authenticate: {:#?}", e),
+                }
+            }
+            match snapshot {
+                Ok(Some(snapshot)) => snapshots.push((symbol.clone(), snapshot)),
+                Ok(None) => {}
+                Err(e) => println!("could not backfill {} {}: {}", symbol, date, e),
+            }
+        }
+        chains.append(&mut score_day(snapshots, short_fees, &mut bias_model));
+        println!("{} - backfilled", date);
+    }
+    chains
+}
+
+/// Builds one day's chains against the history so far, then adds that day to the history.
+fn score_day(
+    snapshots: Vec<(String, ChainSnapshot)>,
+    short_fees: &ShortStockInfo,
+    bias_model: &mut BiasModel,
+) -> Vec<OptionChain> {
+    let chains: Vec<OptionChain> = snapshots
+        .into_iter()
+        .filter_map(|(symbol, snapshot)| {
+            let short_data = short_fees.data.iter().find(|item| item.symbol == symbol);
+            OptionChain::from_snapshot(&symbol, snapshot, short_data, bias_model)
+        })
+        .collect();
+    for chain in &chains {
+        bias_model.record(chain);
+    }
+    chains
+}
+
+pub fn history_directory(date: NaiveDate) -> String {
+    format!("{}/{}", BACKFILL_DIRECTORY, date.format("%F"))
+}
+
+pub fn trading_days(start: NaiveDate, end: NaiveDate) -> Vec<NaiveDate> {
+    let mut days: Vec<NaiveDate> = Vec::new();
+    let mut date = start;
+    while date <= end {
+        if !matches!(date.weekday(), Weekday::Sat | Weekday::Sun) {
+            days.push(date)
+        }
+        date += Duration::days(1);
+    }
+    days
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixture_snapshot;
+    use crate::scoring::{BiasFactor, FactorConfig, Normalization};
+
+    #[test]
+    fn builds_chain_as_of_saved_date() {
+        let directory = std::env::temp_dir().join("options-scan-backfill-test");
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(
+            directory.join("SPY-snapshot.json"),
+            include_str!("../tests/fixtures/chain_snapshot.json"),
+        )
+        .unwrap();
+        std::fs::write(
+            directory.join("SPY-trades.json"),
+            include_str!("../tests/fixtures/vertical.json"),
+        )
+        .unwrap();
+        let as_of = NaiveDate::from_ymd(2022, 1, 14);
+        let snapshot = ChainSnapshot::load(directory.to_str().unwrap(), "SPY", as_of)
+            .unwrap()
+            .unwrap();
+        let chain =
+            OptionChain::from_snapshot("SPY", snapshot, None, &BiasModel::default()).unwrap();
+        assert_eq!(chain.symbol_date, "SPY-1-14-2022");
+        assert_eq!(chain.date, "01/14/22");
+        assert_eq!(chain.spreads.len(), 2);
+        assert!(chain.spreads.iter().all(|spread| spread.dte == 2926));
+        assert!(chain.options.iter().all(|option| option.dte == 2926));
+    }
+
+    #[test]
+    fn refuses_unreadable_trades() {
+        let directory = std::env::temp_dir().join("options-scan-backfill-bad-trades");
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(
+            directory.join("SPY-snapshot.json"),
+            include_str!("../tests/fixtures/chain_snapshot.json"),
+        )
+        .unwrap();
+        std::fs::write(directory.join("SPY-trades.json"), "[{\"root\": 1}]").unwrap();
+        let as_of = NaiveDate::from_ymd(2022, 1, 14);
+        let error = ChainSnapshot::load(directory.to_str().unwrap(), "SPY", as_of).unwrap_err();
+        assert!(error.contains("SPY-trades.json"));
+        std::fs::remove_file(directory.join("SPY-trades.json")).unwrap();
+        let snapshot = ChainSnapshot::load(directory.to_str().unwrap(), "SPY", as_of).unwrap();
+        assert!(snapshot.unwrap().trades.is_empty());
+        assert!(
+            ChainSnapshot::load(directory.to_str().unwrap(), "QQQ", as_of)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn scores_each_day_without_later_days() {
+        let short_fees = ShortStockInfo {
+            date: String::new(),
+            time: String::new(),
+            data: Vec::new(),
+        };
+        let config = ScoringConfig {
+            factors: vec![FactorConfig {
+                factor: BiasFactor::InsiderNet,
+                weight: 1.0,
+                normalization: Normalization::ZScore,
+                center: 0.0,
+                threshold: 0.5,
+                neutral_vote: 0.0,
+            }],
+            min_history: 2,
+            ..ScoringConfig::default()
+        };
+        let days: Vec<(NaiveDate, f64)> = vec![
+            (NaiveDate::from_ymd(2022, 1, 10), 1.0),
+            (NaiveDate::from_ymd(2022, 1, 11), 3.0),
+            (NaiveDate::from_ymd(2022, 1, 12), 2.0),
+            (NaiveDate::from_ymd(2022, 1, 13), 50.0),
+        ];
+        let run = |days: &[(NaiveDate, f64)]| -> Vec<OptionChain> {
+            let mut bias_model = BiasModel {
+                config: config.clone(),
+                history: FactorHistory::default(),
+            };
+            days.iter()
+                .flat_map(|(as_of, insiders)| {
+                    let snapshot = ChainSnapshot {
+                        insiders: *insiders,
+                        ..fixture_snapshot(*as_of)
+                    };
+                    score_day(
+                        vec![("SPY".to_string(), snapshot)],
+                        &short_fees,
+                        &mut bias_model,
+                    )
+                })
+                .collect()
+        };
+        let all_days = run(&days);
+        let through_third_day = run(&days[..3]);
+        assert_eq!(all_days.len(), 4);
+        for (chain, earlier) in all_days.iter().zip(&through_third_day) {
+            assert_eq!(chain.bias_contributions, earlier.bias_contributions);
+            assert_eq!(chain.bias_score, earlier.bias_score);
+        }
+        assert!(all_days[1].bias_contributions[0].warming_up);
+        // 2.0 against the mean of 1.0 and 3.0 alone, not pulled down by the 50.0 after it.
+        assert_eq!(all_days[2].bias_contributions[0].normalized, 0.0);
+        assert!(all_days[3].bias_contributions[0].contribution > 0.0);
+    }
+
+    #[test]
+    fn skips_weekends() {
+        let days = trading_days(
+            NaiveDate::from_ymd(2022, 1, 14),
+            NaiveDate::from_ymd(2022, 1, 18),
+        );
+        assert_eq!(
+            days,
+            vec![
+                NaiveDate::from_ymd(2022, 1, 14),
+                NaiveDate::from_ymd(2022, 1, 17),
+                NaiveDate::from_ymd(2022, 1, 18),
+            ]
+        );
+    }
+}
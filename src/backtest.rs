@@ -0,0 +1,534 @@
+use crate::groups::GroupConfig;
+use crate::models::{get_signals, OptionChain, Signal, SignalType};
+use crate::others::{load_json, round_to_decimals};
+use crate::single_options::{OptionData, OptionType};
+use crate::universe::{normalize_universe, VolumeHistory};
+use crate::{
+    AMOUNT_IN_ACCOUNT, BACKTEST_HOLDING_DAYS, BACKTEST_INSTRUMENT, BACKTEST_OPTION_MIN_DTE,
+    BACKTEST_SLIPPAGE, BACKTEST_STOP_LOSS_PERCENT, BACKTEST_TAKE_PROFIT_PERCENT, OPTION_COMMISSION,
+    STOCK_COMMISSION, UNIVERSE_SIZE_BASIS,
+};
+use chrono::NaiveDate;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Slippage {
+    None,
+    /// Basis points of the price, paid on the way in and on the way out.
+    Bps(f64),
+    /// A fixed amount per share or per option, paid on the way in and on the way out.
+    PerUnit(f64),
+}
+impl Slippage {
+    fn cost(&self, price: f64) -> f64 {
+        match self {
+            Slippage::None => 0.0,
+            Slippage::Bps(bps) => price * bps / 10000.0,
+            Slippage::PerUnit(amount) => *amount,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Instrument {
+    Underlying,
+    /// The call (buy signals) or put (sell signals) closest to the money with at least
+    /// `BACKTEST_OPTION_MIN_DTE` days left.
+    AtTheMoneyOption,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct BacktestConfig {
+    pub holding_days: usize,
+    pub stop_loss_percent: Option<f64>,
+    pub take_profit_percent: Option<f64>,
+    pub slippage: Slippage,
+    pub instrument: Instrument,
+    pub quantity_1: f64,
+    pub quantity_2: f64,
+}
+impl Default for BacktestConfig {
+    fn default() -> Self {
+        Self {
+            holding_days: BACKTEST_HOLDING_DAYS,
+            stop_loss_percent: BACKTEST_STOP_LOSS_PERCENT,
+            take_profit_percent: BACKTEST_TAKE_PROFIT_PERCENT,
+            slippage: BACKTEST_SLIPPAGE,
+            instrument: BACKTEST_INSTRUMENT,
+            quantity_1: 2500.0,
+            quantity_2: 25600.0,
+        }
+    }
+}
+impl BacktestConfig {
+    /// Fields missing from the file keep their defaults from the constants in main. A file that
+    /// doesn't parse is an error, so a backtest never reports on settings nobody chose.
+    pub fn load(filename: &str) -> Result<Self, String> {
+        Ok(load_json(filename)?.unwrap_or_default())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BacktestTrade {
+    pub symbol: String,
+    pub instrument: String,
+    pub side: SignalType,
+    pub quantity: i64,
+    pub entry_date: String,
+    pub entry_price: f64,
+    pub exit_date: String,
+    pub exit_price: f64,
+    pub exit_reason: String,
+    pub costs: f64,
+    pub pnl: f64,
+    pub return_percent: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SymbolAttribution {
+    pub symbol: String,
+    pub trades: usize,
+    pub pnl: f64,
+    pub hit_rate: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BacktestSummary {
+    pub start_date: String,
+    pub end_date: String,
+    pub trades: usize,
+    pub total_pnl: f64,
+    pub total_return_percent: f64,
+    pub hit_rate: f64,
+    pub sharpe: f64,
+    pub max_drawdown: f64,
+    pub max_drawdown_percent: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BacktestReport {
+    pub summary: BacktestSummary,
+    pub trades: Vec<BacktestTrade>,
+    pub attribution: Vec<SymbolAttribution>,
+    pub daily_pnl: Vec<(NaiveDate, f64)>,
+}
+
+#[derive(Debug, Clone)]
+struct OpenPosition {
+    symbol: String,
+    instrument: String,
+    option: Option<(OptionType, f64, NaiveDate)>,
+    side: SignalType,
+    quantity: i64,
+    multiplier: f64,
+    entry_date: NaiveDate,
+    entry_price: f64,
+    last_mark: f64,
+    costs: f64,
+    days_held: usize,
+}
+impl OpenPosition {
+    fn sign(&self) -> f64 {
+        match (&self.side, self.option) {
+            (_, Some(_)) => 1.0,
+            (SignalType::Buy, None) => 1.0,
+            (SignalType::Sell, None) => -1.0,
+        }
+    }
+    fn units(&self) -> f64 {
+        self.quantity as f64 * self.multiplier
+    }
+    fn commission(&self) -> f64 {
+        match self.option {
+            Some(_) => OPTION_COMMISSION * self.quantity as f64,
+            None => STOCK_COMMISSION,
+        }
+    }
+    /// Quote mid for the held option, or what it is worth at expiration once it is no longer
+    /// quoted.
+    fn mark(&self, chain: &OptionChain, date: NaiveDate) -> Option<f64> {
+        match self.option {
+            None => Some(chain.underlying_mid).filter(|price| *price > 0.0),
+            Some((kind, strike, expiry)) => {
+                let quoted = chain
+                    .options
+                    .iter()
+                    .find(|option| option.symbol == self.instrument)
+                    .and_then(|option| option.mid_price);
+                match quoted {
+                    Some(mid) if date < expiry => Some(mid),
+                    _ => Some(match kind {
+                        OptionType::Call => (chain.underlying_mid - strike).max(0.0),
+                        OptionType::Put => (strike - chain.underlying_mid).max(0.0),
+                    }),
+                }
+            }
+        }
+    }
+}
+
+/// Replays chains day by day: each day's `get_signals` output is entered at that day's price
+/// and held for `holding_days` trading days unless a stop, a target or an opposite signal
+/// closes it first. Open positions are marked every day for the return statistics.
+pub fn run_backtest(
+    chains: &[OptionChain],
+    config: &BacktestConfig,
+    groups: &GroupConfig,
+) -> BacktestReport {
+    let mut days: BTreeMap<NaiveDate, Vec<OptionChain>> = BTreeMap::new();
+    for chain in chains {
+        if let Ok(date) = NaiveDate::parse_from_str(&chain.date, "%D") {
+            days.entry(date).or_default().push(chain.clone());
+        }
+    }
+    // each day averages option volume over itself and the days before it
+    let mut volumes = VolumeHistory::default();
+    for day_chains in days.values_mut() {
+        for chain in day_chains.iter() {
+            volumes.record(chain);
+        }
+        normalize_universe(day_chains, UNIVERSE_SIZE_BASIS, &volumes);
+    }
+    let mut open: Vec<OpenPosition> = Vec::new();
+    let mut trades: Vec<BacktestTrade> = Vec::new();
+    let mut daily_pnl: Vec<(NaiveDate, f64)> = Vec::new();
+    let last_day = days.keys().next_back().cloned();
+    for (date, day_chains) in &days {
+        let mut pnl_today = 0.0;
+        let chain_for = |symbol: &str| day_chains.iter().find(|chain| chain.symbol == symbol);
+        let signals: Vec<Signal> =
+            get_signals(day_chains, config.quantity_1, config.quantity_2, groups);
+        let mut still_open: Vec<OpenPosition> = Vec::new();
+        for mut position in open.drain(..) {
+            let mark = chain_for(&position.symbol).and_then(|chain| position.mark(chain, *date));
+            let mark = match mark {
+                Some(mark) => mark,
+                None => {
+                    still_open.push(position);
+                    continue;
+                }
+            };
+            pnl_today += position.sign() * position.units() * (mark - position.last_mark);
+            position.last_mark = mark;
+            position.days_held += 1;
+            let move_percent =
+                100.0 * position.sign() * (mark - position.entry_price) / position.entry_price;
+            let reversed = signals
+                .iter()
+                .any(|signal| signal.symbol == position.symbol && signal.side != position.side);
+            let exit_reason = if config
+                .stop_loss_percent
+                .map(|stop| move_percent <= -stop)
+                .unwrap_or(false)
+            {
+                Some("Stop Loss")
+            } else if config
+                .take_profit_percent
+                .map(|target| move_percent >= target)
+                .unwrap_or(false)
+            {
+                Some("Take Profit")
+            } else if reversed {
+                Some("Opposite Signal")
+            } else if position.days_held >= config.holding_days {
+                Some("Holding Period")
+            } else if position.option.map(|(_, _, expiry)| *date >= expiry) == Some(true) {
+                Some("Expired")
+            } else if Some(*date) == last_day {
+                Some("End of Test")
+            } else {
+                None
+            };
+            match exit_reason {
+                Some(reason) => {
+                    let exit_costs =
+                        config.slippage.cost(mark) * position.units() + position.commission();
+                    pnl_today -= exit_costs;
+                    trades.push(close(position, *date, mark, exit_costs, reason));
+                }
+                None => still_open.push(position),
+            }
+        }
+        open = still_open;
+        if Some(*date) != last_day {
+            for signal in &signals {
+                if open.iter().any(|position| position.symbol == signal.symbol) {
+                    continue;
+                }
+                if let Some(position) =
+                    chain_for(&signal.symbol).and_then(|chain| enter(chain, signal, *date, config))
+                {
+                    pnl_today -= position.costs;
+                    open.push(position);
+                }
+            }
+        }
+        daily_pnl.push((*date, pnl_today));
+    }
+    let attribution = trades
+        .iter()
+        .into_group_map_by(|trade| trade.symbol.clone())
+        .into_iter()
+        .map(|(symbol, symbol_trades)| SymbolAttribution {
+            symbol,
+            trades: symbol_trades.len(),
+            pnl: round_to_decimals(symbol_trades.iter().map(|trade| trade.pnl).sum(), 2),
+            hit_rate: hit_rate(&symbol_trades),
+        })
+        .sorted_by(|a, b| b.pnl.partial_cmp(&a.pnl).unwrap())
+        .collect_vec();
+    BacktestReport {
+        summary: summarize(&trades, &daily_pnl),
+        trades,
+        attribution,
+        daily_pnl,
+    }
+}
+
+fn enter(
+    chain: &OptionChain,
+    signal: &Signal,
+    date: NaiveDate,
+    config: &BacktestConfig,
+) -> Option<OpenPosition> {
+    let (instrument, option, price, quantity, multiplier) = match config.instrument {
+        Instrument::Underlying => (
+            chain.symbol.clone(),
+            None,
+            chain.underlying_mid,
+            signal.quantity_1,
+            1.0,
+        ),
+        Instrument::AtTheMoneyOption => {
+            let kind = match signal.side {
+                SignalType::Buy => OptionType::Call,
+                SignalType::Sell => OptionType::Put,
+            };
+            let option = at_the_money(&chain.options, kind, chain.underlying_mid)?;
+            let expiry = NaiveDate::parse_from_str(&option.expiration_date, "%F").ok()?;
+            (
+                option.symbol.clone(),
+                Some((kind, option.strike, expiry)),
+                option.mid_price?,
+                (signal.quantity_1 / 100).max(1),
+                100.0,
+            )
+        }
+    };
+    if price <= 0.0 || quantity <= 0 {
+        return None;
+    }
+    let mut position = OpenPosition {
+        symbol: chain.symbol.clone(),
+        instrument,
+        option,
+        side: signal.side.clone(),
+        quantity,
+        multiplier,
+        entry_date: date,
+        entry_price: price,
+        last_mark: price,
+        costs: 0.0,
+        days_held: 0,
+    };
+    position.costs = config.slippage.cost(price) * position.units() + position.commission();
+    Some(position)
+}
+
+fn at_the_money(options: &[OptionData], kind: OptionType, underlying: f64) -> Option<&OptionData> {
+    options
+        .iter()
+        .filter(|option| {
+            option.kind == kind
+                && option.dte >= BACKTEST_OPTION_MIN_DTE
+                && option.mid_price.unwrap_or(0.0) > 0.0
+        })
+        .min_by(|a, b| {
+            (a.dte, (a.strike - underlying).abs())
+                .partial_cmp(&(b.dte, (b.strike - underlying).abs()))
+                .unwrap()
+        })
+}
+
+fn close(
+    position: OpenPosition,
+    date: NaiveDate,
+    exit_price: f64,
+    exit_costs: f64,
+    reason: &str,
+) -> BacktestTrade {
+    let costs = position.costs + exit_costs;
+    let gross = position.sign() * position.units() * (exit_price - position.entry_price);
+    let pnl = gross - costs;
+    let return_percent = 100.0 * pnl / (position.entry_price * position.units());
+    BacktestTrade {
+        symbol: position.symbol,
+        instrument: position.instrument,
+        side: position.side,
+        quantity: position.quantity,
+        entry_date: position.entry_date.format("%F").to_string(),
+        entry_price: position.entry_price,
+        exit_date: date.format("%F").to_string(),
+        exit_price,
+        exit_reason: reason.to_string(),
+        costs: round_to_decimals(costs, 2),
+        pnl: round_to_decimals(pnl, 2),
+        return_percent: round_to_decimals(return_percent, 2),
+    }
+}
+
+fn hit_rate(trades: &[&BacktestTrade]) -> f64 {
+    if trades.is_empty() {
+        return 0.0;
+    }
+    let winners = trades.iter().filter(|trade| trade.pnl > 0.0).count();
+    round_to_decimals(100.0 * winners as f64 / trades.len() as f64, 2)
+}
+
+/// Sharpe uses daily P&L against `AMOUNT_IN_ACCOUNT`, annualized over 252 trading days.
+fn summarize(trades: &[BacktestTrade], daily_pnl: &[(NaiveDate, f64)]) -> BacktestSummary {
+    let returns = daily_pnl
+        .iter()
+        .map(|(_, pnl)| pnl / AMOUNT_IN_ACCOUNT)
+        .collect_vec();
+    let mean = returns.iter().sum::<f64>() / returns.len().max(1) as f64;
+    let variance = returns
+        .iter()
+        .map(|value| (value - mean).powi(2))
+        .sum::<f64>()
+        / (returns.len().max(2) - 1) as f64;
+    let sharpe = if variance > 0.0 {
+        mean / variance.sqrt() * 252f64.sqrt()
+    } else {
+        0.0
+    };
+    let mut equity = AMOUNT_IN_ACCOUNT;
+    let mut peak = AMOUNT_IN_ACCOUNT;
+    let mut max_drawdown: f64 = 0.0;
+    let mut max_drawdown_percent: f64 = 0.0;
+    for (_, pnl) in daily_pnl {
+        equity += pnl;
+        peak = peak.max(equity);
+        max_drawdown = max_drawdown.max(peak - equity);
+        max_drawdown_percent = max_drawdown_percent.max(100.0 * (peak - equity) / peak);
+    }
+    let total_pnl: f64 = daily_pnl.iter().map(|(_, pnl)| pnl).sum();
+    BacktestSummary {
+        start_date: daily_pnl
+            .first()
+            .map(|(date, _)| date.format("%F").to_string())
+            .unwrap_or_default(),
+        end_date: daily_pnl
+            .last()
+            .map(|(date, _)| date.format("%F").to_string())
+            .unwrap_or_default(),
+        trades: trades.len(),
+        total_pnl: round_to_decimals(total_pnl, 2),
+        total_return_percent: round_to_decimals(100.0 * total_pnl / AMOUNT_IN_ACCOUNT, 2),
+        hit_rate: hit_rate(&trades.iter().collect_vec()),
+        sharpe: round_to_decimals(sharpe, 2),
+        max_drawdown: round_to_decimals(max_drawdown, 2),
+        max_drawdown_percent: round_to_decimals(max_drawdown_percent, 2),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixture_chain_as_of;
+
+    fn chain(symbol: &str, date: &str, price: f64, net_value: f64) -> OptionChain {
+        OptionChain {
+            symbol: symbol.to_string(),
+            underlying_mid: price,
+            large_trader_net_value: net_value,
+            ..fixture_chain_as_of(symbol, NaiveDate::parse_from_str(date, "%F").unwrap())
+        }
+    }
+
+    #[test]
+    fn holds_signal_for_holding_period() {
+        let chains = vec![
+            chain("SPY", "2022-01-10", 100.0, 5000000.0),
+            chain("QQQ", "2022-01-10", 50.0, 0.0),
+            chain("SPY", "2022-01-11", 101.0, 5000000.0),
+            chain("QQQ", "2022-01-11", 50.0, 0.0),
+            chain("SPY", "2022-01-12", 103.0, 5000000.0),
+            chain("QQQ", "2022-01-12", 49.0, 0.0),
+        ];
+        let config = BacktestConfig {
+            holding_days: 2,
+            stop_loss_percent: None,
+            take_profit_percent: None,
+            slippage: Slippage::None,
+            instrument: Instrument::Underlying,
+            quantity_1: 2500.0,
+            quantity_2: 25600.0,
+        };
+        let report = run_backtest(&chains, &config, &GroupConfig::default());
+        let spy = report
+            .trades
+            .iter()
+            .find(|trade| trade.symbol == "SPY")
+            .unwrap();
+        assert_eq!(spy.quantity, 25);
+        assert_eq!(spy.exit_date, "2022-01-12");
+        assert_eq!(spy.exit_reason, "Holding Period");
+        assert_eq!(spy.pnl, 25.0 * 3.0 - 2.0 * STOCK_COMMISSION);
+        let qqq = report
+            .trades
+            .iter()
+            .find(|trade| trade.symbol == "QQQ")
+            .unwrap();
+        assert_eq!(qqq.pnl, 50.0 * 1.0 - 2.0 * STOCK_COMMISSION);
+        let summary = report.summary;
+        assert_eq!(summary.trades, 2);
+        assert_eq!(summary.hit_rate, 100.0);
+        assert_eq!(summary.total_pnl, 75.0 + 50.0 - 4.0 * STOCK_COMMISSION);
+    }
+
+    #[test]
+    fn stops_out_losing_position() {
+        let chains = vec![
+            chain("SPY", "2022-01-10", 100.0, 5000000.0),
+            chain("QQQ", "2022-01-10", 50.0, 5000000.0),
+            chain("SPY", "2022-01-11", 94.0, 5000000.0),
+            chain("QQQ", "2022-01-11", 50.0, 5000000.0),
+            chain("SPY", "2022-01-12", 110.0, 5000000.0),
+            chain("QQQ", "2022-01-12", 50.0, 5000000.0),
+        ];
+        let config = BacktestConfig {
+            holding_days: 5,
+            stop_loss_percent: Some(5.0),
+            slippage: Slippage::Bps(10.0),
+            instrument: Instrument::Underlying,
+            ..BacktestConfig::default()
+        };
+        let report = run_backtest(&chains, &config, &GroupConfig::default());
+        let spy = report
+            .trades
+            .iter()
+            .find(|trade| trade.symbol == "SPY")
+            .unwrap();
+        assert_eq!(spy.exit_reason, "Stop Loss");
+        assert_eq!(spy.exit_price, 94.0);
+        assert!(spy.pnl < -150.0);
+        assert!(report.summary.max_drawdown > 150.0);
+    }
+
+    #[test]
+    fn refuses_malformed_config() {
+        let filename = std::env::temp_dir().join("malformed_backtest_config.json");
+        std::fs::write(&filename, r#"{"holding_days": "a week"}"#).unwrap();
+        let result = BacktestConfig::load(filename.to_str().unwrap());
+        std::fs::remove_file(&filename).unwrap();
+        assert!(result.is_err());
+        assert_eq!(
+            BacktestConfig::load("no_such_backtest_config.json"),
+            Ok(BacktestConfig::default())
+        );
+    }
+}
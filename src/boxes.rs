@@ -1,9 +1,11 @@
-use std::sync::{Arc, RwLock};
-
 use crate::{
+    margin::MarginSummary,
     models::{DividendInformation, OptionChain},
     others::{get_margin_loan_cost, get_short_fee_cost, round_to_decimals},
     single_options::{OptionData, OptionType},
+    spreads::SpreadLeg,
+    trades::OrderAction,
+    vol_surface::VolSurface,
     MAX_BOX_DTE, MAX_SHORT_BOX_SHORT_FEE, OPTION_COMMISSION,
 };
 use serde::Serialize;
@@ -41,16 +43,24 @@ pub struct OptionsBox {
     pub div_info_estimated: bool,
     pub short_fee: f64,
     pub rank: f64,
+    pub reg_t_margin: f64,
+    pub margin_rules: String,
+    pub portfolio_margin: f64,
+    pub return_on_margin: f64,
+    pub return_on_portfolio_margin: f64,
+    #[serde(skip_serializing)]
+    pub legs: Vec<SpreadLeg>,
 }
 
 impl OptionsBox {
+    /// None when any leg is missing a price or a size.
     pub fn from_options(
         options: [&OptionData; 4],
         timestamp: &str,
         dividend_info: &Option<DividendInformation>,
         short_fee: f64,
         underlying_last: f64,
-    ) -> Self {
+    ) -> Option<Self> {
         let low_strike = options
             .iter()
             .min_by_key(|option| (option.strike * 100.0) as i64)
@@ -70,11 +80,11 @@ impl OptionsBox {
         let mut itm_debit = 0.0;
         for option in &options {
             if option.otm {
-                otm_credit += option.bid_price.unwrap_or(0.0);
-                otm_debit += option.ask_price.unwrap_or(0.0);
+                otm_credit += option.bid_price?;
+                otm_debit += option.ask_price?;
             } else {
-                itm_credit += option.bid_price.unwrap_or(0.0);
-                itm_debit += option.ask_price.unwrap_or(0.0);
+                itm_credit += option.bid_price?;
+                itm_debit += option.ask_price?;
             }
         }
         let long_box_cost = round_to_decimals(otm_credit - itm_debit, 2);
@@ -86,6 +96,17 @@ impl OptionsBox {
         } else {
             BoxType::ShortBox
         };
+        // a long box buys the in the money options and sells the out of the money ones
+        let legs = options
+            .iter()
+            .map(|option| {
+                let side = match (box_type, option.otm) {
+                    (BoxType::LongBox, false) | (BoxType::ShortBox, true) => OrderAction::Bought,
+                    _ => OrderAction::Sold,
+                };
+                SpreadLeg::from_option(option, side)
+            })
+            .collect();
         match box_type {
             BoxType::LongBox => {
                 let options_commissions = OPTION_COMMISSION * 4.0;
@@ -93,12 +114,12 @@ impl OptionsBox {
                 let max_fees = options_commissions + max_margin_loan_cost;
                 let mut size_vec: Vec<i64> = itm
                     .iter()
-                    .map(|option| option.option_ask_size.unwrap())
-                    .collect();
+                    .map(|option| option.option_ask_size)
+                    .collect::<Option<_>>()?;
                 let mut bid_size_vec: Vec<i64> = otm
                     .iter()
-                    .map(|option| option.option_bid_size.unwrap())
-                    .collect();
+                    .map(|option| option.option_bid_size)
+                    .collect::<Option<_>>()?;
                 size_vec.append(&mut bid_size_vec);
                 let net_profit = long_max_profit - max_fees;
                 let net_return = (net_profit) / (long_box_cost.abs() + max_fees);
@@ -107,7 +128,7 @@ impl OptionsBox {
                         + otm.iter().map(|option| option.iv).sum::<f64>());
                 let difficulty = options[0].dte as f64 * net_iv;
                 size_vec.sort_unstable();
-                Self {
+                Some(Self {
                     high_strike,
                     low_strike,
                     expiration_value,
@@ -138,7 +159,13 @@ impl OptionsBox {
                     short_fee_estimate: 0.0,
                     rank: net_return / difficulty,
                     net_iv,
-                }
+                    reg_t_margin: 0.0,
+                    margin_rules: String::new(),
+                    portfolio_margin: 0.0,
+                    return_on_margin: 0.0,
+                    return_on_portfolio_margin: 0.0,
+                    legs,
+                })
             }
             BoxType::ShortBox => {
                 let max_margin_loan_cost =
@@ -155,12 +182,12 @@ impl OptionsBox {
                 };
                 let mut size_vec: Vec<i64> = itm
                     .iter()
-                    .map(|option| option.option_bid_size.unwrap())
-                    .collect();
+                    .map(|option| option.option_bid_size)
+                    .collect::<Option<_>>()?;
                 let mut ask_size_vec: Vec<i64> = otm
                     .iter()
-                    .map(|option| option.option_ask_size.unwrap())
-                    .collect();
+                    .map(|option| option.option_ask_size)
+                    .collect::<Option<_>>()?;
                 size_vec.append(&mut ask_size_vec);
                 let options_commissions = OPTION_COMMISSION * 4.0;
                 let max_fees =
@@ -173,7 +200,7 @@ impl OptionsBox {
                 let net_iv: f64 = 100.0
                     * (itm.iter().map(|option| option.iv).sum::<f64>()
                         - otm.iter().map(|option| option.iv).sum::<f64>());
-                Self {
+                Some(Self {
                     high_strike,
                     low_strike,
                     expiration_value,
@@ -204,15 +231,42 @@ impl OptionsBox {
                     short_fee_estimate: short_fee_cost,
                     rank: (net_return * net_iv) / difficulty,
                     net_iv,
-                }
+                    reg_t_margin: 0.0,
+                    margin_rules: String::new(),
+                    portfolio_margin: 0.0,
+                    return_on_margin: 0.0,
+                    return_on_portfolio_margin: 0.0,
+                    legs,
+                })
             }
         }
     }
+    pub fn with_margin(self, symbol: &str, surface: &VolSurface, underlying: f64) -> Self {
+        let margin = MarginSummary::of(
+            symbol,
+            &self.legs,
+            None,
+            surface,
+            underlying,
+            self.net_profit,
+        );
+        Self {
+            reg_t_margin: margin.reg_t_margin,
+            margin_rules: margin.margin_rules,
+            portfolio_margin: margin.portfolio_margin,
+            return_on_margin: margin.return_on_margin,
+            return_on_portfolio_margin: margin.return_on_portfolio_margin,
+            ..self
+        }
+    }
 }
-pub async fn get_boxes_mt(data: Arc<RwLock<OptionChain>>, output: Arc<RwLock<Vec<OptionsBox>>>) {
+
+/// Every profitable box with every leg's price and size quoted, in expirations before
+/// `MAX_BOX_DTE`. Short boxes are skipped when the stock is expensive to borrow or its dividend is
+/// poisoned.
+pub fn get_boxes(chain: &OptionChain, surface: &VolSurface) -> Vec<OptionsBox> {
     let mut output_vec: Vec<OptionsBox> = Vec::new();
-    let chain = data.read().unwrap();
-    for expiration in &chain.expirations {
+    for expiration in &chain.expirations() {
         if expiration < &MAX_BOX_DTE {
             let this_expiration: Vec<&OptionData> = chain
                 .options
@@ -248,13 +302,17 @@ pub async fn get_boxes_mt(data: Arc<RwLock<OptionChain>>, output: Arc<RwLock<Vec
                             } else {
                                 chain.short_fee
                             };
-                            let boxx = OptionsBox::from_options(
+                            let Some(boxx) = OptionsBox::from_options(
                                 [otm_call, itm_call, otm_put, itm_put],
                                 &chain.data_timestamp,
                                 &chain.dividend_info,
                                 short_fee,
                                 chain.underlying_mid,
-                            );
+                            ) else {
+                                continue;
+                            };
+                            let boxx =
+                                boxx.with_margin(&chain.symbol, surface, chain.underlying_mid);
                             if boxx.net_profit > 0.0 {
                                 if !chain.symbol.contains('^') {
                                     match &chain.dividend_info {
@@ -286,6 +344,69 @@ pub async fn get_boxes_mt(data: Arc<RwLock<OptionChain>>, output: Arc<RwLock<Vec
             }
         }
     }
-    let mut write = output.write().unwrap();
-    write.append(&mut output_vec)
+    output_vec
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixture_chain;
+
+    #[test]
+    fn skips_boxes_without_displayed_size() {
+        let chain = fixture_chain("SPY");
+        let option = |kind: OptionType, strike: f64| {
+            chain
+                .options
+                .iter()
+                .find(|option| option.kind == kind && option.strike == strike)
+                .unwrap()
+                .clone()
+        };
+        let otm_call = option(OptionType::Call, 470.0);
+        let otm_put = option(OptionType::Put, 455.0);
+        let itm_call = OptionData {
+            strike: 455.0,
+            otm: false,
+            bid_price: Some(19.8),
+            ask_price: Some(19.9),
+            ..otm_call.clone()
+        };
+        let itm_put = OptionData {
+            strike: 470.0,
+            otm: false,
+            bid_price: Some(14.3),
+            ask_price: Some(14.4),
+            ..otm_put.clone()
+        };
+        let quoted = OptionsBox::from_options(
+            [&otm_call, &itm_call, &otm_put, &itm_put],
+            &chain.data_timestamp,
+            &None,
+            0.0,
+            chain.underlying_mid,
+        )
+        .unwrap();
+        assert_eq!(quoted.max_size, 95);
+        assert_eq!(quoted.legs.len(), 4);
+        // a long box buys the in the money put at the ask, a short box sells it at the bid
+        let unquoted = match quoted.box_type {
+            BoxType::LongBox => OptionData {
+                option_ask_size: None,
+                ..itm_put.clone()
+            },
+            BoxType::ShortBox => OptionData {
+                option_bid_size: None,
+                ..itm_put.clone()
+            },
+        };
+        assert!(OptionsBox::from_options(
+            [&otm_call, &itm_call, &otm_put, &unquoted],
+            &chain.data_timestamp,
+            &None,
+            0.0,
+            chain.underlying_mid,
+        )
+        .is_none());
+    }
 }
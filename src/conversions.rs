@@ -1,11 +1,14 @@
 use crate::{
+    margin::MarginSummary,
     models::{DividendInformation, OptionChain},
     others::{get_margin_loan_cost, get_short_fee_cost, round_to_decimals},
     single_options::{OptionData, OptionType},
+    spreads::{SpreadLeg, StockLeg},
+    trades::OrderAction,
+    vol_surface::VolSurface,
     OPTION_COMMISSION, STOCK_COMMISSION,
 };
 use serde::Serialize;
-use std::sync::{Arc, RwLock};
 
 #[derive(Debug, Serialize, Clone)]
 pub struct Conversion {
@@ -34,15 +37,19 @@ pub struct Conversion {
     pub difficulty: f64,
     pub ranking: f64,
     pub max_size: i64,
-    #[serde(skip_serializing)]
-    pub symbols: [String; 3],
     pub div_ex_date: Option<String>,
     pub days_to_ex_date: Option<i64>,
     pub div_info_estimated: bool,
     pub short_fee: f64,
     pub annualized_ror: f64,
+    pub reg_t_margin: f64,
+    pub margin_rules: String,
+    pub portfolio_margin: f64,
+    pub return_on_margin: f64,
+    pub return_on_portfolio_margin: f64,
 }
 impl Conversion {
+    /// None when the sold option has no bid or the bought one no ask.
     pub fn from_pair(
         sell_option: &OptionData,
         buy_option: &&OptionData,
@@ -50,7 +57,9 @@ impl Conversion {
         timestamp: &str,
         dividend_info: &Option<DividendInformation>,
         short_fee: f64,
-    ) -> Self {
+    ) -> Option<Self> {
+        let sell_bid_price = sell_option.bid_price?;
+        let buy_ask_price = buy_option.ask_price?;
         let dividend_impact: f64 = if let Some(divi) = dividend_info {
             if divi.days_to_ex_date() < sell_option.dte {
                 match sell_option.kind {
@@ -63,10 +72,7 @@ impl Conversion {
         } else {
             0.0
         };
-        let option_credit = round_to_decimals(
-            sell_option.bid_price.unwrap() - buy_option.ask_price.unwrap(),
-            2,
-        );
+        let option_credit = round_to_decimals(sell_bid_price - buy_ask_price, 2);
         let mut strike_diff = if sell_option.kind == OptionType::Put {
             underlying_bid_or_ask - sell_option.strike
         } else {
@@ -107,21 +113,21 @@ impl Conversion {
         };
         let difficulty = sell_option.dte as f64 * adj_short_fee;
         let natural_price = if sell_option.kind == OptionType::Put {
-            -underlying_bid_or_ask - sell_option.bid_price.unwrap() + buy_option.ask_price.unwrap()
+            -underlying_bid_or_ask - sell_bid_price + buy_ask_price
         } else {
-            underlying_bid_or_ask + sell_option.bid_price.unwrap() - buy_option.ask_price.unwrap()
+            underlying_bid_or_ask + sell_bid_price - buy_ask_price
         };
         let net_iv = 100.0 * (sell_option.iv - buy_option.iv);
         size_vec.sort_unstable();
-        Self {
+        Some(Self {
             symbol: sell_option.root.clone(),
             underlying_bid_or_ask,
             expiration_date: sell_option.expiration_date.clone(),
             dte: sell_option.dte,
             strike: sell_option.strike,
             sell_type: sell_option.kind,
-            sell_bid_price: sell_option.bid_price.unwrap(),
-            buy_ask_price: buy_option.ask_price.unwrap(),
+            sell_bid_price,
+            buy_ask_price,
             option_credit,
             strike_diff,
             gross_profit,
@@ -143,31 +149,71 @@ impl Conversion {
             },
             short_fee,
             annualized_ror: (net_return / sell_option.dte as f64) * 365.0,
-            symbols: [
-                sell_option.symbol.clone(),
-                buy_option.symbol.clone(),
-                sell_option.root.clone(),
-            ],
+            reg_t_margin: 0.0,
+            margin_rules: String::new(),
+            portfolio_margin: 0.0,
+            return_on_margin: 0.0,
+            return_on_portfolio_margin: 0.0,
             difficulty,
             ranking: net_return / difficulty,
             short_fee_cost,
             natural_price,
             net_iv,
+        })
+    }
+    /// The short option is covered by the stock bought against it (a sold put by the stock
+    /// shorted), so the requirement is mostly the stock's.
+    pub fn with_margin(
+        self,
+        sell_option: &OptionData,
+        buy_option: &OptionData,
+        surface: &VolSurface,
+        underlying: f64,
+    ) -> Self {
+        let legs = [
+            SpreadLeg::from_option(sell_option, OrderAction::Sold),
+            SpreadLeg::from_option(buy_option, OrderAction::Bought),
+        ];
+        let stock = StockLeg {
+            side: match self.sell_type {
+                OptionType::Call => OrderAction::Bought,
+                OptionType::Put => OrderAction::Sold,
+            },
+            shares: 100,
+            price: self.underlying_bid_or_ask,
+            inferred: false,
+        };
+        let margin = MarginSummary::of(
+            &self.symbol,
+            &legs,
+            Some(&stock),
+            surface,
+            underlying,
+            self.projected_net_profit,
+        );
+        Self {
+            reg_t_margin: margin.reg_t_margin,
+            margin_rules: margin.margin_rules,
+            portfolio_margin: margin.portfolio_margin,
+            return_on_margin: margin.return_on_margin,
+            return_on_portfolio_margin: margin.return_on_portfolio_margin,
+            ..self
         }
     }
 }
-pub async fn get_conversions_mt(
-    data: Arc<RwLock<OptionChain>>,
-    output: Arc<RwLock<Vec<Conversion>>>,
-) {
-    let chain = data.read().unwrap();
+
+/// Every profitable conversion (sold call, bought put, long stock) and reverse conversion (sold
+/// put, bought call, short stock) at the strikes around the underlying. Index options have no
+/// stock to trade against, and dividends that would be paid first or are poisoned rule a
+/// trade out.
+pub fn get_conversions(chain: &OptionChain, surface: &VolSurface) -> Vec<Conversion> {
     if chain.symbol.contains('^') {
-        return;
+        return Vec::new();
     }
-    let atm_strikes = chain.get_at_the_money_strikes();
+    let atm_strikes = chain.at_the_money_strikes();
     let mut output_vec: Vec<Conversion> = Vec::new();
     for strike in atm_strikes {
-        for expiration in &chain.expirations {
+        for expiration in &chain.expirations() {
             let itm_options: Vec<&OptionData> = chain
                 .options
                 .iter()
@@ -192,13 +238,21 @@ pub async fn get_conversions_mt(
                         OptionType::Call => chain.underlying_ask,
                         OptionType::Put => chain.underlying_bid,
                     };
-                    let conversion = Conversion::from_pair(
+                    let Some(conversion) = Conversion::from_pair(
                         sell_option,
                         buy_option,
                         bid_or_ask,
                         &chain.data_timestamp,
                         &chain.dividend_info,
                         chain.short_fee,
+                    ) else {
+                        continue;
+                    };
+                    let conversion = conversion.with_margin(
+                        sell_option,
+                        buy_option,
+                        surface,
+                        chain.underlying_mid,
                     );
                     if conversion.projected_net_profit > 0.0 {
                         match &chain.dividend_info {
@@ -214,6 +268,5 @@ pub async fn get_conversions_mt(
             }
         }
     }
-    let mut write = output.write().unwrap();
-    write.append(&mut output_vec);
+    output_vec
 }
@@ -0,0 +1,161 @@
+use crate::models::OptionChain;
+use crate::others::round_to_decimals;
+use crate::single_options::OptionType;
+use crate::trades::OptionTrade;
+use chrono::NaiveTime;
+use chrono::Timelike;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum BarSize {
+    OneMinute,
+    FiveMinutes,
+    ThirtyMinutes,
+}
+impl BarSize {
+    pub fn minutes(&self) -> u32 {
+        match self {
+            BarSize::OneMinute => 1,
+            BarSize::FiveMinutes => 5,
+            BarSize::ThirtyMinutes => 30,
+        }
+    }
+    pub fn bar_start(&self, time: NaiveTime) -> NaiveTime {
+        let seconds = time.num_seconds_from_midnight();
+        let bar_seconds = self.minutes() * 60;
+        NaiveTime::from_num_seconds_from_midnight(seconds - seconds % bar_seconds, 0)
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct FlowBar {
+    pub symbol: String,
+    pub bar_start: String,
+    pub bar_size: BarSize,
+    pub trade_count: i64,
+    pub contracts: i64,
+    pub call_premium: f64,
+    pub put_premium: f64,
+    pub net_premium: f64,
+    pub cumulative_net_premium: f64,
+    pub net_delta: f64,
+    pub cumulative_net_delta: f64,
+    pub sweep_count: i64,
+}
+impl FlowBar {
+    fn new(symbol: &str, bar_start: NaiveTime, bar_size: BarSize) -> Self {
+        Self {
+            symbol: symbol.to_string(),
+            bar_start: bar_start.format("%H:%M").to_string(),
+            bar_size,
+            trade_count: 0,
+            contracts: 0,
+            call_premium: 0.0,
+            put_premium: 0.0,
+            net_premium: 0.0,
+            cumulative_net_premium: 0.0,
+            net_delta: 0.0,
+            cumulative_net_delta: 0.0,
+            sweep_count: 0,
+        }
+    }
+    fn add_trade(&mut self, trade: &OptionTrade) {
+        let premium = trade.amount_paid();
+        match trade.option_type {
+            OptionType::Call => self.call_premium += premium,
+            OptionType::Put => self.put_premium += premium,
+        }
+        self.net_premium += premium;
+        self.net_delta += trade.net_delta();
+        self.trade_count += 1;
+        self.contracts += trade.option_trade_size;
+        if trade.condition_id.is_sweep() {
+            self.sweep_count += 1
+        }
+    }
+}
+
+/// Buckets trades by their timestamp into bars of `bar_size`, in session order.
+/// Premium is signed by execution side, so buying flow is positive and selling flow negative.
+pub fn get_flow_bars(symbol: &str, trades: &[OptionTrade], bar_size: BarSize) -> Vec<FlowBar> {
+    let mut bars: BTreeMap<NaiveTime, FlowBar> = BTreeMap::new();
+    for trade in trades {
+        if trade.cancel_flag != 0 || trade.condition_id.is_cancel() {
+            continue;
+        }
+        if let Ok(time) = NaiveTime::parse_from_str(&trade.timestamp, "%H:%M:%S.%3f") {
+            let bar_start = bar_size.bar_start(time);
+            bars.entry(bar_start)
+                .or_insert_with(|| FlowBar::new(symbol, bar_start, bar_size))
+                .add_trade(trade);
+        }
+    }
+    let mut cumulative_net_premium = 0.0;
+    let mut cumulative_net_delta = 0.0;
+    bars.into_values()
+        .map(|mut bar| {
+            cumulative_net_premium += bar.net_premium;
+            cumulative_net_delta += bar.net_delta;
+            bar.call_premium = round_to_decimals(bar.call_premium, 2);
+            bar.put_premium = round_to_decimals(bar.put_premium, 2);
+            bar.net_premium = round_to_decimals(bar.net_premium, 2);
+            bar.net_delta = round_to_decimals(bar.net_delta, 2);
+            bar.cumulative_net_premium = round_to_decimals(cumulative_net_premium, 2);
+            bar.cumulative_net_delta = round_to_decimals(cumulative_net_delta, 2);
+            bar
+        })
+        .collect()
+}
+
+impl OptionChain {
+    pub fn flow_bars(&self, bar_size: BarSize) -> Vec<FlowBar> {
+        get_flow_bars(&self.symbol, &self.trades, bar_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixture_trades;
+    use crate::trades::OrderAction;
+
+    fn trades() -> Vec<OptionTrade> {
+        let mut trades = fixture_trades(include_str!("../tests/fixtures/vertical.json"));
+        trades[1].timestamp = "10:34:59.999".to_string();
+        trades[2].timestamp = "10:36:00.000".to_string();
+        trades[2].order_action = OrderAction::Bought;
+        let mut cancelled = trades[0].clone();
+        cancelled.cancel_flag = 1;
+        trades.push(cancelled);
+        trades
+    }
+
+    #[test]
+    fn buckets_trades_with_running_totals() {
+        let bars = get_flow_bars("SPY", &trades(), BarSize::FiveMinutes);
+        assert_eq!(bars.len(), 2);
+        assert_eq!(bars[0].bar_start, "10:30");
+        assert_eq!(bars[0].trade_count, 2);
+        assert_eq!(bars[0].contracts, 1000);
+        assert_eq!(bars[0].call_premium, 620000.0 - 390000.0);
+        assert_eq!(bars[0].put_premium, 0.0);
+        assert_eq!(bars[0].net_delta, 7000.0);
+        assert_eq!(bars[0].cumulative_net_premium, 230000.0);
+        assert_eq!(bars[1].bar_start, "10:35");
+        assert_eq!(bars[1].put_premium, 36600.0);
+        assert_eq!(bars[1].net_delta, -1440.0);
+        assert_eq!(bars[1].cumulative_net_premium, 266600.0);
+        assert_eq!(bars[1].cumulative_net_delta, 5560.0);
+        let minute_bars = get_flow_bars("SPY", &trades(), BarSize::OneMinute);
+        assert_eq!(
+            minute_bars
+                .iter()
+                .map(|bar| bar.bar_start.as_str())
+                .collect::<Vec<&str>>(),
+            ["10:31", "10:34", "10:36"]
+        );
+        assert_eq!(minute_bars[2].cumulative_net_premium, 266600.0);
+    }
+}
@@ -0,0 +1,244 @@
+use crate::models::OptionChain;
+use crate::others::load_json;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum GroupKind {
+    /// Members are traded as one: their flow becomes a single signal on `trade_symbol` and
+    /// they drop out of the single name signals.
+    Index,
+    /// Members keep their own signals and the group only gets a flow rollup.
+    Sector,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SymbolGroup {
+    pub name: String,
+    pub kind: GroupKind,
+    pub trade_symbol: String,
+    pub members: Vec<String>,
+}
+impl SymbolGroup {
+    fn new(name: &str, kind: GroupKind, members: &[&str]) -> Self {
+        Self {
+            name: name.to_string(),
+            kind,
+            trade_symbol: members[0].to_string(),
+            members: members.iter().map(|member| member.to_string()).collect(),
+        }
+    }
+    pub fn contains(&self, symbol: &str) -> bool {
+        self.members.iter().any(|member| member == symbol)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct GroupConfig {
+    pub groups: Vec<SymbolGroup>,
+}
+impl Default for GroupConfig {
+    fn default() -> Self {
+        let index =
+            |name: &str, members: &[&str]| SymbolGroup::new(name, GroupKind::Index, members);
+        let sector = |name: &str, etf: &str| SymbolGroup::new(name, GroupKind::Sector, &[etf]);
+        Self {
+            groups: vec![
+                index("S&P 500", &["SPY", "^SPX", "XSP"]),
+                index("Nasdaq 100", &["QQQ", "^NDX"]),
+                index("Russell 2000", &["IWM", "^RUT"]),
+                sector("Technology", "XLK"),
+                sector("Financials", "XLF"),
+                sector("Energy", "XLE"),
+                sector("Health Care", "XLV"),
+                sector("Consumer Discretionary", "XLY"),
+                sector("Consumer Staples", "XLP"),
+                sector("Industrials", "XLI"),
+                sector("Utilities", "XLU"),
+                sector("Materials", "XLB"),
+                sector("Real Estate", "XLRE"),
+                sector("Communication Services", "XLC"),
+            ],
+        }
+    }
+}
+impl GroupConfig {
+    /// Reads the groups from a json file, the defaults when there is none and an error when it
+    /// doesn't parse, and then adds any `SYMBOL,Sector` lines of the symbol list to the sector
+    /// named (or whose ETF is named) in the second column.
+    pub fn load(filename: &str, list_filename: &str) -> Result<Self, String> {
+        let mut config: Self = load_json(filename)?.unwrap_or_default();
+        if let Ok(file) = std::fs::read_to_string(list_filename) {
+            config.add_list_sectors(&file);
+        }
+        Ok(config)
+    }
+    fn add_list_sectors(&mut self, list: &str) {
+        for (symbol, sector) in list.lines().filter_map(|line| {
+            let mut fields = line.split(',').map(|field| field.trim());
+            Some((fields.next()?, fields.next()?))
+        }) {
+            if let Some(group) = self.groups.iter_mut().find(|group| {
+                group.kind == GroupKind::Sector
+                    && (group.name.eq_ignore_ascii_case(sector)
+                        || group.trade_symbol.eq_ignore_ascii_case(sector))
+            }) {
+                if !group.contains(symbol) {
+                    group.members.push(symbol.to_string())
+                }
+            }
+        }
+    }
+    pub fn index_groups(&self) -> impl Iterator<Item = &SymbolGroup> {
+        self.groups
+            .iter()
+            .filter(|group| group.kind == GroupKind::Index)
+    }
+    /// Whether a symbol is traded through an index group rather than on its own.
+    pub fn in_index_group(&self, symbol: &str) -> bool {
+        self.index_groups().any(|group| group.contains(symbol))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct GroupFlow {
+    pub name: String,
+    pub kind: GroupKind,
+    pub trade_symbol: String,
+    /// Mid price of `trade_symbol`, 0 when it wasn't scanned.
+    pub trade_price: f64,
+    pub members_found: String,
+    pub members_missing: String,
+    pub large_trader_net_value: f64,
+    pub large_trader_opening_net_value: f64,
+    pub delta_adjusted_notional: f64,
+    pub net_opening_premium: f64,
+    pub bias_score: f64,
+    pub flow_percentile: f64,
+    pub flow_z_score: f64,
+}
+
+/// Sums the flow of every group member that was scanned. Groups with no member in `chains` are
+/// left out; the cross sectional fields come from `trade_symbol` when it is present.
+pub fn aggregate_groups(chains: &[OptionChain], config: &GroupConfig) -> Vec<GroupFlow> {
+    config
+        .groups
+        .iter()
+        .filter_map(|group| {
+            let (found, missing): (Vec<&String>, Vec<&String>) = group
+                .members
+                .iter()
+                .partition(|member| chains.iter().any(|chain| chain.symbol == **member));
+            if found.is_empty() {
+                return None;
+            }
+            let members = chains
+                .iter()
+                .filter(|chain| group.contains(&chain.symbol))
+                .collect_vec();
+            let trade_chain = chains
+                .iter()
+                .find(|chain| chain.symbol == group.trade_symbol);
+            let sum = |value: fn(&OptionChain) -> f64| -> f64 {
+                members.iter().map(|chain| value(chain)).sum()
+            };
+            Some(GroupFlow {
+                name: group.name.clone(),
+                kind: group.kind,
+                trade_symbol: group.trade_symbol.clone(),
+                trade_price: trade_chain.map_or(0.0, |chain| chain.underlying_mid),
+                members_found: found.iter().join("|"),
+                members_missing: missing.iter().join("|"),
+                large_trader_net_value: sum(|chain| chain.large_trader_net_value),
+                large_trader_opening_net_value: sum(|chain| chain.large_trader_opening_net_value),
+                delta_adjusted_notional: sum(|chain| chain.delta_adjusted_notional),
+                net_opening_premium: sum(|chain| {
+                    chain.bullish_opening_premium - chain.bearish_opening_premium
+                }),
+                bias_score: sum(|chain| chain.bias_score),
+                flow_percentile: trade_chain.map_or(0.0, |chain| chain.flow_percentile),
+                flow_z_score: trade_chain.map_or(0.0, |chain| chain.flow_z_score),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{fixture_chain, get_signals, SignalType};
+
+    fn chain(symbol: &str, price: f64, net_value: f64) -> OptionChain {
+        OptionChain {
+            symbol: symbol.to_string(),
+            underlying_mid: price,
+            large_trader_net_value: net_value,
+            ..fixture_chain(symbol)
+        }
+    }
+
+    #[test]
+    fn signals_without_qqq_in_list() {
+        let chains = vec![
+            chain("SPY", 400.0, 2000000.0),
+            chain("^SPX", 4000.0, 3000000.0),
+            chain("IWM", 200.0, -1000000.0),
+        ];
+        let signals = get_signals(&chains, 2500.0, 25600.0, &GroupConfig::default());
+        let spy = signals
+            .iter()
+            .find(|signal| signal.symbol == "SPY")
+            .unwrap();
+        assert_eq!(spy.large_trader_net_value, 5000000.0);
+        assert_eq!(spy.side, SignalType::Buy);
+        assert_eq!(spy.quantity_2, 64);
+        let iwm = signals
+            .iter()
+            .find(|signal| signal.symbol == "IWM")
+            .unwrap();
+        assert_eq!(iwm.side, SignalType::Sell);
+        assert!(signals.iter().all(|signal| signal.symbol != "QQQ"));
+        assert!(signals.iter().all(|signal| signal.symbol != "^SPX"));
+    }
+
+    #[test]
+    fn rolls_up_sectors_from_list() {
+        let mut config = GroupConfig::default();
+        config.add_list_sectors("SPY\nAAPL,Technology\nMSFT,xlk\nJPM,Financials\n");
+        let chains = vec![
+            chain("AAPL", 150.0, 1000000.0),
+            chain("MSFT", 300.0, 500000.0),
+        ];
+        let flows = aggregate_groups(&chains, &config);
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0].name, "Technology");
+        assert_eq!(flows[0].large_trader_net_value, 1500000.0);
+        assert_eq!(flows[0].members_found, "AAPL|MSFT");
+        assert_eq!(flows[0].members_missing, "XLK");
+        assert_eq!(flows[0].trade_price, 0.0);
+    }
+
+    #[test]
+    fn trades_index_group_through_a_scanned_member() {
+        let chains = vec![chain("^SPX", 4000.0, 3000000.0)];
+        let signals = get_signals(&chains, 2500.0, 25600.0, &GroupConfig::default());
+        assert_eq!(signals.len(), 2);
+        assert_eq!(signals[0].symbol, "^SPX");
+        assert_eq!(signals[0].side, SignalType::Buy);
+        assert_eq!(signals[0].quantity_2, 6);
+    }
+
+    #[test]
+    fn refuses_malformed_config() {
+        let filename = std::env::temp_dir().join("malformed_group_config.json");
+        std::fs::write(&filename, r#"{"groups": "SPY"}"#).unwrap();
+        let result = GroupConfig::load(filename.to_str().unwrap(), "no_such_list.csv");
+        std::fs::remove_file(&filename).unwrap();
+        assert!(result.is_err());
+        assert_eq!(
+            GroupConfig::load("no_such_config.json", "no_such_list.csv"),
+            Ok(GroupConfig::default())
+        );
+    }
+}
@@ -0,0 +1,236 @@
+use crate::trades::OptionTrade;
+use crate::LEG_MATCH_TOLERANCE_MS;
+use crate::MAX_LEGS_PER_ORDER;
+use crate::MAX_LEG_RATIO;
+use chrono::NaiveTime;
+use itertools::Itertools;
+
+/// Partitions the multi-leg prints in `trades` into complex orders. Every multi-leg, non
+/// cancelled trade ends up in exactly one order, and orders come out in sequence number order.
+///
+/// Trades are walked in `seq_no` order and matched against every order still open, so legs of
+/// orders that interleave on the tape, or that have gaps where prints were left out of the
+/// fetch, still end up together. A trade can join an order when it shares the root, condition
+/// and exchange of its legs, printed within `LEG_MATCH_TOLERANCE_MS` of its last leg, is a
+/// contract not already in the order, keeps the leg sizes within `MAX_LEG_RATIO` of each other,
+/// and traded at a price consistent with its own quote. When several orders qualify, the one
+/// whose last leg directly precedes it by `exchange_seq_no` or `seq_no` wins, then the most
+/// recent. Anything else starts a new order.
+pub fn match_legs(trades: &[OptionTrade]) -> Vec<Vec<&OptionTrade>> {
+    let multilegs = trades
+        .iter()
+        .filter(|trade| trade.condition_id.is_multi_leg() && !trade.condition_id.is_cancel())
+        .sorted_by_key(|trade| (trade.seq_no, trade.exchange_seq_no))
+        .collect_vec();
+    let mut orders: Vec<Vec<&OptionTrade>> = Vec::new();
+    let mut open: Vec<usize> = Vec::new();
+    for trade in multilegs {
+        // an order whose last leg printed too long before this trade can't take any more legs
+        open.retain(|&index| {
+            orders[index]
+                .last()
+                .is_some_and(|last| within_tolerance(&last.timestamp, &trade.timestamp))
+        });
+        let best = open
+            .iter()
+            .copied()
+            .filter(|&index| continues_order(&orders[index], trade))
+            .max_by_key(|&index| {
+                let last = orders[index].last().unwrap();
+                (
+                    trade.seq_no == last.seq_no + 1
+                        || trade.exchange_seq_no == last.exchange_seq_no + 1,
+                    last.seq_no,
+                )
+            });
+        match best {
+            Some(index) => orders[index].push(trade),
+            None => {
+                open.push(orders.len());
+                orders.push(vec![trade]);
+            }
+        }
+    }
+    orders
+}
+
+fn continues_order(order: &[&OptionTrade], trade: &OptionTrade) -> bool {
+    let previous = match order.last() {
+        Some(previous) => previous,
+        None => return false,
+    };
+    order.len() < MAX_LEGS_PER_ORDER
+        && previous.root == trade.root
+        && previous.condition_id == trade.condition_id
+        && previous.exchange_id == trade.exchange_id
+        && within_tolerance(&previous.timestamp, &trade.timestamp)
+        && order.iter().all(|leg| leg.symbol != trade.symbol)
+        && ratio_consistent(order, trade)
+        && price_consistent(trade)
+}
+
+fn within_tolerance(first: &str, second: &str) -> bool {
+    if first == second {
+        return true;
+    }
+    match (
+        NaiveTime::parse_from_str(first, "%H:%M:%S.%3f"),
+        NaiveTime::parse_from_str(second, "%H:%M:%S.%3f"),
+    ) {
+        (Ok(first), Ok(second)) => {
+            (second - first).num_milliseconds().abs() <= LEG_MATCH_TOLERANCE_MS
+        }
+        _ => false,
+    }
+}
+
+/// Leg sizes of one order reduce to a small whole number ratio such as 1x2 or 1x3x2.
+fn ratio_consistent(order: &[&OptionTrade], trade: &OptionTrade) -> bool {
+    let sizes = order
+        .iter()
+        .map(|leg| leg.option_trade_size)
+        .chain(std::iter::once(trade.option_trade_size))
+        .collect_vec();
+    if sizes.iter().any(|size| *size <= 0) {
+        return false;
+    }
+    let divisor = sizes.iter().fold(0, |acc, size| gcd(acc, *size));
+    sizes.iter().all(|size| size / divisor <= MAX_LEG_RATIO)
+}
+
+/// Complex orders can trade legs through the market, but not by more than the quoted width.
+fn price_consistent(trade: &OptionTrade) -> bool {
+    match (trade.option_trade_price, trade.bid_price, trade.ask_price) {
+        (Some(price), Some(bid), Some(ask)) if ask >= bid && ask > 0.0 => {
+            let width = ask - bid;
+            price >= bid - width && price <= ask + width
+        }
+        (Some(_), _, _) => true,
+        (None, _, _) => false,
+    }
+}
+
+pub fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixture_trades;
+    use crate::spreads::{get_spreads, SpreadName};
+
+    fn seq_nos(orders: &[Vec<&OptionTrade>]) -> Vec<Vec<i64>> {
+        orders
+            .iter()
+            .map(|order| order.iter().map(|trade| trade.seq_no).collect())
+            .collect()
+    }
+
+    #[test]
+    fn groups_consecutive_vertical_legs() {
+        let trades = fixture_trades(include_str!("../tests/fixtures/vertical.json"));
+        let orders = match_legs(&trades);
+        assert_eq!(seq_nos(&orders), vec![vec![1001, 1002]]);
+    }
+
+    #[test]
+    fn splits_back_to_back_identical_orders() {
+        let trades = fixture_trades(include_str!("../tests/fixtures/repeated_verticals.json"));
+        let orders = match_legs(&trades);
+        assert_eq!(seq_nos(&orders), vec![vec![2001, 2002], vec![2003, 2004]]);
+        let spreads = get_spreads(trades);
+        assert_eq!(spreads.len(), 2);
+        assert!(spreads
+            .iter()
+            .all(|spread| spread.spread_name == SpreadName::Vertical));
+    }
+
+    #[test]
+    fn keeps_unequal_ratio_legs_together() {
+        let trades = fixture_trades(include_str!("../tests/fixtures/ratio_spread.json"));
+        let orders = match_legs(&trades);
+        assert_eq!(seq_nos(&orders), vec![vec![3001, 3002]]);
+    }
+
+    #[test]
+    fn separates_legs_outside_tolerance_or_sequence() {
+        let trades = fixture_trades(include_str!("../tests/fixtures/unrelated_legs.json"));
+        let orders = match_legs(&trades);
+        assert_eq!(
+            seq_nos(&orders),
+            vec![vec![4001], vec![4002], vec![4010], vec![4011]]
+        );
+    }
+
+    #[test]
+    fn falls_back_to_exchange_sequence_numbers() {
+        let trades = fixture_trades(include_str!("../tests/fixtures/exchange_sequence.json"));
+        let orders = match_legs(&trades);
+        assert_eq!(seq_nos(&orders), vec![vec![5001, 5007, 5013, 5020]]);
+    }
+
+    #[test]
+    fn untangles_interleaved_orders() {
+        let trades = fixture_trades(include_str!("../tests/fixtures/interleaved_orders.json"));
+        let orders = match_legs(&trades);
+        assert_eq!(
+            seq_nos(&orders),
+            vec![vec![10001, 10003], vec![10002, 10004]]
+        );
+        let spreads = get_spreads(trades);
+        assert_eq!(spreads.len(), 2);
+        assert!(spreads
+            .iter()
+            .all(|spread| spread.spread_name == SpreadName::Vertical));
+    }
+
+    #[test]
+    fn bridges_gaps_in_the_sequence() {
+        let trades = fixture_trades(include_str!("../tests/fixtures/gapped_order.json"));
+        let orders = match_legs(&trades);
+        assert_eq!(seq_nos(&orders), vec![vec![11001, 11004, 11009, 11012]]);
+    }
+
+    #[test]
+    fn assigns_every_multi_leg_trade_exactly_once() {
+        let json = [
+            include_str!("../tests/fixtures/vertical.json"),
+            include_str!("../tests/fixtures/repeated_verticals.json"),
+            include_str!("../tests/fixtures/ratio_spread.json"),
+            include_str!("../tests/fixtures/unrelated_legs.json"),
+            include_str!("../tests/fixtures/exchange_sequence.json"),
+            include_str!("../tests/fixtures/interleaved_orders.json"),
+            include_str!("../tests/fixtures/gapped_order.json"),
+        ];
+        let trades = json
+            .iter()
+            .flat_map(|file| fixture_trades(file))
+            .collect_vec();
+        let orders = match_legs(&trades);
+        let matched = orders
+            .iter()
+            .flatten()
+            .map(|trade| trade.seq_no)
+            .sorted()
+            .collect_vec();
+        let expected = trades
+            .iter()
+            .filter(|trade| trade.condition_id.is_multi_leg())
+            .map(|trade| trade.seq_no)
+            .sorted()
+            .collect_vec();
+        assert_eq!(matched, expected);
+        assert!(!matched.contains(&1003));
+    }
+
+    #[test]
+    fn handles_no_trades() {
+        assert!(match_legs(&[]).is_empty());
+        assert!(get_spreads(Vec::new()).is_empty());
+    }
+}
@@ -1,20 +1,66 @@
 use crate::{
+    alerts::{AlertConfig, AlertEngine},
+    backtest::{run_backtest, BacktestConfig, Instrument, Slippage},
+    boxes::get_boxes,
+    conversions::get_conversions,
+    flow_bars::BarSize,
+    groups::{aggregate_groups, GroupConfig, GroupFlow},
     models::{get_signals, ShortStockInfo, Signal},
-    others::{create_csv_file, get_list},
+    orders::{build_tickets, fix_messages, ib_basket, send_fix_orders, OrderConfig},
+    others::{create_csv_file, create_json_file, get_list},
+    paper::{PaperAccount, PaperConfig},
+    recommend::{recommend, RecommendConfig},
+    risk::{load_portfolio, risk_report, RiskConfig},
+    rolls::Roll,
+    scoring::{BiasModel, FactorContribution},
+    sizing::PositionSizer,
     spreads::OptionSpread,
+    straddles::get_straddles,
+    tracked_positions::PositionTracker,
+    universe::{normalize_universe, SizeBasis, VolumeHistory},
+    unusual_activity::UnusualActivity,
+    vertical_spreads::get_vertical_spreads,
 };
+use chrono::NaiveDate;
 use models::{OptionChain, ShortStockData};
 use requests::get_auth;
+mod alerts;
+mod backfill;
+mod backtest;
+mod boxes;
+mod conversions;
 mod credentials;
+mod flow_bars;
+mod groups;
+mod leg_matching;
+mod margin;
 mod models;
+mod orders;
 mod others;
+mod paper;
+mod payoff;
+mod premium_flow;
+mod pricing;
+mod recommend;
 mod requests;
+mod risk;
+mod rolls;
+mod scoring;
 mod single_options;
+mod sizing;
 mod spreads;
+mod straddles;
 mod strategies;
+mod streaming;
+mod tracked_positions;
 mod trades;
+mod universe;
+mod unusual_activity;
+mod vertical_spreads;
+mod vol_surface;
 pub const OPTION_COMMISSION: f64 = 2.0;
 pub const MARGIN_LOAN_RATE: f64 = 1.6;
+pub const RISK_FREE_RATE: f64 = 4.5;
 pub const STOCK_COMMISSION: f64 = 0.55;
 pub const SHORT_STOCK_DATA_FP: &str = "ftp3.interactivebrokers.com";
 pub const SHORT_FEE_MARGIN_SAFETY: f64 = 1.2;
@@ -25,19 +71,202 @@ pub const TRADES_TO_INCLUDE: &str = "10000";
 pub const MONSTER_SIZE: f64 = 10000000.0;
 pub const DESCRIPTIONS_FILEPATH: &str = "ConditionDescriptions.csv";
 pub const LIST_LOCATION: &str = "new-list.csv";
+pub const GROUP_CONFIG_FILEPATH: &str = "group-config.json";
 pub const AMOUNT_IN_ACCOUNT: f64 = 25000.0;
+pub const FLOW_BAR_SIZE: BarSize = BarSize::FiveMinutes;
+pub const UOA_VOLUME_OI_RATIO: f64 = 5.0;
+pub const UOA_MIN_VOLUME: i64 = 500;
+pub const UOA_PREMIUM_MULTIPLE: f64 = 10.0;
+pub const UOA_MAX_DTE: i64 = 14;
+pub const UOA_MIN_SWEEPS: usize = 3;
+pub const LEG_MATCH_TOLERANCE_MS: i64 = 250;
+pub const MAX_LEG_RATIO: i64 = 5;
+pub const MAX_LEGS_PER_ORDER: usize = 6;
+pub const EXPECTATION_NEUTRAL_DELTA: f64 = 0.01;
+pub const PIN_DISTANCE_PERCENT: f64 = 2.0;
+pub const ROLL_MATCH_TOLERANCE_MS: i64 = 2000;
+pub const TRACKED_POSITIONS_FILEPATH: &str = "tracked-positions";
+pub const ALERT_CONFIG_FILEPATH: &str = "alert-config.json";
+pub const SOCKET_TIMEOUT_SECONDS: u64 = 10;
+pub const BACKFILL_DIRECTORY: &str = "history";
+pub const BACKFILL_DATES: Option<(&str, &str)> = None;
+pub const RUN_BACKTEST: bool = true;
+pub const BACKTEST_CONFIG_FILEPATH: &str = "backtest-config.json";
+pub const BACKTEST_HOLDING_DAYS: usize = 5;
+pub const BACKTEST_STOP_LOSS_PERCENT: Option<f64> = None;
+pub const BACKTEST_TAKE_PROFIT_PERCENT: Option<f64> = None;
+pub const BACKTEST_SLIPPAGE: Slippage = Slippage::Bps(5.0);
+pub const BACKTEST_INSTRUMENT: Instrument = Instrument::Underlying;
+pub const BACKTEST_OPTION_MIN_DTE: i64 = 30;
+pub const BIAS_CONFIG_FILEPATH: &str = "bias-config.json";
+pub const BIAS_HISTORY_FILEPATH: &str = "bias-history";
+pub const BIAS_MIN_HISTORY: usize = 10;
+pub const BIAS_HISTORY_LENGTH: usize = 60;
+pub const SIZING_CONFIG_FILEPATH: &str = "sizing-config.json";
+pub const POSITIONS_FILEPATH: &str = "positions.json";
+pub const PRICE_HISTORY_FILEPATH: &str = "price-history";
+pub const SIZING_IV_DTE: i64 = 30;
+pub const SIZING_MAX_SYMBOL_PERCENT: f64 = 20.0;
+pub const SIZING_MAX_GROSS_PERCENT: f64 = 200.0;
+pub const SIZING_MAX_NET_PERCENT: f64 = 100.0;
+pub const SIZING_REALIZED_VOL_DAYS: usize = 20;
+pub const RECOMMEND_CONFIG_FILEPATH: &str = "recommend-config.json";
+pub const RECOMMEND_TARGET_DTE: i64 = 30;
+pub const RECOMMEND_RISK_PERCENT: f64 = 1.0;
+pub const RECOMMEND_MAX_SPREAD_PERCENT: f64 = 15.0;
+pub const RECOMMEND_MIN_OPEN_INTEREST: i64 = 100;
+pub const RECOMMEND_EDGE_SD: f64 = 0.25;
+pub const RECOMMEND_RICH_VOL_RATIO: f64 = 1.2;
+pub const RECOMMEND_PER_SIGNAL: usize = 2;
+pub const MARGIN_STOCK_PERCENT: f64 = 50.0;
+pub const MARGIN_NAKED_PERCENT: f64 = 20.0;
+pub const MARGIN_NAKED_MIN_PERCENT: f64 = 10.0;
+pub const MARGIN_EQUITY_RANGE_PERCENT: f64 = 15.0;
+pub const MARGIN_INDEX_RANGE_PERCENT: (f64, f64) = (8.0, 6.0);
+pub const MARGIN_BROAD_INDEX_ETFS: [&str; 5] = ["SPY", "QQQ", "IWM", "DIA", "XSP"];
+pub const MARGIN_PM_MIN_PER_CONTRACT: f64 = 37.5;
+pub const ORDER_CONFIG_FILEPATH: &str = "order-config.json";
+pub const PAPER_TRADING: bool = false;
+pub const PAPER_CONFIG_FILEPATH: &str = "paper-config.json";
+pub const PAPER_ACCOUNT_FILEPATH: &str = "paper-account";
+pub const PAPER_COMMISSION_PER_CONTRACT: f64 = 0.65;
+pub const PAPER_COMMISSION_PER_SHARE: f64 = 0.005;
+pub const PAPER_ORDER_LIFE_DAYS: i64 = 1;
+pub const PORTFOLIO_FILEPATH: &str = "portfolio.json";
+pub const RISK_CONFIG_FILEPATH: &str = "risk-config.json";
+pub const RISK_BENCHMARK: &str = "SPY";
+pub const RISK_PRICE_MOVES: [f64; 7] = [-10.0, -5.0, -2.0, 0.0, 2.0, 5.0, 10.0];
+pub const RISK_IV_MOVES: [f64; 3] = [-5.0, 0.0, 5.0];
+pub const RISK_DAYS_FORWARD: [i64; 3] = [0, 7, 30];
+pub const UNIVERSE_SIZE_BASIS: SizeBasis = SizeBasis::OpenInterest;
+pub const UNIVERSE_VOLUME_HISTORY_FILEPATH: &str = "option-volume-history";
+pub const UNIVERSE_VOLUME_DAYS: usize = 20;
+pub const STREAMING_MODE: bool = false;
+pub const STREAM_POLL_SECONDS: u64 = 15;
+pub const STREAM_BATCH_LIMIT: &str = "1000";
+pub const STREAM_SNAPSHOT_REFRESH_POLLS: u64 = 20;
+pub const STREAM_REBUILD_POLLS: u64 = 4;
+pub const STREAM_REPLAY_DIRECTORY: Option<&str> = None;
+pub const STREAM_REPLAY_BATCH_SIZE: usize = 50;
 #[tokio::main]
 async fn main() {
     let short_fees = ShortStockInfo::get().await;
     let start = tokio::time::Instant::now();
     if let Ok(symbol_list) = get_list(LIST_LOCATION) {
+        let mut alert_engine = match AlertConfig::load(ALERT_CONFIG_FILEPATH) {
+            Ok(config) => AlertEngine::new(config),
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+        let mut bias_model = match BiasModel::load(BIAS_CONFIG_FILEPATH, BIAS_HISTORY_FILEPATH) {
+            Ok(bias_model) => bias_model,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+        let groups = match GroupConfig::load(GROUP_CONFIG_FILEPATH, LIST_LOCATION) {
+            Ok(groups) => groups,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+        let mut sizer = match PositionSizer::load(
+            SIZING_CONFIG_FILEPATH,
+            POSITIONS_FILEPATH,
+            PRICE_HISTORY_FILEPATH,
+        ) {
+            Ok(sizer) => sizer,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+        let recommend_config = match RecommendConfig::load(RECOMMEND_CONFIG_FILEPATH) {
+            Ok(recommend_config) => recommend_config,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+        let order_config = match OrderConfig::load(ORDER_CONFIG_FILEPATH) {
+            Ok(order_config) => order_config,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+        let mut volumes = match VolumeHistory::load(UNIVERSE_VOLUME_HISTORY_FILEPATH) {
+            Ok(volumes) => volumes,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+        if let Some((start_date, end_date)) = BACKFILL_DATES {
+            let backtest_config = match BacktestConfig::load(BACKTEST_CONFIG_FILEPATH) {
+                Ok(backtest_config) => backtest_config,
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            };
+            let chains = backfill::backfill(
+                &symbol_list,
+                NaiveDate::parse_from_str(start_date, "%F").unwrap(),
+                NaiveDate::parse_from_str(end_date, "%F").unwrap(),
+                &short_fees,
+                &bias_model.config,
+            )
+            .await;
+            create_csv_file(&chains, "Backfill-ChainData");
+            for chain in &chains {
+                sizer.prices.record(chain);
+                volumes.record(chain);
+            }
+            sizer.save_prices(PRICE_HISTORY_FILEPATH);
+            volumes.save(UNIVERSE_VOLUME_HISTORY_FILEPATH);
+            if RUN_BACKTEST {
+                let report = run_backtest(&chains, &backtest_config, &groups);
+                create_csv_file(&[report.summary], "Backtest-Summary");
+                create_csv_file(&report.trades, "Backtest-Trades");
+                create_csv_file(&report.attribution, "Backtest-Attribution");
+            }
+            let spreads: Vec<OptionSpread> =
+                chains.into_iter().flat_map(|chain| chain.spreads).collect();
+            create_csv_file(&spreads, "Backfill-Trades");
+            println!("Completed in {} seconds", start.elapsed().as_secs());
+            return;
+        }
+        if STREAMING_MODE {
+            streaming::run_live_session(
+                &symbol_list,
+                &short_fees,
+                &mut alert_engine,
+                &bias_model,
+                &streaming::LiveReports {
+                    groups: &groups,
+                    sizer: &sizer,
+                    volumes: &volumes,
+                    recommend_config: &recommend_config,
+                },
+            )
+            .await;
+            println!("Completed in {} seconds", start.elapsed().as_secs());
+            return;
+        }
         let mut all_option_chains: Vec<OptionChain> = Vec::new();
         let mut n = 1;
         println!("Starting up...");
         for symbol in &symbol_list {
             let start_time = tokio::time::Instant::now();
             let short_data = short_fees.data.iter().find(|item| item.symbol == *symbol);
-            if let Some(chain) = get_chain_for_one_symbol(symbol, short_data).await {
+            if let Some(chain) = get_chain_for_one_symbol(symbol, short_data, &bias_model).await {
+                let alerts = alert_engine.evaluate(&chain);
+                alert_engine.dispatch(&alerts).await;
                 all_option_chains.push(chain);
             }
             let one_thousand: u64 = 1000;
@@ -54,18 +283,170 @@ async fn main() {
             );
             n += 1;
         }
+        for chain in &all_option_chains {
+            bias_model.record(chain);
+        }
+        bias_model.save_history(BIAS_HISTORY_FILEPATH);
+        let bias_contributions: Vec<FactorContribution> = all_option_chains
+            .iter()
+            .flat_map(|chain| chain.bias_contributions.clone())
+            .collect();
+        create_csv_file(&bias_contributions, "ALL-BiasContributions");
+        for chain in &all_option_chains {
+            volumes.record(chain);
+        }
+        volumes.save(UNIVERSE_VOLUME_HISTORY_FILEPATH);
+        let cross_sectional =
+            normalize_universe(&mut all_option_chains, UNIVERSE_SIZE_BASIS, &volumes);
+        create_csv_file(&cross_sectional, "ALL-CrossSectional");
         all_option_chains.sort_unstable_by_key(|chain| -chain.bias);
-        let signals: Vec<Signal> = get_signals(&all_option_chains, 2500.0, 25600.0);
+        for chain in &all_option_chains {
+            sizer.prices.record(chain);
+        }
+        sizer.save_prices(PRICE_HISTORY_FILEPATH);
+        let mut signals: Vec<Signal> = get_signals(&all_option_chains, 2500.0, 25600.0, &groups);
+        sizer.size(&mut signals, &all_option_chains);
+        let recommendations = recommend(
+            &signals,
+            &all_option_chains,
+            &recommend_config,
+            sizer.config.account_value,
+            |symbol| {
+                sizer
+                    .prices
+                    .realized_volatility(symbol, sizer.config.realized_vol_days)
+            },
+        );
+        create_csv_file(&recommendations, "Trade-Recommendations");
+        let tickets = build_tickets(
+            &signals,
+            &recommendations,
+            &all_option_chains,
+            &order_config,
+        );
+        create_csv_file(&tickets, "Orders-Tickets");
+        create_csv_file(&ib_basket(&tickets, &order_config), "Orders-IB-Basket");
+        if !tickets.is_empty() {
+            std::fs::write(
+                format!("Orders-FIX-{}.txt", chrono::Local::now().format("%F-%H%M")),
+                fix_messages(&tickets, &order_config).join("\n"),
+            )
+            .unwrap();
+        }
+        if let Some(acceptor) = &order_config.acceptor {
+            match send_fix_orders(acceptor, &tickets, &order_config).await {
+                Ok(acks) => create_csv_file(&acks, "Orders-FIX-Acks"),
+                Err(e) => println!("FIX session with {} failed: {}", acceptor, e),
+            }
+        }
+        if PAPER_TRADING {
+            match PaperConfig::load(PAPER_CONFIG_FILEPATH).and_then(|paper_config| {
+                Ok((
+                    PaperAccount::load(PAPER_ACCOUNT_FILEPATH, &paper_config)?,
+                    paper_config,
+                ))
+            }) {
+                Ok((mut paper, paper_config)) => {
+                    paper.process(&all_option_chains, &paper_config);
+                    paper.submit(&tickets, &all_option_chains);
+                    paper.save(PAPER_ACCOUNT_FILEPATH);
+                    create_csv_file(
+                        &paper
+                            .orders
+                            .iter()
+                            .map(|order| order.row())
+                            .collect::<Vec<_>>(),
+                        "Paper-Orders",
+                    );
+                    create_csv_file(&paper.positions, "Paper-Positions");
+                    create_csv_file(&paper.fills, "Paper-Fills");
+                    create_csv_file(&paper.history, "Paper-Account");
+                }
+                Err(e) => println!("Paper account left untouched: {}", e),
+            }
+        }
+        let group_flows: Vec<GroupFlow> = aggregate_groups(&all_option_chains, &groups);
+        create_csv_file(&group_flows, "ALL-GroupFlow");
         create_csv_file(&signals, "Trade-Signals");
         create_csv_file(&all_option_chains, "ALL-ChainData");
+        for chain in &all_option_chains {
+            create_csv_file(
+                &chain.flow_bars(FLOW_BAR_SIZE),
+                &format!("{}-FlowBars", chain.symbol),
+            );
+        }
+        let mut all_unusual_activity: Vec<UnusualActivity> = all_option_chains
+            .iter()
+            .flat_map(|chain| chain.unusual_activity())
+            .collect();
+        all_unusual_activity.sort_unstable_by(|a, b| b.score.total_cmp(&a.score));
+        create_csv_file(&all_unusual_activity, "ALL-UnusualActivity");
+        let mut all_rolls: Vec<Roll> = all_option_chains
+            .iter()
+            .flat_map(|chain| chain.rolls.clone())
+            .collect();
+        all_rolls.sort_unstable_by_key(|roll| -roll.gross_value as i64);
+        create_csv_file(&all_rolls, "ALL-Rolls");
+        match PositionTracker::load(TRACKED_POSITIONS_FILEPATH) {
+            Ok(mut tracker) => {
+                for chain in &all_option_chains {
+                    tracker.update(chain);
+                }
+                tracker.save(TRACKED_POSITIONS_FILEPATH);
+                create_csv_file(&tracker.positions, "ALL-TrackedPositions");
+            }
+            Err(e) => println!("Tracked positions left untouched: {}", e),
+        }
+        match load_portfolio(PORTFOLIO_FILEPATH, &sizer.holdings)
+            .and_then(|portfolio| Ok((portfolio, RiskConfig::load(RISK_CONFIG_FILEPATH)?)))
+        {
+            Ok((portfolio, risk_config)) if !portfolio.is_empty() => {
+                let report = risk_report(&portfolio, &all_option_chains, &risk_config);
+                create_csv_file(&report.positions, "Risk-Positions");
+                create_csv_file(&[report.summary], "Risk-Summary");
+                create_csv_file(&report.scenarios, "Risk-Scenarios");
+            }
+            Ok(_) => {}
+            Err(e) => println!("No risk report: {}", e),
+        }
+        let mut all_boxes = Vec::new();
+        let mut all_conversions = Vec::new();
+        let mut all_vertical_spreads = Vec::new();
+        let mut all_condoors = Vec::new();
+        let mut all_straddles = Vec::new();
+        for chain in &all_option_chains {
+            let surface = chain.vol_surface();
+            all_boxes.append(&mut get_boxes(chain, &surface));
+            all_conversions.append(&mut get_conversions(chain, &surface));
+            let (mut verticals, mut condoors) = get_vertical_spreads(chain, &surface);
+            all_vertical_spreads.append(&mut verticals);
+            all_condoors.append(&mut condoors);
+            all_straddles.append(&mut get_straddles(
+                chain,
+                &surface,
+                sizer
+                    .prices
+                    .realized_volatility(&chain.symbol, sizer.config.realized_vol_days),
+            ));
+        }
+        create_csv_file(&all_boxes, "ALL-Boxes");
+        create_csv_file(&all_conversions, "ALL-Conversions");
+        create_csv_file(&all_vertical_spreads, "ALL-VerticalSpreads");
+        create_csv_file(&all_condoors, "ALL-IronCondoors");
+        create_csv_file(&all_straddles, "ALL-Straddles");
         let mut all_spreads: Vec<OptionSpread> = Vec::new();
         for mut chain in all_option_chains {
             all_spreads.append(&mut chain.spreads);
         }
         all_spreads.sort_unstable_by_key(|spread| -spread.net_value.abs() as i64);
-        create_csv_file(
-            &all_spreads[0..std::cmp::min(10000, all_spreads.len() - 1)],
-            "ALL-Trades",
+        let top_spreads = &all_spreads[..all_spreads.len().min(10000)];
+        create_csv_file(top_spreads, "ALL-Trades");
+        create_json_file(
+            &format!("ALL-Trades-{}", chrono::Local::now().format("%F-%H%M")),
+            &top_spreads
+                .iter()
+                .map(|spread| spread.nested())
+                .collect::<Vec<_>>(),
         );
         create_csv_file(&short_fees.data, "ALL-ShortFee");
     } else {
@@ -77,13 +458,10 @@ async fn main() {
 pub async fn get_chain_for_one_symbol(
     symbol: &str,
     short_data: Option<&ShortStockData>,
+    bias_model: &BiasModel,
 ) -> Option<OptionChain> {
     if let Ok(token) = get_auth().await {
-        if let Some(option_chain) = OptionChain::get(symbol, &token, short_data).await {
-            Some(option_chain)
-        } else {
-            None
-        }
+        OptionChain::get(symbol, &token, short_data, bias_model).await
     } else {
         None
     }
@@ -0,0 +1,366 @@
+use crate::others::round_to_decimals;
+use crate::pricing::{black_scholes, years_from_dte};
+use crate::single_options::OptionType;
+use crate::spreads::{OptionSpread, SpreadLeg, StockLeg};
+use crate::trades::OrderAction;
+use crate::vol_surface::VolSurface;
+use crate::{
+    MARGIN_BROAD_INDEX_ETFS, MARGIN_EQUITY_RANGE_PERCENT, MARGIN_INDEX_RANGE_PERCENT,
+    MARGIN_NAKED_MIN_PERCENT, MARGIN_NAKED_PERCENT, MARGIN_PM_MIN_PER_CONTRACT,
+    MARGIN_STOCK_PERCENT,
+};
+use chrono::NaiveDate;
+use itertools::Itertools;
+
+const STRESS_POINTS: usize = 10;
+
+/// Option contracts still to be margined, by strike and expiry.
+struct Contracts {
+    kind: OptionType,
+    strike: f64,
+    expiry: String,
+    price: f64,
+    count: i64,
+}
+
+/// Reg-T strategy based requirement, as the buying power the position takes: longs are paid
+/// in full, each short is paired with the long of the same type (expiring no earlier) that
+/// needs the least margin, what is left is covered by the stock leg or margined naked, and
+/// stock takes `MARGIN_STOCK_PERCENT`. Premium received offsets the total. Call and put credit
+/// spreads in the same expiry (iron condors, short boxes) only carry the wider side. Returns
+/// the requirement and the rules used, pipe delimited.
+pub fn reg_t_margin(
+    legs: &[SpreadLeg],
+    stock_leg: Option<&StockLeg>,
+    underlying: f64,
+) -> (f64, String) {
+    let contracts = |side: OrderAction| {
+        legs.iter()
+            .filter(|leg| leg.side == side && leg.size > 0)
+            .map(|leg| Contracts {
+                kind: leg.option_type,
+                strike: leg.strike,
+                expiry: leg.expiry.clone(),
+                price: leg.price.unwrap_or(0.0),
+                count: leg.size,
+            })
+            .collect_vec()
+    };
+    let mut longs = contracts(OrderAction::Bought);
+    let mut shorts = contracts(OrderAction::Sold);
+    let premium = |side: &[Contracts]| -> f64 {
+        side.iter()
+            .map(|leg| leg.price * 100.0 * leg.count as f64)
+            .sum()
+    };
+    let mut requirement = premium(&longs) - premium(&shorts);
+    let mut rules: Vec<&str> = Vec::new();
+    if !longs.is_empty() {
+        rules.push("Long")
+    }
+    // (expiry, kind) -> summed spread widths
+    let mut spread_sides: Vec<(String, OptionType, f64)> = Vec::new();
+    for short in shorts.iter_mut() {
+        while short.count > 0 {
+            let width = |long: &Contracts| match short.kind {
+                OptionType::Call => (long.strike - short.strike).max(0.0),
+                OptionType::Put => (short.strike - long.strike).max(0.0),
+            };
+            let Some(long) = longs
+                .iter_mut()
+                .filter(|long| {
+                    long.count > 0 && long.kind == short.kind && long.expiry >= short.expiry
+                })
+                .min_by(|a, b| width(a).partial_cmp(&width(b)).unwrap())
+            else {
+                break;
+            };
+            let paired = long.count.min(short.count);
+            let amount = width(long) * 100.0 * paired as f64;
+            long.count -= paired;
+            short.count -= paired;
+            match spread_sides
+                .iter_mut()
+                .find(|side| side.0 == short.expiry && side.1 == short.kind)
+            {
+                Some(side) => side.2 += amount,
+                None => spread_sides.push((short.expiry.clone(), short.kind, amount)),
+            }
+            rules.push("Spread");
+        }
+    }
+    for expiry in spread_sides.iter().map(|side| &side.0).unique() {
+        let widths = spread_sides
+            .iter()
+            .filter(|side| side.0 == *expiry)
+            .map(|side| side.2)
+            .collect_vec();
+        requirement += if widths.len() == 2 && widths.iter().all(|width| *width > 0.0) {
+            widths.iter().cloned().fold(0.0, f64::max)
+        } else {
+            widths.iter().sum()
+        };
+    }
+    let shares = stock_leg.map_or(0.0, |stock| stock.signed_shares());
+    let stock_price = stock_leg
+        .map(|stock| stock.price)
+        .filter(|price| *price > 0.0)
+        .unwrap_or(underlying);
+    let mut covering = shares.abs() / 100.0;
+    for short in shorts.iter().filter(|short| short.count > 0) {
+        let covered = match short.kind {
+            OptionType::Call if shares > 0.0 => (short.count as f64).min(covering),
+            OptionType::Put if shares < 0.0 => (short.count as f64).min(covering),
+            _ => 0.0,
+        };
+        covering -= covered;
+        if covered > 0.0 {
+            rules.push("Covered")
+        }
+        let naked = short.count as f64 - covered;
+        if naked > 0.0 {
+            requirement += naked_margin(short.kind, short.strike, short.price, underlying) * naked;
+            rules.push("Naked")
+        }
+    }
+    if shares != 0.0 {
+        requirement += shares.abs() * stock_price * MARGIN_STOCK_PERCENT / 100.0;
+        rules.push("Stock")
+    }
+    (
+        round_to_decimals(requirement.max(0.0), 2),
+        rules.into_iter().unique().join("|"),
+    )
+}
+
+/// Per contract: the greater of `MARGIN_NAKED_PERCENT` of the underlying less the out of the
+/// money amount, or `MARGIN_NAKED_MIN_PERCENT` of the underlying (calls) or strike (puts),
+/// plus the premium.
+fn naked_margin(kind: OptionType, strike: f64, price: f64, underlying: f64) -> f64 {
+    let (out_of_money, floor_base) = match kind {
+        OptionType::Call => ((strike - underlying).max(0.0), underlying),
+        OptionType::Put => ((underlying - strike).max(0.0), strike),
+    };
+    let percent = (MARGIN_NAKED_PERCENT / 100.0 * underlying - out_of_money)
+        .max(MARGIN_NAKED_MIN_PERCENT / 100.0 * floor_base);
+    (percent + price) * 100.0
+}
+
+/// Simplified TIMS: the worst loss when the underlying is moved across `STRESS_POINTS` equal
+/// steps of the range (`MARGIN_INDEX_RANGE_PERCENT` down and up for indexes and broad index
+/// ETFs, `MARGIN_EQUITY_RANGE_PERCENT` either way otherwise), repricing every leg at its
+/// current vol with no time passing. At least `MARGIN_PM_MIN_PER_CONTRACT` per contract.
+pub fn portfolio_margin(
+    symbol: &str,
+    legs: &[SpreadLeg],
+    stock_leg: Option<&StockLeg>,
+    surface: &VolSurface,
+    underlying: f64,
+) -> f64 {
+    if underlying <= 0.0 {
+        return 0.0;
+    }
+    let (down, up) = if symbol.starts_with('^') || MARGIN_BROAD_INDEX_ETFS.contains(&symbol) {
+        MARGIN_INDEX_RANGE_PERCENT
+    } else {
+        (MARGIN_EQUITY_RANGE_PERCENT, MARGIN_EQUITY_RANGE_PERCENT)
+    };
+    let as_of = surface.as_of();
+    let priced = legs
+        .iter()
+        .map(|leg| {
+            let dte = NaiveDate::parse_from_str(&leg.expiry, "%F")
+                .map_or(0, |date| (date - as_of).num_days());
+            let years = if dte > 0 { years_from_dte(dte) } else { 0.0 };
+            let iv = surface.iv(&leg.expiry, leg.strike).unwrap_or(leg.iv);
+            let contracts = match leg.side {
+                OrderAction::Bought => 100.0 * leg.size as f64,
+                OrderAction::Sold => -100.0 * leg.size as f64,
+                OrderAction::Unknown => 0.0,
+            };
+            (leg, years, iv, contracts)
+        })
+        .collect_vec();
+    let value = |price: f64| -> f64 {
+        priced
+            .iter()
+            .map(|(leg, years, iv, contracts)| {
+                contracts * black_scholes(leg.option_type, price, leg.strike, *years, *iv).price
+            })
+            .sum::<f64>()
+            + stock_leg.map_or(0.0, |stock| stock.signed_shares() * price)
+    };
+    let current = value(underlying);
+    let worst_loss = (0..=STRESS_POINTS)
+        .map(|step| -down + (down + up) * step as f64 / STRESS_POINTS as f64)
+        .map(|percent| current - value(underlying * (1.0 + percent / 100.0)))
+        .fold(0.0, f64::max);
+    let contracts: i64 = legs.iter().map(|leg| leg.size).sum();
+    round_to_decimals(
+        worst_loss.max(MARGIN_PM_MIN_PER_CONTRACT * contracts as f64),
+        2,
+    )
+}
+
+/// Max profit as a percent of the requirement, 0 when nothing is required or the profit is
+/// unbounded.
+fn return_on(max_profit: f64, requirement: f64) -> f64 {
+    if requirement > 0.0 && max_profit.is_finite() {
+        round_to_decimals(100.0 * max_profit / requirement, 2)
+    } else {
+        0.0
+    }
+}
+
+/// Both requirements for one result and its max profit as a return on each.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MarginSummary {
+    pub reg_t_margin: f64,
+    pub margin_rules: String,
+    pub portfolio_margin: f64,
+    pub return_on_margin: f64,
+    pub return_on_portfolio_margin: f64,
+}
+impl MarginSummary {
+    pub fn of(
+        symbol: &str,
+        legs: &[SpreadLeg],
+        stock_leg: Option<&StockLeg>,
+        surface: &VolSurface,
+        underlying: f64,
+        max_profit: f64,
+    ) -> Self {
+        let (reg_t_margin, margin_rules) = reg_t_margin(legs, stock_leg, underlying);
+        let portfolio_margin = portfolio_margin(symbol, legs, stock_leg, surface, underlying);
+        Self {
+            return_on_margin: return_on(max_profit, reg_t_margin),
+            return_on_portfolio_margin: return_on(max_profit, portfolio_margin),
+            reg_t_margin,
+            margin_rules,
+            portfolio_margin,
+        }
+    }
+}
+
+impl OptionSpread {
+    pub fn with_margin(self, surface: &VolSurface, underlying: f64) -> Self {
+        let margin = MarginSummary::of(
+            &self.symbol,
+            &self.legs,
+            self.stock_leg.as_ref(),
+            surface,
+            underlying,
+            self.max_profit,
+        );
+        Self {
+            reg_t_margin: margin.reg_t_margin,
+            margin_rules: margin.margin_rules,
+            portfolio_margin: margin.portfolio_margin,
+            return_on_margin: margin.return_on_margin,
+            return_on_portfolio_margin: margin.return_on_portfolio_margin,
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trades::{ConditionID, FillStyle, TransactionType};
+
+    fn leg(kind: OptionType, strike: f64, side: OrderAction, size: i64, price: f64) -> SpreadLeg {
+        SpreadLeg {
+            option_symbol: format!("{:?}{}", kind, strike),
+            strike,
+            expiry: "2030-01-18".to_string(),
+            option_type: kind,
+            side,
+            size,
+            price: Some(price),
+            iv: 0.21,
+            delta: 0.0,
+            condition_id: ConditionID::Regular,
+            transaction_estimate: TransactionType::Uncalculated,
+            edge_vs_mid_vol_points: 0.0,
+            spread_aggressiveness: 0.0,
+            fill_style: FillStyle::Unknown,
+        }
+    }
+
+    #[test]
+    fn reg_t_by_strategy() {
+        use OptionType::{Call, Put};
+        use OrderAction::{Bought, Sold};
+        let debit = [
+            leg(Call, 470.0, Bought, 500, 12.4),
+            leg(Call, 480.0, Sold, 500, 7.8),
+        ];
+        assert_eq!(
+            reg_t_margin(&debit, None, 464.905),
+            (230000.0, "Long|Spread".to_string())
+        );
+        let credit = [
+            leg(Call, 470.0, Sold, 500, 12.4),
+            leg(Call, 480.0, Bought, 500, 7.8),
+        ];
+        assert_eq!(reg_t_margin(&credit, None, 464.905).0, 270000.0);
+        let naked = [leg(Put, 455.0, Sold, 40, 9.15)];
+        assert_eq!(
+            reg_t_margin(&naked, None, 464.905),
+            (
+                round_to_decimals((92.981 - 9.905) * 100.0 * 40.0, 2),
+                "Naked".to_string()
+            )
+        );
+        let short_box = [
+            leg(Call, 460.0, Sold, 1, 9.0),
+            leg(Call, 470.0, Bought, 1, 4.0),
+            leg(Put, 470.0, Sold, 1, 9.0),
+            leg(Put, 460.0, Bought, 1, 5.0),
+        ];
+        assert_eq!(reg_t_margin(&short_box, None, 464.905).0, 100.0);
+        let covered = [leg(Call, 480.0, Sold, 2, 7.8)];
+        let stock = StockLeg {
+            side: Bought,
+            shares: 200,
+            price: 465.0,
+            inferred: false,
+        };
+        assert_eq!(
+            reg_t_margin(&covered, Some(&stock), 464.905),
+            (200.0 * 465.0 * 0.5 - 1560.0, "Covered|Stock".to_string())
+        );
+    }
+
+    #[test]
+    fn portfolio_margin_is_worst_stress_loss() {
+        use OptionType::Call;
+        use OrderAction::{Bought, Sold};
+        let surface = VolSurface {
+            as_of: Some(NaiveDate::from_ymd(2029, 12, 18)),
+            ..VolSurface::default()
+        };
+        let debit = [
+            leg(Call, 470.0, Bought, 1, 12.4),
+            leg(Call, 480.0, Sold, 1, 7.8),
+        ];
+        let spread = portfolio_margin("AAPL", &debit, None, &surface, 464.905);
+        assert!(spread > 0.0 && spread < reg_t_margin(&debit, None, 464.905).0);
+        let naked = [leg(Call, 480.0, Sold, 1, 7.8)];
+        let equity = portfolio_margin("AAPL", &naked, None, &surface, 464.905);
+        let index = portfolio_margin("SPY", &naked, None, &surface, 464.905);
+        assert!(index < equity && equity < reg_t_margin(&naked, None, 464.905).0);
+        let far_otm = [leg(Call, 900.0, Sold, 2, 0.01)];
+        assert_eq!(
+            portfolio_margin("SPY", &far_otm, None, &surface, 464.905),
+            2.0 * MARGIN_PM_MIN_PER_CONTRACT
+        );
+    }
+
+    #[test]
+    fn return_on_margin_ignores_unbounded_profit() {
+        assert_eq!(return_on(540.0, 460.0), 117.39);
+        assert_eq!(return_on(f64::INFINITY, 460.0), 0.0);
+        assert_eq!(return_on(540.0, 0.0), 0.0);
+    }
+}
@@ -1,8 +1,12 @@
+use crate::groups::{aggregate_groups, GroupConfig, GroupKind};
+use crate::rolls::Roll;
+use crate::scoring::FactorContribution;
 use crate::single_options::OptionData;
 use crate::spreads::OptionSpread;
 use crate::strategies::remove_decimals;
 use crate::trades::Expectation;
 use crate::trades::OptionTrade;
+use chrono::NaiveDate;
 use itertools::Itertools;
 use serde::Deserialize;
 use serde::Serialize;
@@ -19,6 +23,10 @@ pub struct OptionChain {
     #[serde(skip_serializing)]
     pub options: Vec<OptionData>,
     pub underlying_mid: f64,
+    /// The stock's own quote, which conversions trade against. The mid falls in for either
+    /// side when there is none.
+    pub underlying_bid: f64,
+    pub underlying_ask: f64,
     pub data_timestamp: String,
     pub ex_div_date: String,
     #[serde(skip_serializing)]
@@ -40,12 +48,32 @@ pub struct OptionChain {
     pub shares_available: String,
     pub dealer_delta: f64,
     pub naive_dealer_delta: f64,
+    pub dealer_gamma: f64,
     pub put_call_oi_ratio: f64,
     pub put_call_volume_ratio: f64,
     pub insider_net_transaction: f64,
+    pub market_cap: f64,
+    pub beta: f64,
+    /// Implied volatility at the money for the expiry closest to `SIZING_IV_DTE` days.
+    pub atm_iv: f64,
+    pub option_volume: i64,
+    pub open_interest: i64,
+    /// `large_trader_net_value` over the symbol's size, and where that sits in the scanned
+    /// universe. Filled in by `normalize_universe`.
+    pub flow_per_size: f64,
+    pub flow_percentile: f64,
+    pub flow_z_score: f64,
     pub bias: i64,
+    pub bias_score: f64,
+    pub bias_breakdown: String,
+    #[serde(skip_serializing)]
+    pub bias_contributions: Vec<FactorContribution>,
     #[serde(skip_serializing)]
     pub spreads: Vec<OptionSpread>,
+    #[serde(skip_serializing)]
+    pub trades: Vec<OptionTrade>,
+    #[serde(skip_serializing)]
+    pub rolls: Vec<Roll>,
     pub short_interest_percent: f64,
     pub large_trader_delta: f64,
     pub large_trader_opening_delta: f64,
@@ -54,7 +82,21 @@ pub struct OptionChain {
     pub large_trader_net_value: f64,
     pub large_trader_opening_net_value: f64,
     pub large_trader_opening_absolute_value: f64,
+    pub large_trader_roll_count: i64,
+    pub large_trader_roll_net_value: f64,
+    pub large_trader_roll_delta_shift: f64,
     pub shares_to_trade: i64,
+    pub delta_adjusted_notional: f64,
+    pub gamma_weighted_notional: f64,
+    pub vega_weighted_notional: f64,
+    pub bullish_premium: f64,
+    pub bearish_premium: f64,
+    pub bullish_bought_premium: f64,
+    pub bullish_sold_premium: f64,
+    pub bearish_bought_premium: f64,
+    pub bearish_sold_premium: f64,
+    pub bullish_opening_premium: f64,
+    pub bearish_opening_premium: f64,
 }
 impl OptionChain {
     pub fn to_signal(&self, quantity_1: f64, quantity_2: f64) -> Signal {
@@ -68,6 +110,10 @@ impl OptionChain {
             quantity_1: remove_decimals(quantity_1 / self.underlying_mid),
             quantity_2: remove_decimals(quantity_2 / self.underlying_mid),
             large_trader_net_value: self.large_trader_net_value,
+            delta_adjusted_notional: self.delta_adjusted_notional,
+            flow_percentile: self.flow_percentile,
+            flow_z_score: self.flow_z_score,
+            ..Signal::default()
         }
     }
 }
@@ -209,6 +255,19 @@ impl Default for DividendInformation {
         Self::new()
     }
 }
+/// Everything requested for one symbol before any of it is combined into an `OptionChain`.
+#[derive(Debug, Clone)]
+pub struct ChainSnapshot {
+    pub data: ServerResponse,
+    pub trades: Vec<OptionTrade>,
+    pub dividend_info: Option<DividendInformation>,
+    pub insiders: f64,
+    pub short_interest_percent: f64,
+    pub market_cap: f64,
+    pub beta: f64,
+    /// Trading date the quotes and trades are from.
+    pub as_of: NaiveDate,
+}
 #[derive(Debug)]
 pub struct OptionsLock {
     pub cboe_token: String,
@@ -287,58 +346,46 @@ impl InsiderTransaction {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Signal {
     pub symbol: String,
     pub side: SignalType,
     pub quantity_1: i64,
     pub quantity_2: i64,
     pub large_trader_net_value: f64,
+    pub delta_adjusted_notional: f64,
+    pub flow_percentile: f64,
+    pub flow_z_score: f64,
+    /// Filled in by `PositionSizer::size`.
+    pub price: f64,
+    pub current_quantity: i64,
+    pub target_quantity: i64,
+    pub order_quantity: i64,
+    pub order_value: f64,
+    pub sizing_limit: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
 pub enum SignalType {
+    #[default]
     Buy,
     Sell,
 }
 
-pub fn get_signals(chains: &Vec<OptionChain>, quantity_1: f64, quantity_2: f64) -> Vec<Signal> {
-    let qqq_price = chains
-        .iter()
-        .find(|chain| chain.symbol == "QQQ")
-        .unwrap()
-        .underlying_mid;
-    let spy_price = chains
-        .iter()
-        .find(|chain| chain.symbol == "SPY")
-        .unwrap()
-        .underlying_mid;
+/// One signal per index group with a member in `chains`, from the summed flow of its members
+/// and on its trade symbol (or a scanned member when that is missing), plus the single names
+/// with strong enough flow and bias. Returns the four highest and four lowest ranked.
+pub fn get_signals(
+    chains: &[OptionChain],
+    quantity_1: f64,
+    quantity_2: f64,
+    groups: &GroupConfig,
+) -> Vec<Signal> {
     let mut signals: Vec<Signal> = Vec::new();
-    let mut large = chains
-        .iter()
-        .filter(|chain| chain.large_trader_net_value.abs() > 0.0)
-        .collect_vec();
-    large.sort_unstable_by_key(|chain| chain.large_trader_net_value.abs() as i64);
-    let sp_500 = chains
-        .iter()
-        .filter(|chain| chain.symbol == "SPY" || chain.symbol == "^SPX")
-        .collect_vec();
-    let nasdaq = chains
-        .iter()
-        .filter(|chain| chain.symbol == "QQQ" || chain.symbol == "^NDX")
-        .collect_vec();
     let all_others = chains
         .iter()
-        .filter(|chain| !matches!(chain.symbol.as_ref(), "SPY" | "QQQ" | "^SPX" | "^NDX"))
+        .filter(|chain| !groups.in_index_group(&chain.symbol))
         .collect_vec();
-    let mut sp_large_trader_net = 0.0;
-    for chain in sp_500 {
-        sp_large_trader_net += chain.large_trader_net_value;
-    }
-    let mut nq_large_trader_net = 0.0;
-    for chain in nasdaq {
-        nq_large_trader_net += chain.large_trader_net_value;
-    }
     for chain in &all_others {
         if chain.large_trader_net_value > 0.0 && chain.bias > 2 {
             signals.push(chain.to_signal(quantity_1, quantity_2))
@@ -346,35 +393,121 @@ pub fn get_signals(chains: &Vec<OptionChain>, quantity_1: f64, quantity_2: f64)
             signals.push(chain.to_signal(quantity_1, quantity_2))
         }
     }
-    let spy_signal_type = if sp_large_trader_net > 0.0 {
-        SignalType::Buy
-    } else {
-        SignalType::Sell
-    };
-    let spy_signal = Signal {
-        symbol: "SPY".to_string(),
-        side: spy_signal_type,
-        quantity_1: remove_decimals(quantity_1 / spy_price),
-        quantity_2: remove_decimals(quantity_2 / spy_price),
-        large_trader_net_value: sp_large_trader_net,
-    };
-    let qqq_signal_type = if nq_large_trader_net > 0.0 {
-        SignalType::Buy
-    } else {
-        SignalType::Sell
-    };
-    let qqq_signal = Signal {
-        symbol: "QQQ".to_string(),
-        side: qqq_signal_type,
-        quantity_1: remove_decimals(quantity_1 / qqq_price),
-        quantity_2: remove_decimals(quantity_2 / qqq_price),
-        large_trader_net_value: nq_large_trader_net,
-    };
-    signals.push(spy_signal);
-    signals.push(qqq_signal);
-    signals.sort_unstable_by_key(|signal| -signal.large_trader_net_value as i64);
+    for group in aggregate_groups(chains, groups)
+        .into_iter()
+        .filter(|group| group.kind == GroupKind::Index)
+    {
+        // without its trade symbol the group is traded through its first scanned member
+        let (symbol, price, flow_percentile, flow_z_score) = if group.trade_price > 0.0 {
+            (
+                group.trade_symbol,
+                group.trade_price,
+                group.flow_percentile,
+                group.flow_z_score,
+            )
+        } else {
+            let Some(member) = group.members_found.split('|').find_map(|member| {
+                chains
+                    .iter()
+                    .find(|chain| chain.symbol == member && chain.underlying_mid > 0.0)
+            }) else {
+                println!(
+                    "No signal for {}: none of its members has a price",
+                    group.name
+                );
+                continue;
+            };
+            (
+                member.symbol.clone(),
+                member.underlying_mid,
+                member.flow_percentile,
+                member.flow_z_score,
+            )
+        };
+        signals.push(Signal {
+            symbol,
+            side: if group.large_trader_net_value > 0.0 {
+                SignalType::Buy
+            } else {
+                SignalType::Sell
+            },
+            quantity_1: remove_decimals(quantity_1 / price),
+            quantity_2: remove_decimals(quantity_2 / price),
+            large_trader_net_value: group.large_trader_net_value,
+            delta_adjusted_notional: group.delta_adjusted_notional,
+            flow_percentile,
+            flow_z_score,
+            ..Signal::default()
+        })
+    }
+    // size adjusted flow ranks first, raw dollars break ties (and decide everything when the
+    // universe hasn't been normalized)
+    signals.sort_unstable_by(|a, b| {
+        (b.flow_z_score, b.large_trader_net_value)
+            .partial_cmp(&(a.flow_z_score, a.large_trader_net_value))
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
     let mut final_vec = signals.clone().into_iter().take(4).collect_vec();
-    signals.sort_unstable_by_key(|signal| signal.large_trader_net_value as i64);
+    signals.reverse();
     final_vec.append(&mut signals.into_iter().take(4).collect_vec());
     final_vec
 }
+
+/// The day the fixture trades printed, matching their `SPY-1-14-2022` symbol date.
+#[cfg(test)]
+pub(crate) fn fixture_date() -> NaiveDate {
+    NaiveDate::from_ymd(2022, 1, 14)
+}
+
+/// The trades in one of the `tests/fixtures` files, filled in as printed on `fixture_date`.
+#[cfg(test)]
+pub(crate) fn fixture_trades(json: &str) -> Vec<OptionTrade> {
+    serde_json::from_str::<Vec<OptionTrade>>(json)
+        .unwrap()
+        .into_iter()
+        .map(|trade| trade.get_values("SPY-1-14-2022", fixture_date()))
+        .collect()
+}
+
+/// The SPY quotes and 470/480 call vertical in `tests/fixtures`, as traded on `as_of`.
+#[cfg(test)]
+pub(crate) fn fixture_snapshot(as_of: NaiveDate) -> ChainSnapshot {
+    ChainSnapshot {
+        data: serde_json::from_str::<ServerResponse>(include_str!(
+            "../tests/fixtures/chain_snapshot.json"
+        ))
+        .unwrap(),
+        trades: serde_json::from_str::<Vec<OptionTrade>>(include_str!(
+            "../tests/fixtures/vertical.json"
+        ))
+        .unwrap(),
+        dividend_info: None,
+        insiders: 0.0,
+        short_interest_percent: 0.0,
+        market_cap: 0.0,
+        beta: 0.0,
+        as_of,
+    }
+}
+
+/// The fixture chain as of `as_of`, under `symbol`.
+#[cfg(test)]
+pub(crate) fn fixture_chain_as_of(symbol: &str, as_of: NaiveDate) -> OptionChain {
+    let chain = OptionChain::from_snapshot(
+        symbol,
+        fixture_snapshot(as_of),
+        None,
+        &crate::scoring::BiasModel::default(),
+    )
+    .unwrap();
+    OptionChain {
+        symbol: symbol.to_string(),
+        ..chain
+    }
+}
+
+/// The fixture chain as of `fixture_date`, under `symbol`.
+#[cfg(test)]
+pub(crate) fn fixture_chain(symbol: &str) -> OptionChain {
+    fixture_chain_as_of(symbol, fixture_date())
+}
@@ -0,0 +1,859 @@
+use crate::models::{OptionChain, Signal};
+use crate::others::{connect_with_timeout, load_json, round_to_decimals};
+use crate::recommend::Recommendation;
+use crate::single_options::OptionType;
+use crate::spreads::{OptionSpread, SpreadLeg, StockLeg};
+use crate::trades::OrderAction;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::io::{BufReader, Read, Write};
+
+const SOH: char = '\u{1}';
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum PriceSource {
+    Mid,
+    /// Pays the ask on bought legs and sells at the bid on sold ones.
+    Natural,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct OrderConfig {
+    pub price_source: PriceSource,
+    pub account: String,
+    pub time_in_force: String,
+    pub sender_comp_id: String,
+    pub target_comp_id: String,
+    /// `host:port` of a FIX acceptor to send the tickets to. Files are written either way.
+    pub acceptor: Option<String>,
+    /// How many of each signal's best recommendations become tickets.
+    pub recommendations_per_signal: usize,
+    /// `sequence_numbers` of scanned spreads to copy, as shown in the trades report.
+    pub selected_spreads: Vec<String>,
+    /// Spread units to order for each selected spread.
+    pub spread_quantity: i64,
+}
+impl Default for OrderConfig {
+    fn default() -> Self {
+        Self {
+            price_source: PriceSource::Mid,
+            account: String::new(),
+            time_in_force: "DAY".to_string(),
+            sender_comp_id: "OPTIONSSCAN".to_string(),
+            target_comp_id: "IB".to_string(),
+            acceptor: None,
+            recommendations_per_signal: 1,
+            selected_spreads: Vec::new(),
+            spread_quantity: 1,
+        }
+    }
+}
+impl OrderConfig {
+    /// The defaults when there is no config file. One that doesn't parse is an error, since
+    /// its settings go straight into orders.
+    pub fn load(filename: &str) -> Result<Self, String> {
+        Ok(load_json(filename)?.unwrap_or_default())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum SecType {
+    Stock,
+    Option,
+    Combo,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum OrderSide {
+    Buy,
+    Sell,
+    /// A stock sale of shares that aren't held, opening or adding to a short.
+    SellShort,
+}
+impl OrderSide {
+    fn from_action(action: OrderAction) -> Self {
+        match action {
+            OrderAction::Sold => OrderSide::Sell,
+            _ => OrderSide::Buy,
+        }
+    }
+    fn sign(&self) -> f64 {
+        match self {
+            OrderSide::Buy => 1.0,
+            OrderSide::Sell | OrderSide::SellShort => -1.0,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TicketLeg {
+    pub sec_type: SecType,
+    pub side: OrderSide,
+    /// Contracts (or shares for a stock leg) per unit of the ticket's quantity.
+    pub ratio: i64,
+    pub expiry: Option<String>,
+    pub strike: Option<f64>,
+    pub right: Option<OptionType>,
+    pub price: f64,
+}
+
+/// One order. Combos are always bought: a negative limit price is a credit.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct OrderTicket {
+    pub id: String,
+    pub source: String,
+    pub symbol: String,
+    pub sec_type: SecType,
+    pub side: OrderSide,
+    pub quantity: i64,
+    pub limit_price: f64,
+    pub description: String,
+    #[serde(skip_serializing, default)]
+    pub legs: Vec<TicketLeg>,
+}
+impl OrderTicket {
+    /// A share order for part of the signal's sized `order_quantity`, limited at the
+    /// underlying mid, the only stock quote the chain keeps.
+    pub fn from_signal(signal: &Signal, side: OrderSide, quantity: i64, id: String) -> Self {
+        Self {
+            id,
+            source: "Signal".to_string(),
+            symbol: signal.symbol.clone(),
+            sec_type: SecType::Stock,
+            side,
+            quantity,
+            limit_price: round_to_decimals(signal.price, 2),
+            description: format!("{:?} {} {}", side, quantity, signal.symbol),
+            legs: vec![TicketLeg {
+                sec_type: SecType::Stock,
+                side,
+                ratio: 1,
+                expiry: None,
+                strike: None,
+                right: None,
+                price: signal.price,
+            }],
+        }
+    }
+    pub fn from_recommendation(
+        recommendation: &Recommendation,
+        chain: &OptionChain,
+        price_source: PriceSource,
+        id: String,
+    ) -> Option<Self> {
+        let ticket = Self::from_legs(
+            id,
+            chain,
+            &recommendation.legs,
+            None,
+            recommendation.contracts,
+            price_source,
+        )?;
+        Some(Self {
+            source: "Recommendation".to_string(),
+            description: format!(
+                "{:?} {}",
+                recommendation.strategy, recommendation.legs_summary
+            ),
+            ..ticket
+        })
+    }
+    pub fn from_spread(
+        spread: &OptionSpread,
+        chain: &OptionChain,
+        quantity: i64,
+        price_source: PriceSource,
+        id: String,
+    ) -> Option<Self> {
+        let ticket = Self::from_legs(
+            id,
+            chain,
+            &spread.legs,
+            spread.stock_leg.as_ref(),
+            quantity,
+            price_source,
+        )?;
+        Some(Self {
+            source: "Spread".to_string(),
+            description: format!("{:?} {}", spread.spread_name, spread.summary),
+            ..ticket
+        })
+    }
+    /// Reduces leg sizes to their smallest whole ratio and prices each leg from the chain's
+    /// current quote, falling back to the leg's own price. A stock leg's price counts per 100
+    /// shares so the limit stays per spread unit.
+    fn from_legs(
+        id: String,
+        chain: &OptionChain,
+        legs: &[SpreadLeg],
+        stock_leg: Option<&StockLeg>,
+        quantity: i64,
+        price_source: PriceSource,
+    ) -> Option<Self> {
+        if legs.is_empty() || quantity <= 0 {
+            return None;
+        }
+        let unit = legs.iter().map(|leg| leg.size).fold(0, gcd).max(1);
+        let mut ticket_legs = legs
+            .iter()
+            .map(|leg| {
+                let quote = chain
+                    .options
+                    .iter()
+                    .find(|option| option.symbol == leg.option_symbol);
+                let quoted = quote.and_then(|option| match (price_source, leg.side) {
+                    (PriceSource::Natural, OrderAction::Sold) => option.bid_price,
+                    (PriceSource::Natural, _) => option.ask_price,
+                    (PriceSource::Mid, _) => Some(0.5 * (option.bid_price? + option.ask_price?)),
+                });
+                TicketLeg {
+                    sec_type: SecType::Option,
+                    side: OrderSide::from_action(leg.side),
+                    ratio: leg.size / unit,
+                    expiry: Some(leg.expiry.clone()),
+                    strike: Some(leg.strike),
+                    right: Some(leg.option_type),
+                    price: quoted.or(leg.price).unwrap_or(0.0),
+                }
+            })
+            .collect_vec();
+        if let Some(stock) = stock_leg {
+            ticket_legs.push(TicketLeg {
+                sec_type: SecType::Stock,
+                side: OrderSide::from_action(stock.side),
+                ratio: stock.shares / unit,
+                expiry: None,
+                strike: None,
+                right: None,
+                price: chain.underlying_mid,
+            })
+        }
+        let single = ticket_legs.len() == 1;
+        let limit_price: f64 = ticket_legs
+            .iter()
+            .map(|leg| {
+                let per_unit = match leg.sec_type {
+                    SecType::Stock => leg.price / 100.0,
+                    _ => leg.price,
+                };
+                let sign = if single { 1.0 } else { leg.side.sign() };
+                sign * leg.ratio as f64 * per_unit
+            })
+            .sum();
+        Some(Self {
+            id,
+            source: String::new(),
+            symbol: chain.symbol.clone(),
+            sec_type: if single {
+                SecType::Option
+            } else {
+                SecType::Combo
+            },
+            side: if single {
+                ticket_legs[0].side
+            } else {
+                OrderSide::Buy
+            },
+            quantity: if single {
+                quantity * ticket_legs[0].ratio
+            } else {
+                quantity
+            },
+            limit_price: round_to_decimals(limit_price, 2),
+            description: String::new(),
+            legs: if single {
+                vec![TicketLeg {
+                    ratio: 1,
+                    ..ticket_legs[0].clone()
+                }]
+            } else {
+                ticket_legs
+            },
+        })
+    }
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Tickets for the sized signals, each signal's best recommendations, and the spreads picked
+/// in the config, numbered in that order.
+pub fn build_tickets(
+    signals: &[Signal],
+    recommendations: &[Recommendation],
+    chains: &[OptionChain],
+    config: &OrderConfig,
+) -> Vec<OrderTicket> {
+    let prefix = chrono::Local::now().format("%Y%m%d%H%M").to_string();
+    let mut tickets: Vec<OrderTicket> = Vec::new();
+    let next_id = |tickets: &Vec<OrderTicket>| format!("{}-{}", prefix, tickets.len() + 1);
+    for signal in signals.iter().unique_by(|signal| &signal.symbol) {
+        for (side, quantity) in stock_orders(signal) {
+            let ticket = OrderTicket::from_signal(signal, side, quantity, next_id(&tickets));
+            tickets.push(ticket)
+        }
+    }
+    let chain_for = |symbol: &str| chains.iter().find(|chain| chain.symbol == symbol);
+    for group in recommendations.chunk_by(|a, b| a.symbol == b.symbol) {
+        if let Some(chain) = chain_for(&group[0].symbol) {
+            for recommendation in group.iter().take(config.recommendations_per_signal) {
+                if let Some(ticket) = OrderTicket::from_recommendation(
+                    recommendation,
+                    chain,
+                    config.price_source,
+                    next_id(&tickets),
+                ) {
+                    tickets.push(ticket)
+                }
+            }
+        }
+    }
+    for chain in chains {
+        for spread in chain
+            .spreads
+            .iter()
+            .filter(|spread| config.selected_spreads.contains(&spread.sequence_numbers))
+        {
+            if let Some(ticket) = OrderTicket::from_spread(
+                spread,
+                chain,
+                config.spread_quantity,
+                config.price_source,
+                next_id(&tickets),
+            ) {
+                tickets.push(ticket)
+            }
+        }
+    }
+    tickets
+}
+
+/// Splits a signal's `order_quantity` at zero shares: selling down to flat is a `Sell` and
+/// anything past it a `SellShort`, since the two have to go to the broker as separate orders.
+fn stock_orders(signal: &Signal) -> Vec<(OrderSide, i64)> {
+    if signal.order_quantity == 0 || signal.price <= 0.0 {
+        return Vec::new();
+    }
+    if signal.order_quantity > 0 {
+        return vec![(OrderSide::Buy, signal.order_quantity)];
+    }
+    let held = signal.current_quantity.max(0).min(-signal.order_quantity);
+    let short = -signal.order_quantity - held;
+    [(OrderSide::Sell, held), (OrderSide::SellShort, short)]
+        .into_iter()
+        .filter(|(_, quantity)| *quantity > 0)
+        .collect()
+}
+
+/// A row of an Interactive Brokers basket file. Combos are a `BAG` row carrying the quantity
+/// and limit, followed by one row per leg with the same `OrderRef` and the leg ratio as
+/// quantity.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct BasketRow {
+    pub action: String,
+    pub quantity: i64,
+    pub symbol: String,
+    pub sec_type: String,
+    pub last_trading_day_or_contract_month: String,
+    pub strike: Option<f64>,
+    pub right: String,
+    pub exchange: String,
+    pub currency: String,
+    pub time_in_force: String,
+    pub order_type: String,
+    pub lmt_price: Option<f64>,
+    pub basket_tag: String,
+    pub account: String,
+    pub order_ref: String,
+}
+
+pub fn ib_basket(tickets: &[OrderTicket], config: &OrderConfig) -> Vec<BasketRow> {
+    let mut rows: Vec<BasketRow> = Vec::new();
+    for ticket in tickets {
+        let symbol = ticket.symbol.replace('^', "");
+        let row =
+            |side: OrderSide, quantity: i64, leg: Option<&TicketLeg>, sec_type: &str| BasketRow {
+                action: match side {
+                    OrderSide::Buy => "BUY".to_string(),
+                    OrderSide::Sell => "SELL".to_string(),
+                    OrderSide::SellShort => "SSHORT".to_string(),
+                },
+                quantity,
+                symbol: symbol.clone(),
+                sec_type: sec_type.to_string(),
+                last_trading_day_or_contract_month: leg
+                    .and_then(|leg| leg.expiry.as_ref())
+                    .map(|expiry| expiry.replace('-', ""))
+                    .unwrap_or_default(),
+                strike: leg.and_then(|leg| leg.strike),
+                right: match leg.and_then(|leg| leg.right) {
+                    Some(OptionType::Call) => "C".to_string(),
+                    Some(OptionType::Put) => "P".to_string(),
+                    None => String::new(),
+                },
+                exchange: "SMART".to_string(),
+                currency: "USD".to_string(),
+                time_in_force: config.time_in_force.clone(),
+                order_type: "LMT".to_string(),
+                lmt_price: Some(ticket.limit_price),
+                basket_tag: "options_scan".to_string(),
+                account: config.account.clone(),
+                order_ref: ticket.id.clone(),
+            };
+        match ticket.sec_type {
+            SecType::Stock => rows.push(row(ticket.side, ticket.quantity, None, "STK")),
+            SecType::Option => rows.push(row(
+                ticket.side,
+                ticket.quantity,
+                ticket.legs.first(),
+                "OPT",
+            )),
+            SecType::Combo => {
+                rows.push(row(ticket.side, ticket.quantity, None, "BAG"));
+                for leg in &ticket.legs {
+                    let sec_type = if leg.sec_type == SecType::Stock {
+                        "STK"
+                    } else {
+                        "OPT"
+                    };
+                    rows.push(BasketRow {
+                        order_type: String::new(),
+                        lmt_price: None,
+                        ..row(leg.side, leg.ratio, Some(leg), sec_type)
+                    })
+                }
+            }
+        }
+    }
+    rows
+}
+
+/// FIX 4.4 body fields for a ticket: NewOrderSingle (D) for stock and single options,
+/// NewOrderMultileg (AB) for combos.
+pub fn fix_order_fields(
+    ticket: &OrderTicket,
+    config: &OrderConfig,
+) -> (&'static str, Vec<(u32, String)>) {
+    let side = |side: OrderSide| match side {
+        OrderSide::Buy => "1".to_string(),
+        OrderSide::Sell => "2".to_string(),
+        OrderSide::SellShort => "5".to_string(),
+    };
+    let mut fields: Vec<(u32, String)> = vec![(11, ticket.id.clone())];
+    if !config.account.is_empty() {
+        fields.push((1, config.account.clone()))
+    }
+    fields.push((55, ticket.symbol.replace('^', "")));
+    let msg_type = match ticket.sec_type {
+        SecType::Stock => {
+            fields.push((167, "CS".to_string()));
+            "D"
+        }
+        SecType::Option => {
+            let leg = &ticket.legs[0];
+            fields.push((167, "OPT".to_string()));
+            if let Some(expiry) = &leg.expiry {
+                fields.push((200, expiry.replace('-', "")[..6].to_string()));
+                fields.push((541, expiry.replace('-', "")));
+            }
+            if let Some(right) = leg.right {
+                fields.push((
+                    201,
+                    if right == OptionType::Put { "0" } else { "1" }.to_string(),
+                ));
+            }
+            if let Some(strike) = leg.strike {
+                fields.push((202, strike.to_string()));
+            }
+            fields.push((77, "O".to_string()));
+            "D"
+        }
+        SecType::Combo => {
+            fields.push((167, "MLEG".to_string()));
+            fields.push((555, ticket.legs.len().to_string()));
+            for leg in &ticket.legs {
+                fields.push((600, ticket.symbol.replace('^', "")));
+                match leg.sec_type {
+                    SecType::Stock => fields.push((609, "CS".to_string())),
+                    _ => {
+                        fields.push((609, "OPT".to_string()));
+                        let code = if leg.right == Some(OptionType::Put) {
+                            "OPXXXX"
+                        } else {
+                            "OCXXXX"
+                        };
+                        fields.push((608, code.to_string()));
+                        if let Some(expiry) = &leg.expiry {
+                            fields.push((611, expiry.replace('-', "")));
+                        }
+                        if let Some(strike) = leg.strike {
+                            fields.push((612, strike.to_string()));
+                        }
+                    }
+                }
+                fields.push((623, leg.ratio.to_string()));
+                fields.push((624, side(leg.side)));
+                fields.push((564, "O".to_string()));
+            }
+            "AB"
+        }
+    };
+    fields.extend([
+        (54, side(ticket.side)),
+        (38, ticket.quantity.to_string()),
+        (40, "2".to_string()),
+        (44, ticket.limit_price.to_string()),
+        (
+            59,
+            if config.time_in_force == "GTC" {
+                "1"
+            } else {
+                "0"
+            }
+            .to_string(),
+        ),
+        (60, fix_timestamp()),
+    ]);
+    (msg_type, fields)
+}
+
+fn fix_timestamp() -> String {
+    chrono::Utc::now().format("%Y%m%d-%H:%M:%S%.3f").to_string()
+}
+
+/// Adds the standard header, body length and checksum.
+pub fn encode_fix(
+    msg_type: &str,
+    fields: &[(u32, String)],
+    config: &OrderConfig,
+    seq_no: u64,
+) -> String {
+    let mut body = format!(
+        "35={msg_type}{SOH}49={}{SOH}56={}{SOH}34={seq_no}{SOH}52={}{SOH}",
+        config.sender_comp_id,
+        config.target_comp_id,
+        fix_timestamp()
+    );
+    for (tag, value) in fields {
+        body.push_str(&format!("{tag}={value}{SOH}"));
+    }
+    let message = format!("8=FIX.4.4{SOH}9={}{SOH}{body}", body.len());
+    let checksum = message.bytes().map(|byte| byte as u32).sum::<u32>() % 256;
+    format!("{message}10={checksum:03}{SOH}")
+}
+
+pub fn fix_messages(tickets: &[OrderTicket], config: &OrderConfig) -> Vec<String> {
+    tickets
+        .iter()
+        .enumerate()
+        .map(|(n, ticket)| {
+            let (msg_type, fields) = fix_order_fields(ticket, config);
+            encode_fix(msg_type, &fields, config, n as u64 + 1)
+        })
+        .collect()
+}
+
+fn fix_field(message: &str, tag: u32) -> Option<&str> {
+    let prefix = format!("{}=", tag);
+    message
+        .split(SOH)
+        .find_map(|field| field.strip_prefix(prefix.as_str()))
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct FixAck {
+    pub id: String,
+    pub msg_type: String,
+    pub order_status: String,
+    pub text: String,
+}
+
+/// Logs on to the acceptor, resetting both sides' sequence numbers, sends each ticket and waits
+/// for its execution report (or reject), then logs out. The session runs on a blocking thread so it doesn't hold up the runtime.
+pub async fn send_fix_orders(
+    server: &str,
+    tickets: &[OrderTicket],
+    config: &OrderConfig,
+) -> std::io::Result<Vec<FixAck>> {
+    let (server, tickets, config) = (server.to_string(), tickets.to_vec(), config.clone());
+    tokio::task::spawn_blocking(move || fix_session(&server, &tickets, &config))
+        .await
+        .map_err(std::io::Error::other)?
+}
+
+fn fix_session(
+    server: &str,
+    tickets: &[OrderTicket],
+    config: &OrderConfig,
+) -> std::io::Result<Vec<FixAck>> {
+    let stream = connect_with_timeout(server)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    let mut seq_no = 1;
+    let logon = [
+        (98, "0".to_string()),
+        (108, "30".to_string()),
+        (141, "Y".to_string()),
+    ];
+    writer.write_all(encode_fix("A", &logon, config, seq_no).as_bytes())?;
+    let reply = read_fix_message(&mut reader)?;
+    if fix_field(&reply, 35) != Some("A") {
+        return Err(std::io::Error::other(format!(
+            "logon refused: {}",
+            reply.replace(SOH, "|")
+        )));
+    }
+    let mut acks: Vec<FixAck> = Vec::new();
+    for ticket in tickets {
+        seq_no += 1;
+        let (msg_type, fields) = fix_order_fields(ticket, config);
+        writer.write_all(encode_fix(msg_type, &fields, config, seq_no).as_bytes())?;
+        let order_seq_no = seq_no.to_string();
+        // heartbeats, test requests and reports on other orders aren't this ticket's answer
+        let reply = loop {
+            let reply = read_fix_message(&mut reader)?;
+            let answers = match fix_field(&reply, 35) {
+                Some("8") => fix_field(&reply, 11) == Some(&ticket.id),
+                Some("j") => fix_field(&reply, 379) == Some(&ticket.id),
+                Some("3") => fix_field(&reply, 45) == Some(&order_seq_no),
+                Some("1") => {
+                    seq_no += 1;
+                    let heartbeat = [(112, fix_field(&reply, 112).unwrap_or_default().to_string())];
+                    writer.write_all(encode_fix("0", &heartbeat, config, seq_no).as_bytes())?;
+                    false
+                }
+                _ => false,
+            };
+            if answers {
+                break reply;
+            }
+        };
+        acks.push(FixAck {
+            id: fix_field(&reply, 11).unwrap_or(&ticket.id).to_string(),
+            msg_type: fix_field(&reply, 35).unwrap_or_default().to_string(),
+            order_status: fix_field(&reply, 39).unwrap_or_default().to_string(),
+            text: fix_field(&reply, 58).unwrap_or_default().to_string(),
+        })
+    }
+    seq_no += 1;
+    writer.write_all(encode_fix("5", &[], config, seq_no).as_bytes())?;
+    read_fix_message(&mut reader)?;
+    Ok(acks)
+}
+
+/// Reads up to and including the checksum field.
+fn read_fix_message(reader: &mut impl Read) -> std::io::Result<String> {
+    let mut message = String::new();
+    let mut byte = [0u8; 1];
+    loop {
+        reader.read_exact(&mut byte)?;
+        message.push(byte[0] as char);
+        if byte[0] == SOH as u8 {
+            let field_start = message[..message.len() - 1]
+                .rfind(SOH)
+                .map_or(0, |position| position + 1);
+            if message[field_start..].starts_with("10=") {
+                return Ok(message);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixture_chain;
+
+    fn selected_vertical() -> (OptionChain, OrderConfig) {
+        let chain = fixture_chain("SPY");
+        let vertical = chain
+            .spreads
+            .iter()
+            .find(|spread| spread.legs.len() == 2)
+            .unwrap();
+        let config = OrderConfig {
+            selected_spreads: vec![vertical.sequence_numbers.clone()],
+            spread_quantity: 3,
+            ..OrderConfig::default()
+        };
+        (chain, config)
+    }
+
+    #[test]
+    fn prices_selected_spread_as_combo() {
+        let (chain, config) = selected_vertical();
+        let tickets = build_tickets(&[], &[], std::slice::from_ref(&chain), &config);
+        assert_eq!(tickets.len(), 1);
+        let ticket = &tickets[0];
+        assert_eq!(ticket.sec_type, SecType::Combo);
+        assert_eq!(ticket.quantity, 3);
+        assert_eq!(ticket.limit_price, 4.5);
+        assert!(ticket.legs.iter().all(|leg| leg.ratio == 1));
+        let natural = build_tickets(
+            &[],
+            &[],
+            &[chain],
+            &OrderConfig {
+                price_source: PriceSource::Natural,
+                ..config.clone()
+            },
+        );
+        assert_eq!(natural[0].limit_price, 4.6);
+        let rows = ib_basket(&tickets, &config);
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].sec_type, "BAG");
+        assert_eq!(rows[1].action, "BUY");
+        assert_eq!(rows[1].last_trading_day_or_contract_month, "20300118");
+        assert_eq!(rows[2].strike, Some(480.0));
+        assert_eq!(rows[2].action, "SELL");
+    }
+
+    #[test]
+    fn encodes_fix_multileg() {
+        let (chain, config) = selected_vertical();
+        let tickets = build_tickets(&[], &[], &[chain], &config);
+        let message = &fix_messages(&tickets, &config)[0];
+        assert_eq!(fix_field(message, 35), Some("AB"));
+        assert_eq!(fix_field(message, 555), Some("2"));
+        assert_eq!(fix_field(message, 44), Some("4.5"));
+        let body_start = message.find("35=").unwrap();
+        let checksum_start = message.rfind("10=").unwrap();
+        let length: usize = fix_field(message, 9).unwrap().parse().unwrap();
+        assert_eq!(checksum_start - body_start, length);
+        let checksum = message[..checksum_start]
+            .bytes()
+            .map(|byte| byte as u32)
+            .sum::<u32>()
+            % 256;
+        assert_eq!(
+            fix_field(message, 10),
+            Some(format!("{:03}", checksum).as_str())
+        );
+    }
+
+    #[tokio::test]
+    async fn sends_orders_to_local_acceptor() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        let acceptor = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let config = OrderConfig {
+                sender_comp_id: "IB".to_string(),
+                target_comp_id: "OPTIONSSCAN".to_string(),
+                ..OrderConfig::default()
+            };
+            let mut received: Vec<String> = Vec::new();
+            let mut seq_no = 0;
+            loop {
+                let message = read_fix_message(&mut reader).unwrap();
+                seq_no += 1;
+                let reply = match fix_field(&message, 35).unwrap() {
+                    "A" => {
+                        assert_eq!(fix_field(&message, 141), Some("Y"));
+                        encode_fix("A", &[(98, "0".to_string())], &config, seq_no)
+                    }
+                    "0" => {
+                        assert_eq!(fix_field(&message, 112), Some("ping"));
+                        continue;
+                    }
+                    "5" => {
+                        writer
+                            .write_all(encode_fix("5", &[], &config, seq_no).as_bytes())
+                            .unwrap();
+                        break;
+                    }
+                    msg_type => {
+                        received.push(msg_type.to_string());
+                        let id = fix_field(&message, 11).unwrap().to_string();
+                        for (msg_type, fields) in [
+                            ("0", vec![]),
+                            ("1", vec![(112, "ping".to_string())]),
+                            (
+                                "8",
+                                vec![(11, "another".to_string()), (39, "8".to_string())],
+                            ),
+                        ] {
+                            seq_no += 1;
+                            writer
+                                .write_all(
+                                    encode_fix(msg_type, &fields, &config, seq_no).as_bytes(),
+                                )
+                                .unwrap();
+                        }
+                        seq_no += 1;
+                        encode_fix(
+                            "8",
+                            &[(11, id), (39, "0".to_string()), (150, "0".to_string())],
+                            &config,
+                            seq_no,
+                        )
+                    }
+                };
+                writer.write_all(reply.as_bytes()).unwrap();
+            }
+            received
+        });
+        let (chain, config) = selected_vertical();
+        let signal = Signal {
+            symbol: "SPY".to_string(),
+            price: 464.905,
+            order_quantity: -20,
+            ..Signal::default()
+        };
+        let tickets = build_tickets(&[signal], &[], &[chain], &config);
+        let acks = send_fix_orders(&server, &tickets, &config).await.unwrap();
+        assert_eq!(ack_types(&acks), vec!["8", "8"]);
+        assert_eq!(acks[0].id, tickets[0].id);
+        assert_eq!(acks[0].order_status, "0");
+        assert_eq!(acceptor.join().unwrap(), vec!["D", "AB"]);
+    }
+
+    #[test]
+    fn marks_sales_past_holdings_as_short() {
+        let signal = Signal {
+            symbol: "SPY".to_string(),
+            price: 464.905,
+            current_quantity: 15,
+            order_quantity: -20,
+            ..Signal::default()
+        };
+        let config = OrderConfig::default();
+        let tickets = build_tickets(std::slice::from_ref(&signal), &[], &[], &config);
+        assert_eq!(tickets.len(), 2);
+        assert_eq!(
+            (tickets[0].side, tickets[0].quantity),
+            (OrderSide::Sell, 15)
+        );
+        assert_eq!(
+            (tickets[1].side, tickets[1].quantity),
+            (OrderSide::SellShort, 5)
+        );
+        let messages = fix_messages(&tickets, &config);
+        assert_eq!(fix_field(&messages[0], 54), Some("2"));
+        assert_eq!(fix_field(&messages[1], 54), Some("5"));
+        assert_eq!(ib_basket(&tickets, &config)[1].action, "SSHORT");
+        let flat = Signal {
+            current_quantity: 0,
+            ..signal
+        };
+        let tickets = build_tickets(&[flat], &[], &[], &config);
+        assert_eq!(tickets.len(), 1);
+        assert_eq!(
+            fix_field(&fix_messages(&tickets, &config)[0], 54),
+            Some("5")
+        );
+    }
+
+    fn ack_types(acks: &[FixAck]) -> Vec<&str> {
+        acks.iter().map(|ack| ack.msg_type.as_str()).collect()
+    }
+}
@@ -1,11 +1,12 @@
 use crate::{
     models::{ShortStockData, ShortStockInfo},
-    SHORT_STOCK_DATA_FP,
+    MARGIN_LOAN_RATE, SHORT_FEE_MARGIN_SAFETY, SHORT_STOCK_DATA_FP, SOCKET_TIMEOUT_SECONDS,
 };
 use async_ftp::FtpStream;
 use chrono::DateTime;
 use chrono_tz::Tz;
 use serde_json::Value;
+use std::net::{TcpStream, ToSocketAddrs};
 use std::str;
 
 pub fn create_csv_file<T: serde::Serialize>(data: &[T], filename: &str) {
@@ -43,7 +44,14 @@ pub fn get_list(filename: &str) -> Result<Vec<String>, std::io::Error> {
     let file = std::fs::read_to_string(filename)?;
     let lines = file.lines();
     for line in lines {
-        output.push(line.to_string())
+        // an optional second column names the symbol's sector, see `GroupConfig::load`
+        output.push(
+            line.split(',')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_string(),
+        )
     }
     println!("Found {} symbols in list", output.len());
     Ok(output)
@@ -53,7 +61,6 @@ pub fn get_new_york_time() -> DateTime<Tz> {
     chrono::Utc::now().with_timezone(&chrono_tz::America::New_York)
     //
 }
-/*
 pub fn get_margin_loan_cost(principal: f64, duration: i64) -> f64 {
     round_to_decimals(
         ((MARGIN_LOAN_RATE / 365.0) / 100.0) * (duration as f64) * (principal),
@@ -75,7 +82,6 @@ pub fn round_up(num: f64) -> f64{
         num.round()+1.0
     } else {num.round()}
 }
-*/
 
 impl ShortStockInfo {
     pub async fn get() -> Self {
@@ -130,6 +136,30 @@ pub fn open_json(filename: &str) -> Result<Value, std::io::Error> {
     Ok(data_file)
 }
 
+/// Sets the value for `date`, a chain's `%D` date, in a series kept oldest first. A day that is
+/// already there is overwritten and a day recorded out of order (a backfill after live runs) is
+/// put in its place.
+pub fn record_daily<T>(series: &mut Vec<(String, T)>, date: &str, value: T) {
+    let day = |date: &str| chrono::NaiveDate::parse_from_str(date, "%D").ok();
+    match series.binary_search_by_key(&day(date), |(recorded, _)| day(recorded)) {
+        Ok(i) => series[i].1 = value,
+        Err(i) => series.insert(i, (date.to_string(), value)),
+    }
+}
+
+/// Reads a JSON file written by an earlier run, `Ok(None)` when there isn't one yet. A file
+/// that exists but doesn't parse is an error, so it is never mistaken for an empty store and
+/// overwritten.
+pub fn load_json<T: serde::de::DeserializeOwned>(filename: &str) -> Result<Option<T>, String> {
+    match std::fs::read_to_string(filename) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .map(Some)
+            .map_err(|e| format!("{} could not be read: {}", filename, e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("{} could not be opened: {}", filename, e)),
+    }
+}
+
 pub fn create_json_file<T: serde::Serialize>(filename: &str, contents: &T) {
     let filename = format!("{}.json", filename);
     serde_json::to_writer(&std::fs::File::create(filename).unwrap(), contents).unwrap();
@@ -138,3 +168,26 @@ pub fn create_json_file<T: serde::Serialize>(filename: &str, contents: &T) {
 pub fn delete_file(filename: &str) {
     std::fs::remove_file(filename).unwrap()
 }
+
+/// Connects to a `host:port`, giving up on the connection and on every later read or write
+/// after `SOCKET_TIMEOUT_SECONDS` so an unreachable server can't stall the scan.
+pub fn connect_with_timeout(server: &str) -> std::io::Result<TcpStream> {
+    let timeout = std::time::Duration::from_secs(SOCKET_TIMEOUT_SECONDS);
+    let mut last_error = None;
+    for address in server.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("no address for {}", server),
+        )
+    }))
+}
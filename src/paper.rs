@@ -0,0 +1,813 @@
+use crate::models::OptionChain;
+use crate::orders::{OrderSide, OrderTicket, SecType, TicketLeg};
+use crate::others::{create_json_file, load_json, round_to_decimals};
+use crate::single_options::OptionType;
+use crate::{
+    AMOUNT_IN_ACCOUNT, PAPER_COMMISSION_PER_CONTRACT, PAPER_COMMISSION_PER_SHARE,
+    PAPER_ORDER_LIFE_DAYS,
+};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct PaperConfig {
+    pub starting_cash: f64,
+    pub commission_per_contract: f64,
+    pub commission_per_share: f64,
+    /// Calendar days an order keeps working before whatever is left of it is cancelled.
+    pub order_life_days: i64,
+    pub stock_margin_percent: f64,
+    /// Of the underlying, less any out of the money amount, for each short option.
+    pub short_option_margin_percent: f64,
+    /// Floor for a short option, of the underlying for calls and of the strike for puts.
+    pub min_short_option_margin_percent: f64,
+}
+impl Default for PaperConfig {
+    fn default() -> Self {
+        Self {
+            starting_cash: AMOUNT_IN_ACCOUNT,
+            commission_per_contract: PAPER_COMMISSION_PER_CONTRACT,
+            commission_per_share: PAPER_COMMISSION_PER_SHARE,
+            order_life_days: PAPER_ORDER_LIFE_DAYS,
+            stock_margin_percent: 50.0,
+            short_option_margin_percent: 20.0,
+            min_short_option_margin_percent: 10.0,
+        }
+    }
+}
+impl PaperConfig {
+    /// The defaults when there is no config file. One that doesn't parse is an error, so the
+    /// account is never run with settings nobody chose.
+    pub fn load(filename: &str) -> Result<Self, String> {
+        Ok(load_json(filename)?.unwrap_or_default())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum PaperOrderStatus {
+    Working,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PaperOrder {
+    pub id: String,
+    pub source: String,
+    pub symbol: String,
+    pub description: String,
+    pub sec_type: SecType,
+    pub side: OrderSide,
+    pub quantity: i64,
+    pub filled_quantity: i64,
+    pub limit_price: f64,
+    pub average_fill_price: f64,
+    pub status: PaperOrderStatus,
+    pub submitted_date: String,
+    /// `data_timestamp` of the chain the ticket was built from, then of the last quote that
+    /// filled it. Only later quotes can fill it, so one quote's size is never taken twice.
+    pub quote_timestamp: String,
+    /// Saved with the account, since later runs fill the order from them.
+    #[serde(default)]
+    pub legs: Vec<TicketLeg>,
+}
+impl PaperOrder {
+    fn is_working(&self) -> bool {
+        matches!(
+            self.status,
+            PaperOrderStatus::Working | PaperOrderStatus::PartiallyFilled
+        )
+    }
+    /// 1 for share orders, 100 for anything quoted per contract.
+    fn multiplier(&self) -> f64 {
+        if self.sec_type == SecType::Stock {
+            1.0
+        } else {
+            100.0
+        }
+    }
+    pub fn row(&self) -> PaperOrderRow<'_> {
+        PaperOrderRow {
+            id: &self.id,
+            source: &self.source,
+            symbol: &self.symbol,
+            description: &self.description,
+            sec_type: self.sec_type,
+            side: self.side,
+            quantity: self.quantity,
+            filled_quantity: self.filled_quantity,
+            limit_price: self.limit_price,
+            average_fill_price: self.average_fill_price,
+            status: self.status,
+            submitted_date: &self.submitted_date,
+            quote_timestamp: &self.quote_timestamp,
+        }
+    }
+}
+
+/// An order without its legs, for the CSV report.
+#[derive(Debug, Serialize)]
+pub struct PaperOrderRow<'a> {
+    pub id: &'a str,
+    pub source: &'a str,
+    pub symbol: &'a str,
+    pub description: &'a str,
+    pub sec_type: SecType,
+    pub side: OrderSide,
+    pub quantity: i64,
+    pub filled_quantity: i64,
+    pub limit_price: f64,
+    pub average_fill_price: f64,
+    pub status: PaperOrderStatus,
+    pub submitted_date: &'a str,
+    pub quote_timestamp: &'a str,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PaperPosition {
+    pub symbol: String,
+    /// The option symbol, or the stock symbol for shares.
+    pub instrument: String,
+    pub option_type: Option<OptionType>,
+    pub strike: Option<f64>,
+    pub expiry: Option<String>,
+    /// Contracts or shares, negative for short.
+    pub quantity: i64,
+    pub average_price: f64,
+    pub last_mark: f64,
+    pub market_value: f64,
+    pub unrealized_pnl: f64,
+    pub realized_pnl: f64,
+    pub margin: f64,
+}
+impl PaperPosition {
+    fn multiplier(&self) -> f64 {
+        if self.option_type.is_some() {
+            100.0
+        } else {
+            1.0
+        }
+    }
+    /// Adds to the position at `price`, realizing P&L on whatever part of it closes.
+    fn apply(&mut self, quantity: i64, price: f64) {
+        if self.quantity != 0 && self.quantity.signum() != quantity.signum() {
+            let closing = quantity.abs().min(self.quantity.abs()) as f64;
+            self.realized_pnl += self.quantity.signum() as f64
+                * closing
+                * (price - self.average_price)
+                * self.multiplier();
+            let remaining = self.quantity + quantity;
+            if remaining != 0 && remaining.signum() != self.quantity.signum() {
+                self.average_price = price
+            }
+            self.quantity = remaining;
+        } else {
+            let total = self.quantity + quantity;
+            self.average_price = (self.average_price * self.quantity.abs() as f64
+                + price * quantity.abs() as f64)
+                / total.abs() as f64;
+            self.quantity = total;
+        }
+        self.realized_pnl = round_to_decimals(self.realized_pnl, 2);
+    }
+    fn mark(&mut self, mark: f64) {
+        self.last_mark = mark;
+        let units = self.quantity as f64 * self.multiplier();
+        self.market_value = round_to_decimals(mark * units, 2);
+        self.unrealized_pnl = round_to_decimals((mark - self.average_price) * units, 2);
+    }
+    /// Each position is margined on its own, so spreads are charged as if naked. Long options
+    /// are paid in full and have no loan value.
+    fn set_margin(&mut self, underlying: f64, config: &PaperConfig) {
+        let size = self.quantity.abs() as f64;
+        self.margin = round_to_decimals(
+            match (self.option_type, self.strike) {
+                (Some(kind), Some(strike)) if self.quantity < 0 => {
+                    let out_of_money = match kind {
+                        OptionType::Call => strike - underlying,
+                        OptionType::Put => underlying - strike,
+                    }
+                    .max(0.0);
+                    let floor = match kind {
+                        OptionType::Call => underlying,
+                        OptionType::Put => strike,
+                    } * config.min_short_option_margin_percent
+                        / 100.0;
+                    let requirement = (config.short_option_margin_percent / 100.0 * underlying
+                        - out_of_money)
+                        .max(floor);
+                    (requirement + self.last_mark) * 100.0 * size
+                }
+                (Some(_), _) => self.market_value.abs(),
+                _ => self.market_value.abs() * config.stock_margin_percent / 100.0,
+            },
+            2,
+        );
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum FillKind {
+    Fill,
+    /// A long option settled in the money.
+    Exercise,
+    /// A short option settled in the money.
+    Assignment,
+    Expiry,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PaperFill {
+    pub date: String,
+    pub order_id: String,
+    pub symbol: String,
+    pub instrument: String,
+    pub kind: FillKind,
+    /// Signed, positive when bought.
+    pub quantity: i64,
+    pub price: f64,
+    pub commission: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AccountSnapshot {
+    pub date: String,
+    pub cash: f64,
+    pub market_value: f64,
+    pub equity: f64,
+    pub margin: f64,
+    pub excess_liquidity: f64,
+    pub realized_pnl: f64,
+    pub unrealized_pnl: f64,
+    pub open_orders: usize,
+    pub open_positions: usize,
+}
+
+/// The simulated account, kept between runs: generated tickets become working orders that
+/// later chain quotes fill at the natural price, up to the displayed size.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct PaperAccount {
+    pub cash: f64,
+    pub orders: Vec<PaperOrder>,
+    pub positions: Vec<PaperPosition>,
+    pub fills: Vec<PaperFill>,
+    /// One snapshot per chain date, the last run of the day replacing earlier ones.
+    pub history: Vec<AccountSnapshot>,
+}
+impl PaperAccount {
+    /// Loads the account written by a previous run, opening a new one with the configured cash
+    /// when there is none. An account file that doesn't parse is an error rather than a fresh
+    /// account, so it is never saved over.
+    pub fn load(filename: &str, config: &PaperConfig) -> Result<Self, String> {
+        Ok(
+            load_json(&format!("{}.json", filename))?.unwrap_or_else(|| Self {
+                cash: config.starting_cash,
+                ..Self::default()
+            }),
+        )
+    }
+    pub fn save(&self, filename: &str) {
+        create_json_file(filename, self)
+    }
+    /// Queues tickets that haven't been seen before, stamped with their symbol's quote time.
+    pub fn submit(&mut self, tickets: &[OrderTicket], chains: &[OptionChain]) {
+        for ticket in tickets {
+            let Some(chain) = chains.iter().find(|chain| chain.symbol == ticket.symbol) else {
+                continue;
+            };
+            if self.orders.iter().any(|order| order.id == ticket.id) {
+                continue;
+            }
+            self.orders.push(PaperOrder {
+                id: ticket.id.clone(),
+                source: ticket.source.clone(),
+                symbol: ticket.symbol.clone(),
+                description: ticket.description.clone(),
+                sec_type: ticket.sec_type,
+                side: ticket.side,
+                quantity: ticket.quantity,
+                filled_quantity: 0,
+                limit_price: ticket.limit_price,
+                average_fill_price: 0.0,
+                status: PaperOrderStatus::Working,
+                submitted_date: chain_date(chain).format("%F").to_string(),
+                quote_timestamp: chain.data_timestamp.clone(),
+                legs: ticket.legs.clone(),
+            })
+        }
+    }
+    /// Fills working orders against the new quotes, settles expired options, cancels stale
+    /// orders, then marks everything and records the day.
+    pub fn process(&mut self, chains: &[OptionChain], config: &PaperConfig) {
+        for i in 0..self.orders.len() {
+            let Some(chain) = chains
+                .iter()
+                .find(|chain| chain.symbol == self.orders[i].symbol)
+            else {
+                continue;
+            };
+            if !self.orders[i].is_working()
+                || chain.data_timestamp == self.orders[i].quote_timestamp
+            {
+                continue;
+            }
+            self.fill(i, chain, config);
+            let submitted =
+                NaiveDate::parse_from_str(&self.orders[i].submitted_date, "%F").unwrap();
+            if self.orders[i].is_working()
+                && (chain_date(chain) - submitted).num_days() >= config.order_life_days
+            {
+                self.orders[i].status = PaperOrderStatus::Cancelled
+            }
+        }
+        for chain in chains {
+            self.settle_expired(chain);
+        }
+        for position in self.positions.iter_mut() {
+            let Some(chain) = chains.iter().find(|chain| chain.symbol == position.symbol) else {
+                continue;
+            };
+            let mark = match (&position.expiry, position.strike, position.option_type) {
+                (Some(expiry), Some(strike), Some(kind)) => {
+                    find_option(chain, expiry, strike, kind)
+                        .and_then(|option| Some(0.5 * (option.bid_price? + option.ask_price?)))
+                        .unwrap_or(position.last_mark)
+                }
+                _ => chain.underlying_mid,
+            };
+            position.mark(mark);
+            position.set_margin(chain.underlying_mid, config);
+        }
+        if let Some(date) = chains.iter().map(chain_date).max() {
+            self.record(date.format("%F").to_string())
+        }
+    }
+    /// Fills as many units as every leg's displayed size allows, if the natural price is within
+    /// the order's limit. An order without legs has nothing to price and never fills.
+    fn fill(&mut self, i: usize, chain: &OptionChain, config: &PaperConfig) {
+        let order = &self.orders[i];
+        if order.legs.is_empty() {
+            return;
+        }
+        let quotes: Option<Vec<LegQuote>> = order
+            .legs
+            .iter()
+            .map(|leg| LegQuote::of(chain, leg))
+            .collect();
+        let Some(quotes) = quotes else {
+            return;
+        };
+        let net: f64 = order
+            .legs
+            .iter()
+            .zip(&quotes)
+            .map(|(leg, quote)| {
+                side_sign(leg.side) * leg.ratio as f64 * quote.price * quote.multiplier
+                    / order.multiplier()
+            })
+            .sum();
+        let within_limit = match order.side {
+            OrderSide::Buy => net <= order.limit_price + 1e-9,
+            OrderSide::Sell | OrderSide::SellShort => -net >= order.limit_price - 1e-9,
+        };
+        let units = order
+            .legs
+            .iter()
+            .zip(&quotes)
+            .filter_map(|(leg, quote)| Some(quote.size? / leg.ratio.max(1)))
+            .fold(order.quantity - order.filled_quantity, i64::min);
+        if !within_limit || units <= 0 {
+            return;
+        }
+        let date = chain_date(chain).format("%F").to_string();
+        for (leg, quote) in order.legs.clone().iter().zip(quotes) {
+            let quantity = side_sign(leg.side) as i64 * units * leg.ratio;
+            let commission = quantity.abs() as f64
+                * if quote.multiplier == 1.0 {
+                    config.commission_per_share
+                } else {
+                    config.commission_per_contract
+                };
+            self.cash -= quantity as f64 * quote.price * quote.multiplier + commission;
+            self.position(chain, leg, &quote.instrument)
+                .apply(quantity, quote.price);
+            self.fills.push(PaperFill {
+                date: date.clone(),
+                order_id: self.orders[i].id.clone(),
+                symbol: chain.symbol.clone(),
+                instrument: quote.instrument,
+                kind: FillKind::Fill,
+                quantity,
+                price: quote.price,
+                commission: round_to_decimals(commission, 2),
+            })
+        }
+        self.cash = round_to_decimals(self.cash, 2);
+        let order = &mut self.orders[i];
+        order.average_fill_price = round_to_decimals(
+            (order.average_fill_price * order.filled_quantity as f64 + net.abs() * units as f64)
+                / (order.filled_quantity + units) as f64,
+            4,
+        );
+        order.filled_quantity += units;
+        order.quote_timestamp = chain.data_timestamp.clone();
+        order.status = if order.filled_quantity == order.quantity {
+            PaperOrderStatus::Filled
+        } else {
+            PaperOrderStatus::PartiallyFilled
+        };
+    }
+    fn position(
+        &mut self,
+        chain: &OptionChain,
+        leg: &TicketLeg,
+        instrument: &str,
+    ) -> &mut PaperPosition {
+        if let Some(i) = self
+            .positions
+            .iter()
+            .position(|position| position.instrument == instrument)
+        {
+            return &mut self.positions[i];
+        }
+        self.positions.push(PaperPosition {
+            symbol: chain.symbol.clone(),
+            instrument: instrument.to_string(),
+            option_type: leg.right,
+            strike: leg.strike,
+            expiry: leg.expiry.clone(),
+            quantity: 0,
+            average_price: 0.0,
+            last_mark: 0.0,
+            market_value: 0.0,
+            unrealized_pnl: 0.0,
+            realized_pnl: 0.0,
+            margin: 0.0,
+        });
+        self.positions.last_mut().unwrap()
+    }
+    /// Options past expiry close at their intrinsic value against the chain's underlying. In
+    /// the money equity options then become shares at that price, which nets to trading at the
+    /// strike; index options settle in cash.
+    fn settle_expired(&mut self, chain: &OptionChain) {
+        let date = chain_date(chain);
+        let underlying = chain.underlying_mid;
+        let mut deliveries: Vec<(String, i64)> = Vec::new();
+        for position in self
+            .positions
+            .iter_mut()
+            .filter(|position| position.symbol == chain.symbol && position.quantity != 0)
+        {
+            let (Some(expiry), Some(strike), Some(kind)) =
+                (&position.expiry, position.strike, position.option_type)
+            else {
+                continue;
+            };
+            if NaiveDate::parse_from_str(expiry, "%F").map_or(true, |expiry| expiry >= date) {
+                continue;
+            }
+            let intrinsic = round_to_decimals(
+                match kind {
+                    OptionType::Call => underlying - strike,
+                    OptionType::Put => strike - underlying,
+                }
+                .max(0.0),
+                2,
+            );
+            let quantity = position.quantity;
+            let kind_of_fill = if intrinsic < 0.01 {
+                FillKind::Expiry
+            } else if quantity > 0 {
+                FillKind::Exercise
+            } else {
+                FillKind::Assignment
+            };
+            self.cash += quantity as f64 * intrinsic * 100.0;
+            position.apply(-quantity, intrinsic);
+            self.fills.push(PaperFill {
+                date: date.format("%F").to_string(),
+                order_id: String::new(),
+                symbol: chain.symbol.clone(),
+                instrument: position.instrument.clone(),
+                kind: kind_of_fill,
+                quantity: -quantity,
+                price: intrinsic,
+                commission: 0.0,
+            });
+            if kind_of_fill != FillKind::Expiry && !chain.symbol.starts_with('^') {
+                let shares = match kind {
+                    OptionType::Call => 100 * quantity,
+                    OptionType::Put => -100 * quantity,
+                };
+                deliveries.push((position.instrument.clone(), shares))
+            }
+        }
+        for (instrument, shares) in deliveries {
+            let stock = TicketLeg {
+                sec_type: SecType::Stock,
+                side: if shares > 0 {
+                    OrderSide::Buy
+                } else {
+                    OrderSide::Sell
+                },
+                ratio: 1,
+                expiry: None,
+                strike: None,
+                right: None,
+                price: underlying,
+            };
+            self.cash -= shares as f64 * underlying;
+            self.position(chain, &stock, &chain.symbol)
+                .apply(shares, underlying);
+            self.fills.push(PaperFill {
+                date: date.format("%F").to_string(),
+                order_id: instrument,
+                symbol: chain.symbol.clone(),
+                instrument: chain.symbol.clone(),
+                kind: if shares > 0 {
+                    FillKind::Exercise
+                } else {
+                    FillKind::Assignment
+                },
+                quantity: shares,
+                price: underlying,
+                commission: 0.0,
+            })
+        }
+        self.cash = round_to_decimals(self.cash, 2);
+    }
+    fn record(&mut self, date: String) {
+        let sum = |value: fn(&PaperPosition) -> f64| -> f64 {
+            round_to_decimals(self.positions.iter().map(value).sum(), 2)
+        };
+        let market_value = sum(|position| position.market_value);
+        let margin = sum(|position| position.margin);
+        let equity = round_to_decimals(self.cash + market_value, 2);
+        let snapshot = AccountSnapshot {
+            date,
+            cash: self.cash,
+            market_value,
+            equity,
+            margin,
+            excess_liquidity: round_to_decimals(equity - margin, 2),
+            realized_pnl: sum(|position| position.realized_pnl),
+            unrealized_pnl: sum(|position| position.unrealized_pnl),
+            open_orders: self
+                .orders
+                .iter()
+                .filter(|order| order.is_working())
+                .count(),
+            open_positions: self
+                .positions
+                .iter()
+                .filter(|position| position.quantity != 0)
+                .count(),
+        };
+        match self.history.last_mut() {
+            Some(last) if last.date == snapshot.date => *last = snapshot,
+            _ => self.history.push(snapshot),
+        }
+    }
+}
+
+/// What a leg can trade at right now: the ask when buying and the bid when selling, with the
+/// size shown there. Shares trade at the underlying mid in any size.
+struct LegQuote {
+    instrument: String,
+    price: f64,
+    size: Option<i64>,
+    multiplier: f64,
+}
+impl LegQuote {
+    fn of(chain: &OptionChain, leg: &TicketLeg) -> Option<Self> {
+        match (&leg.expiry, leg.strike, leg.right) {
+            (Some(expiry), Some(strike), Some(kind)) => {
+                let option = find_option(chain, expiry, strike, kind)?;
+                let (price, size) = match leg.side {
+                    OrderSide::Buy => (option.ask_price?, option.option_ask_size?),
+                    OrderSide::Sell | OrderSide::SellShort => {
+                        (option.bid_price?, option.option_bid_size?)
+                    }
+                };
+                if price <= 0.0 || size <= 0 {
+                    return None;
+                }
+                Some(Self {
+                    instrument: option.symbol.clone(),
+                    price,
+                    size: Some(size),
+                    multiplier: 100.0,
+                })
+            }
+            _ if chain.underlying_mid > 0.0 => Some(Self {
+                instrument: chain.symbol.clone(),
+                price: chain.underlying_mid,
+                size: None,
+                multiplier: 1.0,
+            }),
+            _ => None,
+        }
+    }
+}
+
+fn find_option<'a>(
+    chain: &'a OptionChain,
+    expiry: &str,
+    strike: f64,
+    kind: OptionType,
+) -> Option<&'a crate::single_options::OptionData> {
+    chain.options.iter().find(|option| {
+        option.expiration_date == expiry && option.strike == strike && option.kind == kind
+    })
+}
+
+fn side_sign(side: OrderSide) -> f64 {
+    match side {
+        OrderSide::Buy => 1.0,
+        OrderSide::Sell | OrderSide::SellShort => -1.0,
+    }
+}
+
+fn chain_date(chain: &OptionChain) -> NaiveDate {
+    NaiveDate::parse_from_str(&chain.date, "%D").unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixture_chain_as_of;
+    use crate::orders::{build_tickets, OrderConfig, PriceSource};
+
+    fn later(chain: &OptionChain, date: &str, underlying_mid: f64) -> OptionChain {
+        OptionChain {
+            date: date.to_string(),
+            data_timestamp: date.to_string(),
+            underlying_mid,
+            ..chain.clone()
+        }
+    }
+
+    #[test]
+    fn fills_against_later_quotes_up_to_displayed_size() {
+        let chain = fixture_chain_as_of("SPY", NaiveDate::from_ymd(2029, 12, 3));
+        let vertical = chain
+            .spreads
+            .iter()
+            .find(|spread| spread.legs.len() == 2)
+            .unwrap();
+        let order_config = OrderConfig {
+            price_source: PriceSource::Natural,
+            selected_spreads: vec![vertical.sequence_numbers.clone()],
+            spread_quantity: 200,
+            ..OrderConfig::default()
+        };
+        let tickets = build_tickets(&[], &[], std::slice::from_ref(&chain), &order_config);
+        let config = PaperConfig::default();
+        let mut account = PaperAccount::load("no-such-paper-account", &config).unwrap();
+        account.submit(&tickets, std::slice::from_ref(&chain));
+        account.process(std::slice::from_ref(&chain), &config);
+        assert_eq!(account.orders[0].filled_quantity, 0);
+
+        account.process(&[later(&chain, "12/03/29", chain.underlying_mid)], &config);
+        let order = &account.orders[0];
+        assert_eq!(order.filled_quantity, 95);
+        assert_eq!(order.status, PaperOrderStatus::PartiallyFilled);
+        assert_eq!(order.average_fill_price, 4.6);
+        assert_eq!(
+            account.cash,
+            round_to_decimals(25000.0 - 95.0 * 460.0 - 190.0 * 0.65, 2)
+        );
+        assert_eq!(account.positions.len(), 2);
+        assert_eq!(account.positions[1].quantity, -95);
+        assert!(account.positions[1].margin > 0.0);
+        assert_eq!(account.history.len(), 1);
+
+        account.process(&[later(&chain, "12/04/29", chain.underlying_mid)], &config);
+        assert_eq!(account.orders[0].filled_quantity, 190);
+        assert_eq!(account.orders[0].status, PaperOrderStatus::Cancelled);
+        assert_eq!(account.history.len(), 2);
+    }
+
+    #[test]
+    fn fills_each_quote_once() {
+        let chain = fixture_chain_as_of("SPY", NaiveDate::from_ymd(2029, 12, 3));
+        let vertical = chain
+            .spreads
+            .iter()
+            .find(|spread| spread.legs.len() == 2)
+            .unwrap();
+        let order_config = OrderConfig {
+            price_source: PriceSource::Natural,
+            selected_spreads: vec![vertical.sequence_numbers.clone()],
+            spread_quantity: 200,
+            ..OrderConfig::default()
+        };
+        let tickets = build_tickets(&[], &[], std::slice::from_ref(&chain), &order_config);
+        let config = PaperConfig::default();
+        let mut account = PaperAccount {
+            cash: config.starting_cash,
+            ..PaperAccount::default()
+        };
+        account.submit(&tickets, std::slice::from_ref(&chain));
+        let quote = later(&chain, "12/03/29", chain.underlying_mid);
+        account.process(std::slice::from_ref(&quote), &config);
+        assert_eq!(account.orders[0].filled_quantity, 95);
+        assert_eq!(account.orders[0].quote_timestamp, "12/03/29");
+        let cash = account.cash;
+        account.process(std::slice::from_ref(&quote), &config);
+        assert_eq!(account.orders[0].filled_quantity, 95);
+        assert_eq!(account.cash, cash);
+        assert_eq!(account.fills.len(), 2);
+    }
+
+    #[test]
+    fn fills_orders_reloaded_from_file() {
+        let chain = fixture_chain_as_of("SPY", NaiveDate::from_ymd(2029, 12, 3));
+        let vertical = chain
+            .spreads
+            .iter()
+            .find(|spread| spread.legs.len() == 2)
+            .unwrap();
+        let order_config = OrderConfig {
+            price_source: PriceSource::Natural,
+            selected_spreads: vec![vertical.sequence_numbers.clone()],
+            spread_quantity: 200,
+            ..OrderConfig::default()
+        };
+        let tickets = build_tickets(&[], &[], std::slice::from_ref(&chain), &order_config);
+        let config = PaperConfig::default();
+        let filename = std::env::temp_dir().join("options-scan-paper-reload-test");
+        let filename = filename.to_str().unwrap();
+        let _ = std::fs::remove_file(format!("{}.json", filename));
+        let mut account = PaperAccount::load(filename, &config).unwrap();
+        account.submit(&tickets, std::slice::from_ref(&chain));
+        account.save(filename);
+
+        let mut account = PaperAccount::load(filename, &config).unwrap();
+        assert_eq!(account.orders[0].legs, tickets[0].legs);
+        account.process(&[later(&chain, "12/03/29", chain.underlying_mid)], &config);
+        assert_eq!(account.orders[0].filled_quantity, 95);
+        assert_eq!(account.fills.len(), 2);
+        assert_eq!(account.positions.len(), 2);
+
+        account.orders[0].legs.clear();
+        account.process(&[later(&chain, "12/04/29", chain.underlying_mid)], &config);
+        assert_eq!(account.orders[0].filled_quantity, 95);
+        assert_eq!(account.orders[0].status, PaperOrderStatus::Cancelled);
+    }
+
+    #[test]
+    fn refuses_unreadable_account() {
+        let filename = std::env::temp_dir().join("options-scan-paper-test");
+        let filename = filename.to_str().unwrap();
+        std::fs::write(format!("{}.json", filename), r#"{"cash": "lots"}"#).unwrap();
+        assert!(PaperAccount::load(filename, &PaperConfig::default()).is_err());
+        std::fs::write(format!("{}.json", filename), r#"{"order_life_days": "2"}"#).unwrap();
+        assert!(PaperConfig::load(&format!("{}.json", filename)).is_err());
+    }
+
+    #[test]
+    fn settles_options_at_expiry() {
+        let chain = fixture_chain_as_of("SPY", NaiveDate::from_ymd(2029, 12, 3));
+        let call = TicketLeg {
+            sec_type: SecType::Option,
+            side: OrderSide::Buy,
+            ratio: 1,
+            expiry: Some("2030-01-18".to_string()),
+            strike: Some(470.0),
+            right: Some(OptionType::Call),
+            price: 12.4,
+        };
+        let put = TicketLeg {
+            side: OrderSide::Sell,
+            strike: Some(455.0),
+            right: Some(OptionType::Put),
+            ..call.clone()
+        };
+        let mut account = PaperAccount {
+            cash: 10000.0,
+            ..PaperAccount::default()
+        };
+        account
+            .position(&chain, &call, ".SPY300118C470")
+            .apply(2, 12.4);
+        account
+            .position(&chain, &put, ".SPY300118P455")
+            .apply(-1, 9.1);
+        account.process(&[later(&chain, "01/19/30", 480.0)], &PaperConfig::default());
+        assert_eq!(account.positions[0].quantity, 0);
+        assert_eq!(account.positions[0].realized_pnl, -480.0);
+        assert_eq!(account.positions[1].quantity, 0);
+        assert_eq!(account.positions[1].realized_pnl, 910.0);
+        let shares = &account.positions[2];
+        assert_eq!((shares.quantity, shares.average_price), (200, 480.0));
+        assert_eq!(account.cash, 10000.0 - 200.0 * 470.0);
+        let kinds: Vec<FillKind> = account.fills.iter().map(|fill| fill.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![FillKind::Exercise, FillKind::Expiry, FillKind::Exercise]
+        );
+    }
+}
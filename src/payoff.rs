@@ -0,0 +1,452 @@
+use crate::others::round_to_decimals;
+use crate::pricing::{black_scholes, norm_cdf, norm_pdf, years_from_dte, Greeks};
+use crate::single_options::OptionType;
+use crate::spreads::{OptionSpread, SpreadLeg};
+use crate::trades::Expectation;
+use crate::trades::OrderAction;
+use crate::vol_surface::VolSurface;
+use crate::EXPECTATION_NEUTRAL_DELTA;
+use crate::PIN_DISTANCE_PERCENT;
+use crate::RISK_FREE_RATE;
+use chrono::NaiveDate;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+const GRID_STEPS: usize = 2000;
+const EXPECTATION_STEPS: usize = 400;
+
+/// Risk profile of a set of legs at the first expiration, with later legs still carrying
+/// time value priced off the surface.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Payoff {
+    pub max_profit: f64,
+    pub max_loss: f64,
+    pub breakevens: Vec<f64>,
+    pub probability_of_profit: f64,
+    pub greeks: Greeks,
+}
+impl Payoff {
+    /// `stock_shares` is a signed share count tied to the legs and `stock_price` what was paid
+    /// for it. Unbounded profit or loss is reported as infinity.
+    pub fn from_legs(
+        legs: &[SpreadLeg],
+        stock_shares: f64,
+        stock_price: f64,
+        surface: &VolSurface,
+        underlying: f64,
+    ) -> Option<Self> {
+        if legs.is_empty() || underlying <= 0.0 {
+            return None;
+        }
+        let as_of = surface.as_of();
+        let dte = |expiry: &str| {
+            NaiveDate::parse_from_str(expiry, "%F")
+                .map(|date| (date - as_of).num_days())
+                .unwrap_or(0)
+        };
+        let dtes = leg_dtes(legs, as_of);
+        let front_dte = *dtes.iter().min()?;
+        let leg_iv = |leg: &SpreadLeg| surface.iv(&leg.expiry, leg.strike).unwrap_or(leg.iv);
+        let pnl_at = |price: f64| -> f64 {
+            options_pnl(legs, &dtes, front_dte, surface, price)
+                + stock_shares * (price - stock_price)
+        };
+        let top = 3.0 * legs.iter().map(|leg| leg.strike).fold(underlying, f64::max);
+        // with every leg expiring together the P&L is linear between strikes, so the strikes
+        // are enough; later-dated legs still carry time value and need the full grid
+        let steps = if dtes.iter().all(|dte| *dte == front_dte) {
+            1
+        } else {
+            GRID_STEPS
+        };
+        let mut grid = (0..=steps)
+            .map(|step| top * step as f64 / steps as f64)
+            .chain(legs.iter().map(|leg| leg.strike))
+            .collect_vec();
+        grid.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+        grid.dedup();
+        let pnl = grid.iter().map(|price| pnl_at(*price)).collect_vec();
+        let mut max_profit = pnl.iter().cloned().fold(f64::MIN, f64::max);
+        let mut max_loss = -pnl.iter().cloned().fold(f64::MAX, f64::min);
+        let upside_slope = pnl_at(2.0 * top) - pnl_at(top);
+        if upside_slope > 1.0 {
+            max_profit = f64::INFINITY
+        } else if upside_slope < -1.0 {
+            max_loss = f64::INFINITY
+        }
+        let mut crossings: Vec<f64> = Vec::new();
+        for n in 1..grid.len() {
+            let (low, high) = (pnl[n - 1], pnl[n]);
+            if (low < 0.0 && high >= 0.0) || (low > 0.0 && high <= 0.0) {
+                let fraction = low / (low - high);
+                crossings.push(grid[n - 1] + fraction * (grid[n] - grid[n - 1]));
+            }
+        }
+        let mut breakevens = crossings
+            .iter()
+            .map(|price| round_to_decimals(*price, 2))
+            .collect_vec();
+        breakevens.dedup();
+        // profitable stretches start and end at the breakevens
+        let mut edges = grid.iter().chain(crossings.iter()).cloned().collect_vec();
+        edges.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+        edges.dedup();
+        let atm_iv = legs
+            .iter()
+            .min_by_key(|leg| dte(&leg.expiry))
+            .and_then(|leg| surface.iv(&leg.expiry, underlying))
+            .unwrap_or_else(|| legs.iter().map(|leg| leg.iv).sum::<f64>() / legs.len() as f64);
+        let years = years_from_dte(front_dte);
+        let below = |price: f64| -> f64 {
+            if price <= 0.0 || atm_iv <= 0.0 {
+                return 0.0;
+            }
+            let drift = (RISK_FREE_RATE / 100.0 - 0.5 * atm_iv * atm_iv) * years;
+            norm_cdf(((price / underlying).ln() - drift) / (atm_iv * years.sqrt()))
+        };
+        let mut probability_of_profit: f64 = (1..edges.len())
+            .filter(|n| pnl_at(0.5 * (edges[n - 1] + edges[*n])) > 0.0)
+            .map(|n| below(edges[n]) - below(edges[n - 1]))
+            .sum();
+        if pnl_at(2.0 * top) > 0.0 {
+            probability_of_profit += 1.0 - below(*edges.last()?);
+        }
+        let greeks = legs.iter().fold(
+            Greeks {
+                delta: stock_shares,
+                ..Greeks::default()
+            },
+            |total, leg| {
+                let leg_greeks = black_scholes(
+                    leg.option_type,
+                    underlying,
+                    leg.strike,
+                    years_from_dte(dte(&leg.expiry)),
+                    leg_iv(leg),
+                );
+                let contracts = leg_sign(leg) * 100.0 * leg.size as f64;
+                Greeks {
+                    price: total.price + contracts * leg_greeks.price,
+                    delta: total.delta + contracts * leg_greeks.delta,
+                    gamma: total.gamma + contracts * leg_greeks.gamma,
+                    vega: total.vega + contracts * leg_greeks.vega,
+                    theta: total.theta + contracts * leg_greeks.theta,
+                }
+            },
+        );
+        Some(Self {
+            max_profit: round_to_decimals(max_profit, 2),
+            max_loss: round_to_decimals(max_loss, 2),
+            breakevens,
+            probability_of_profit: round_to_decimals(100.0 * probability_of_profit, 2),
+            greeks,
+        })
+    }
+}
+
+/// Average P&L of the legs at the first expiration, with the underlying lognormal at the front
+/// month's at the money vol and its mean moved `drift_sd` standard deviations (negative for
+/// down).
+pub fn expected_pnl(
+    legs: &[SpreadLeg],
+    surface: &VolSurface,
+    underlying: f64,
+    drift_sd: f64,
+) -> Option<f64> {
+    if legs.is_empty() || underlying <= 0.0 {
+        return None;
+    }
+    let dtes = leg_dtes(legs, surface.as_of());
+    let (front, front_dte) = legs
+        .iter()
+        .zip(dtes.iter().cloned())
+        .min_by_key(|(_, dte)| *dte)?;
+    let iv = surface.iv(&front.expiry, underlying).unwrap_or(front.iv);
+    let years = years_from_dte(front_dte);
+    let sd = iv * years.sqrt();
+    if sd <= 0.0 {
+        return Some(options_pnl(legs, &dtes, front_dte, surface, underlying));
+    }
+    let mean = (RISK_FREE_RATE / 100.0 - 0.5 * iv * iv) * years + drift_sd * sd;
+    // midpoint rule over five standard deviations each way
+    let (mut total, mut weights) = (0.0, 0.0);
+    for step in 0..EXPECTATION_STEPS {
+        let z = -5.0 + 10.0 * (step as f64 + 0.5) / EXPECTATION_STEPS as f64;
+        let weight = norm_pdf(z);
+        let price = underlying * (mean + sd * z).exp();
+        total += weight * options_pnl(legs, &dtes, front_dte, surface, price);
+        weights += weight;
+    }
+    Some(total / weights)
+}
+
+fn leg_dtes(legs: &[SpreadLeg], as_of: NaiveDate) -> Vec<i64> {
+    legs.iter()
+        .map(|leg| {
+            NaiveDate::parse_from_str(&leg.expiry, "%F")
+                .map(|date| (date - as_of).num_days())
+                .unwrap_or(0)
+        })
+        .collect()
+}
+
+/// P&L of the option legs at the front expiration for one underlying price, legs expiring later
+/// valued off the surface.
+fn options_pnl(
+    legs: &[SpreadLeg],
+    dtes: &[i64],
+    front_dte: i64,
+    surface: &VolSurface,
+    price: f64,
+) -> f64 {
+    legs.iter()
+        .zip(dtes)
+        .map(|(leg, dte)| {
+            let remaining = dte - front_dte;
+            let value = if remaining <= 0 {
+                intrinsic(leg.option_type, price, leg.strike)
+            } else {
+                black_scholes(
+                    leg.option_type,
+                    price,
+                    leg.strike,
+                    years_from_dte(remaining),
+                    surface.iv(&leg.expiry, leg.strike).unwrap_or(leg.iv),
+                )
+                .price
+            };
+            leg_sign(leg) * 100.0 * leg.size as f64 * (value - leg.price.unwrap_or(0.0))
+        })
+        .sum()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum VolatilityView {
+    #[serde(rename(serialize = "Long Vol"))]
+    LongVol,
+    #[serde(rename(serialize = "Short Vol"))]
+    ShortVol,
+    Flat,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum TimeView {
+    #[serde(rename(serialize = "Collects Decay"))]
+    CollectsDecay,
+    #[serde(rename(serialize = "Pays Decay"))]
+    PaysDecay,
+    Flat,
+}
+
+/// What a position is betting on, read from its greeks rather than from leg directions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outlook {
+    pub direction: Expectation,
+    pub volatility: VolatilityView,
+    pub time: TimeView,
+    pub pin_strike: Option<f64>,
+}
+impl Outlook {
+    /// Delta within `EXPECTATION_NEUTRAL_DELTA` of the largest leg's share equivalent counts
+    /// as neutral. A short gamma position with a sold strike within `PIN_DISTANCE_PERCENT` of the
+    /// underlying is pinning on that strike.
+    pub fn from_greeks(
+        legs: &[SpreadLeg],
+        stock_shares: f64,
+        greeks: &Greeks,
+        underlying: f64,
+    ) -> Self {
+        let largest_leg_shares = stock_shares.abs()
+            + legs
+                .iter()
+                .map(|leg| 100.0 * leg.size as f64)
+                .fold(0.0, f64::max);
+        let direction = if largest_leg_shares == 0.0 {
+            Expectation::Unknown
+        } else if greeks.delta.abs() <= EXPECTATION_NEUTRAL_DELTA * largest_leg_shares {
+            Expectation::Neutral
+        } else if greeks.delta > 0.0 {
+            Expectation::Bullish
+        } else {
+            Expectation::Bearish
+        };
+        let volatility = if greeks.vega > 0.005 {
+            VolatilityView::LongVol
+        } else if greeks.vega < -0.005 {
+            VolatilityView::ShortVol
+        } else {
+            VolatilityView::Flat
+        };
+        let time = if greeks.theta > 0.005 {
+            TimeView::CollectsDecay
+        } else if greeks.theta < -0.005 {
+            TimeView::PaysDecay
+        } else {
+            TimeView::Flat
+        };
+        let pin_strike = if greeks.gamma < 0.0 {
+            legs.iter()
+                .filter(|leg| leg.side == OrderAction::Sold)
+                .map(|leg| leg.strike)
+                .filter(|strike| {
+                    100.0 * (strike - underlying).abs() / underlying <= PIN_DISTANCE_PERCENT
+                })
+                .min_by(|a, b| {
+                    (a - underlying)
+                        .abs()
+                        .partial_cmp(&(b - underlying).abs())
+                        .unwrap()
+                })
+        } else {
+            None
+        };
+        Self {
+            direction,
+            volatility,
+            time,
+            pin_strike,
+        }
+    }
+    /// Reads like `Neutral, Short Vol, Collects Decay, Pin 470`.
+    pub fn describe(&self) -> String {
+        let mut parts = vec![format!("{:?}", self.direction)];
+        match self.volatility {
+            VolatilityView::LongVol => parts.push("Long Vol".to_string()),
+            VolatilityView::ShortVol => parts.push("Short Vol".to_string()),
+            VolatilityView::Flat => {}
+        }
+        match self.time {
+            TimeView::CollectsDecay => parts.push("Collects Decay".to_string()),
+            TimeView::PaysDecay => parts.push("Pays Decay".to_string()),
+            TimeView::Flat => {}
+        }
+        if let Some(strike) = self.pin_strike {
+            parts.push(format!("Pin {}", strike))
+        }
+        parts.join(", ")
+    }
+}
+
+fn intrinsic(kind: OptionType, underlying: f64, strike: f64) -> f64 {
+    match kind {
+        OptionType::Call => (underlying - strike).max(0.0),
+        OptionType::Put => (strike - underlying).max(0.0),
+    }
+}
+
+fn leg_sign(leg: &SpreadLeg) -> f64 {
+    match leg.side {
+        OrderAction::Bought => 1.0,
+        OrderAction::Sold => -1.0,
+        OrderAction::Unknown => 0.0,
+    }
+}
+
+impl OptionSpread {
+    pub fn with_payoff(self, surface: &VolSurface, underlying: f64) -> Self {
+        let (stock_shares, stock_price) = match &self.stock_leg {
+            Some(stock) => (stock.signed_shares(), stock.price),
+            None => (0.0, underlying),
+        };
+        match Payoff::from_legs(&self.legs, stock_shares, stock_price, surface, underlying) {
+            Some(payoff) => {
+                let outlook =
+                    Outlook::from_greeks(&self.legs, stock_shares, &payoff.greeks, underlying);
+                Self {
+                    expectation: outlook.direction,
+                    volatility_view: outlook.volatility,
+                    time_view: outlook.time,
+                    pin_strike: outlook.pin_strike,
+                    outlook: outlook.describe(),
+                    max_profit: payoff.max_profit,
+                    max_loss: payoff.max_loss,
+                    breakevens: payoff.breakevens.iter().join("|"),
+                    probability_of_profit: payoff.probability_of_profit,
+                    position_delta: round_to_decimals(payoff.greeks.delta, 2),
+                    position_gamma: round_to_decimals(payoff.greeks.gamma, 4),
+                    position_vega: round_to_decimals(payoff.greeks.vega, 2),
+                    position_theta: round_to_decimals(payoff.greeks.theta, 2),
+                    ..self
+                }
+            }
+            None => self,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixture_trades;
+    use crate::spreads::get_spreads;
+
+    #[test]
+    fn prices_vertical_payoff() {
+        let trades = fixture_trades(include_str!("../tests/fixtures/vertical.json"));
+        // a month before the fixture's expiration
+        let surface = VolSurface {
+            as_of: Some(NaiveDate::from_ymd(2029, 12, 19)),
+            ..VolSurface::default()
+        };
+        let spread = get_spreads(trades).remove(0).with_payoff(&surface, 464.905);
+        assert_eq!(spread.max_loss, 230000.0);
+        assert_eq!(spread.max_profit, 270000.0);
+        assert_eq!(spread.breakevens, "474.6");
+        assert!(spread.probability_of_profit > 0.0 && spread.probability_of_profit < 100.0);
+        assert!(spread.position_delta > 0.0);
+        assert_eq!(spread.expectation, Expectation::Bullish);
+    }
+
+    #[test]
+    fn prices_iron_condor_payoff() {
+        let trades = fixture_trades(include_str!("../tests/fixtures/iron_condor.json"));
+        // a month before the fixture's expiration
+        let surface = VolSurface {
+            as_of: Some(NaiveDate::from_ymd(2029, 12, 19)),
+            ..VolSurface::default()
+        };
+        // the underlying where the wings' deltas balance
+        let spread = get_spreads(trades).remove(0).with_payoff(&surface, 463.0);
+        assert_eq!(spread.spread_name, crate::spreads::SpreadName::IronCondoor);
+        // 5.65 credit on 10 wide wings, 400 contracts
+        assert_eq!(spread.max_profit, 226000.0);
+        assert_eq!(spread.max_loss, 174000.0);
+        assert_eq!(spread.breakevens, "434.35|495.65");
+        assert!(spread.probability_of_profit > 50.0 && spread.probability_of_profit < 100.0);
+        assert!(spread.position_vega < 0.0 && spread.position_theta > 0.0);
+        assert_eq!(spread.expectation, Expectation::Neutral);
+        assert_eq!(spread.outlook, "Neutral, Short Vol, Collects Decay");
+    }
+
+    #[test]
+    fn reads_short_vol_outlook_from_greeks() {
+        let trades = fixture_trades(include_str!("../tests/fixtures/vertical.json"));
+        let template = get_spreads(trades).remove(0).legs.remove(0);
+        let leg = |strike: f64, side: OrderAction| SpreadLeg {
+            strike,
+            side,
+            size: 10,
+            ..template.clone()
+        };
+        let legs = vec![
+            leg(460.0, OrderAction::Bought),
+            leg(470.0, OrderAction::Sold),
+            leg(470.0, OrderAction::Sold),
+            leg(480.0, OrderAction::Bought),
+        ];
+        let greeks = Greeks {
+            delta: 6.0,
+            gamma: -8.5,
+            vega: -310.0,
+            theta: 95.0,
+            ..Greeks::default()
+        };
+        let outlook = Outlook::from_greeks(&legs, 0.0, &greeks, 468.0);
+        assert_eq!(outlook.direction, Expectation::Neutral);
+        assert_eq!(outlook.pin_strike, Some(470.0));
+        assert_eq!(
+            outlook.describe(),
+            "Neutral, Short Vol, Collects Decay, Pin 470"
+        );
+    }
+}
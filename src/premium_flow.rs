@@ -0,0 +1,126 @@
+use crate::others::round_to_decimals;
+use crate::trades::OptionTrade;
+use crate::trades::OrderAction;
+
+/// Greek-weighted and direction-split flow for one chain's trades.
+/// All values are signed from the trader's side: buying adds, selling subtracts.
+#[derive(Debug, Clone, Default)]
+pub struct PremiumFlow {
+    pub delta_adjusted_notional: f64,
+    pub gamma_weighted_notional: f64,
+    pub vega_weighted_notional: f64,
+    pub bullish_premium: f64,
+    pub bearish_premium: f64,
+    pub bullish_bought_premium: f64,
+    pub bullish_sold_premium: f64,
+    pub bearish_bought_premium: f64,
+    pub bearish_sold_premium: f64,
+    pub bullish_opening_premium: f64,
+    pub bearish_opening_premium: f64,
+}
+impl PremiumFlow {
+    pub fn from_trades(trades: &[OptionTrade], underlying_mid: f64) -> Self {
+        let mut flow = Self::default();
+        for trade in trades {
+            if trade.cancel_flag != 0 || trade.condition_id.is_cancel() {
+                continue;
+            }
+            let underlying = if trade.implied_underlying_mid > 0.0 {
+                trade.implied_underlying_mid
+            } else {
+                underlying_mid
+            };
+            let signed_contracts = match trade.order_action {
+                OrderAction::Bought => trade.option_trade_size as f64,
+                OrderAction::Sold => -trade.option_trade_size as f64,
+                OrderAction::Unknown => 0.0,
+            };
+            flow.delta_adjusted_notional += trade.net_delta() * underlying;
+            // dollar gamma for a 1% move in the underlying
+            flow.gamma_weighted_notional +=
+                signed_contracts * 100.0 * trade.current_gamma * underlying * underlying * 0.01;
+            // dollars per vol point
+            flow.vega_weighted_notional += signed_contracts * 100.0 * trade.current_vega;
+            let premium = trade.notional_value;
+            let bullish = trade.is_call_buy() || trade.is_put_sell();
+            let bearish = trade.is_put_buy() || trade.is_call_sell();
+            if bullish {
+                flow.bullish_premium += premium;
+                if trade.is_buy() {
+                    flow.bullish_bought_premium += premium
+                } else {
+                    flow.bullish_sold_premium += premium
+                }
+                if trade.is_opening() {
+                    flow.bullish_opening_premium += premium
+                }
+            } else if bearish {
+                flow.bearish_premium += premium;
+                if trade.is_buy() {
+                    flow.bearish_bought_premium += premium
+                } else {
+                    flow.bearish_sold_premium += premium
+                }
+                if trade.is_opening() {
+                    flow.bearish_opening_premium += premium
+                }
+            }
+        }
+        flow.round()
+    }
+    fn round(self) -> Self {
+        Self {
+            delta_adjusted_notional: round_to_decimals(self.delta_adjusted_notional, 2),
+            gamma_weighted_notional: round_to_decimals(self.gamma_weighted_notional, 2),
+            vega_weighted_notional: round_to_decimals(self.vega_weighted_notional, 2),
+            bullish_premium: round_to_decimals(self.bullish_premium, 2),
+            bearish_premium: round_to_decimals(self.bearish_premium, 2),
+            bullish_bought_premium: round_to_decimals(self.bullish_bought_premium, 2),
+            bullish_sold_premium: round_to_decimals(self.bullish_sold_premium, 2),
+            bearish_bought_premium: round_to_decimals(self.bearish_bought_premium, 2),
+            bearish_sold_premium: round_to_decimals(self.bearish_sold_premium, 2),
+            bullish_opening_premium: round_to_decimals(self.bullish_opening_premium, 2),
+            bearish_opening_premium: round_to_decimals(self.bearish_opening_premium, 2),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixture_trades;
+    use crate::trades::TransactionType;
+
+    #[test]
+    fn splits_premium_by_direction() {
+        let mut trades = fixture_trades(include_str!("../tests/fixtures/vertical.json"));
+        trades[0].transaction_estimate = TransactionType::BuyToOpen;
+        trades[1].transaction_estimate = TransactionType::MaybeSellToClose;
+        trades[2].order_action = OrderAction::Sold;
+        trades[2].transaction_estimate = TransactionType::SellToOpen;
+        let mut put_bought = trades[2].clone();
+        put_bought.order_action = OrderAction::Bought;
+        put_bought.transaction_estimate = TransactionType::MaybeBuyToClose;
+        trades.push(put_bought);
+        let mut cancelled = trades[0].clone();
+        cancelled.cancel_flag = 1;
+        trades.push(cancelled);
+        let flow = PremiumFlow::from_trades(&trades, 464.905);
+        // call bought and put sold
+        assert_eq!(flow.bullish_bought_premium, 620000.0);
+        assert_eq!(flow.bullish_sold_premium, 36600.0);
+        assert_eq!(flow.bullish_premium, 656600.0);
+        assert_eq!(flow.bullish_opening_premium, 656600.0);
+        // call sold and put bought
+        assert_eq!(flow.bearish_sold_premium, 390000.0);
+        assert_eq!(flow.bearish_bought_premium, 36600.0);
+        assert_eq!(flow.bearish_premium, 426600.0);
+        assert_eq!(flow.bearish_opening_premium, 0.0);
+        // the two puts offset, leaving the vertical's long delta
+        assert!(flow.delta_adjusted_notional > 0.0);
+        assert_eq!(
+            flow.delta_adjusted_notional,
+            round_to_decimals((trades[0].net_delta() + trades[1].net_delta()) * 464.905, 2)
+        );
+    }
+}
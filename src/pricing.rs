@@ -0,0 +1,133 @@
+use crate::single_options::OptionType;
+use crate::RISK_FREE_RATE;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Greeks {
+    pub price: f64,
+    pub delta: f64,
+    pub gamma: f64,
+    /// change in price for one vol point
+    pub vega: f64,
+    /// change in price for one calendar day
+    pub theta: f64,
+}
+
+pub fn norm_pdf(x: f64) -> f64 {
+    (-0.5 * x * x).exp() / (2.0 * std::f64::consts::PI).sqrt()
+}
+
+/// Abramowitz and Stegun 26.2.17, accurate to about 7.5e-8.
+pub fn norm_cdf(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.2316419 * x.abs());
+    let poly = t
+        * (0.319381530
+            + t * (-0.356563782 + t * (1.781477937 + t * (-1.821255978 + t * 1.330274429))));
+    let tail = norm_pdf(x) * poly;
+    if x >= 0.0 {
+        1.0 - tail
+    } else {
+        tail
+    }
+}
+
+/// Years to expiration from days, floored at half a day so same-day options still price.
+pub fn years_from_dte(dte: i64) -> f64 {
+    (dte as f64).max(0.5) / 365.0
+}
+
+/// Black-Scholes value and greeks per share, using `RISK_FREE_RATE` and no dividends.
+pub fn black_scholes(
+    kind: OptionType,
+    underlying: f64,
+    strike: f64,
+    years: f64,
+    iv: f64,
+) -> Greeks {
+    let intrinsic = match kind {
+        OptionType::Call => (underlying - strike).max(0.0),
+        OptionType::Put => (strike - underlying).max(0.0),
+    };
+    if underlying <= 0.0 || strike <= 0.0 || years <= 0.0 || iv <= 0.0 {
+        let itm = intrinsic > 0.0;
+        return Greeks {
+            price: intrinsic,
+            delta: match kind {
+                OptionType::Call if itm => 1.0,
+                OptionType::Put if itm => -1.0,
+                _ => 0.0,
+            },
+            ..Greeks::default()
+        };
+    }
+    let rate = RISK_FREE_RATE / 100.0;
+    let sqrt_years = years.sqrt();
+    let d1 = ((underlying / strike).ln() + (rate + 0.5 * iv * iv) * years) / (iv * sqrt_years);
+    let d2 = d1 - iv * sqrt_years;
+    let discount = (-rate * years).exp();
+    let gamma = norm_pdf(d1) / (underlying * iv * sqrt_years);
+    let vega = underlying * norm_pdf(d1) * sqrt_years / 100.0;
+    let decay = -underlying * norm_pdf(d1) * iv / (2.0 * sqrt_years);
+    match kind {
+        OptionType::Call => Greeks {
+            price: underlying * norm_cdf(d1) - strike * discount * norm_cdf(d2),
+            delta: norm_cdf(d1),
+            gamma,
+            vega,
+            theta: (decay - rate * strike * discount * norm_cdf(d2)) / 365.0,
+        },
+        OptionType::Put => Greeks {
+            price: strike * discount * norm_cdf(-d2) - underlying * norm_cdf(-d1),
+            delta: norm_cdf(d1) - 1.0,
+            gamma,
+            vega,
+            theta: (decay + rate * strike * discount * norm_cdf(-d2)) / 365.0,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() < tolerance,
+            "{} is not within {} of {}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn matches_closed_form_values() {
+        // at the money, one year, 20 vol and the 4.5% rate
+        let call = black_scholes(OptionType::Call, 100.0, 100.0, 1.0, 0.2);
+        assert_close(call.price, 10.1861, 1e-4);
+        assert_close(call.delta, 0.6274, 1e-4);
+        assert_close(call.gamma, 0.018921, 1e-6);
+        assert_close(call.vega, 0.37842, 1e-5);
+        assert_close(call.theta, -0.016847, 1e-6);
+        let put = black_scholes(OptionType::Put, 100.0, 100.0, 1.0, 0.2);
+        assert_close(put.price, 5.7859, 1e-4);
+        assert_close(put.delta, call.delta - 1.0, 1e-9);
+        // put-call parity
+        assert_close(
+            call.price - put.price,
+            100.0 - 100.0 * (-0.045f64).exp(),
+            1e-6,
+        );
+        let otm_put = black_scholes(OptionType::Put, 464.905, 480.0, 0.5, 0.21);
+        assert_close(otm_put.price, 29.9100, 1e-4);
+        assert_close(otm_put.delta, -0.49578, 1e-5);
+    }
+
+    #[test]
+    fn expired_options_are_worth_intrinsic() {
+        let call = black_scholes(OptionType::Call, 105.0, 100.0, 0.0, 0.2);
+        assert_eq!((call.price, call.delta), (5.0, 1.0));
+        let put = black_scholes(OptionType::Put, 105.0, 100.0, 0.0, 0.2);
+        assert_eq!((put.price, put.delta), (0.0, 0.0));
+        assert_eq!(years_from_dte(0), 0.5 / 365.0);
+    }
+}
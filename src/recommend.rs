@@ -0,0 +1,590 @@
+use crate::models::{OptionChain, Signal, SignalType};
+use crate::others::{load_json, round_to_decimals};
+use crate::payoff::{expected_pnl, Payoff};
+use crate::single_options::{OptionData, OptionType};
+use crate::spreads::{SpreadLeg, SpreadName};
+use crate::trades::OrderAction;
+use crate::vol_surface::VolSurface;
+use crate::{
+    RECOMMEND_EDGE_SD, RECOMMEND_MAX_SPREAD_PERCENT, RECOMMEND_MIN_OPEN_INTEREST,
+    RECOMMEND_PER_SIGNAL, RECOMMEND_RICH_VOL_RATIO, RECOMMEND_RISK_PERCENT, RECOMMEND_TARGET_DTE,
+};
+use chrono::NaiveDate;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum VolRegime {
+    /// Implied well above realized (or the front month over the back): sell premium.
+    Rich,
+    /// Implied below realized (or the front month under the back): buy premium.
+    Cheap,
+    Normal,
+}
+impl VolRegime {
+    /// Compares front month at the money vol with realized vol when there is enough price
+    /// history, and with the next expiration's at the money vol when there isn't.
+    pub fn of(
+        chain: &OptionChain,
+        surface: &VolSurface,
+        realized: Option<f64>,
+        ratio: f64,
+    ) -> Self {
+        let (front, back) = match realized {
+            Some(realized) if realized > 0.0 => (chain.atm_iv, realized),
+            _ => {
+                let mut term = surface
+                    .smiles
+                    .keys()
+                    .filter(|expiry| **expiry > surface.as_of())
+                    .filter_map(|expiry| {
+                        surface.iv(&expiry.format("%F").to_string(), chain.underlying_mid)
+                    });
+                match (term.next(), term.next()) {
+                    (Some(front), Some(back)) => (front, back),
+                    _ => return VolRegime::Normal,
+                }
+            }
+        };
+        if front <= 0.0 || back <= 0.0 {
+            VolRegime::Normal
+        } else if front / back >= ratio {
+            VolRegime::Rich
+        } else if back / front >= ratio {
+            VolRegime::Cheap
+        } else {
+            VolRegime::Normal
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct RecommendConfig {
+    pub target_dte: i64,
+    /// Absolute deltas used to pick strikes: the long leg of a debit spread, the short legs, and
+    /// the protective wings.
+    pub long_delta: f64,
+    pub short_delta: f64,
+    pub wing_delta: f64,
+    /// Percent of the account a single recommendation may lose at worst.
+    pub risk_percent: f64,
+    /// Widest bid/ask allowed on any leg, as a percent of its mid.
+    pub max_spread_percent: f64,
+    pub min_open_interest: i64,
+    /// How far, in standard deviations, the signal is assumed to move the underlying when
+    /// computing expected value.
+    pub edge_sd: f64,
+    pub rich_vol_ratio: f64,
+    pub per_signal: usize,
+}
+impl Default for RecommendConfig {
+    fn default() -> Self {
+        Self {
+            target_dte: RECOMMEND_TARGET_DTE,
+            long_delta: 0.5,
+            short_delta: 0.3,
+            wing_delta: 0.1,
+            risk_percent: RECOMMEND_RISK_PERCENT,
+            max_spread_percent: RECOMMEND_MAX_SPREAD_PERCENT,
+            min_open_interest: RECOMMEND_MIN_OPEN_INTEREST,
+            edge_sd: RECOMMEND_EDGE_SD,
+            rich_vol_ratio: RECOMMEND_RICH_VOL_RATIO,
+            per_signal: RECOMMEND_PER_SIGNAL,
+        }
+    }
+}
+impl RecommendConfig {
+    /// The defaults when there is no config file. One that doesn't parse is an error, so trades
+    /// are never proposed on limits nobody chose.
+    pub fn load(filename: &str) -> Result<Self, String> {
+        Ok(load_json(filename)?.unwrap_or_default())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Recommendation {
+    pub symbol: String,
+    pub side: SignalType,
+    pub vol_regime: VolRegime,
+    pub strategy: SpreadName,
+    pub expiration_date: String,
+    pub dte: i64,
+    /// Reads like `+1 C470 / -1 C480`, with the expiry added to calendar legs.
+    pub legs_summary: String,
+    /// Per spread at the natural price, positive for a debit.
+    pub net_price: f64,
+    pub max_profit: f64,
+    pub max_loss: f64,
+    pub breakevens: String,
+    pub probability_of_profit: f64,
+    pub expected_value: f64,
+    /// One minus the average bid/ask width as a fraction of mid across the legs.
+    pub liquidity: f64,
+    pub min_open_interest: i64,
+    pub score: f64,
+    pub contracts: i64,
+    pub total_risk: f64,
+    #[serde(skip_serializing, default)]
+    pub legs: Vec<SpreadLeg>,
+}
+
+/// Proposes defined risk option trades for each signal that fit its direction and the
+/// symbol's vol regime, best expected value per dollar of risk (discounted for wide markets)
+/// first, `per_signal` at most for each.
+pub fn recommend(
+    signals: &[Signal],
+    chains: &[OptionChain],
+    config: &RecommendConfig,
+    account_value: f64,
+    realized_vol: impl Fn(&str) -> Option<f64>,
+) -> Vec<Recommendation> {
+    let mut output: Vec<Recommendation> = Vec::new();
+    for signal in signals.iter().unique_by(|signal| &signal.symbol) {
+        let chain = match chains.iter().find(|chain| chain.symbol == signal.symbol) {
+            Some(chain) if chain.underlying_mid > 0.0 => chain,
+            _ => continue,
+        };
+        let as_of = NaiveDate::parse_from_str(&chain.date, "%D")
+            .unwrap_or_else(|_| chrono::Local::now().naive_local().date());
+        let surface = VolSurface::from_options(&chain.options, as_of);
+        let regime = VolRegime::of(
+            chain,
+            &surface,
+            realized_vol(&chain.symbol),
+            config.rich_vol_ratio,
+        );
+        let builder = Builder {
+            chain,
+            config,
+            surface: &surface,
+            bullish: signal.side == SignalType::Buy,
+        };
+        let candidates = match regime {
+            VolRegime::Rich => vec![
+                builder.credit_vertical(),
+                builder.risk_reversal(),
+                builder.skewed_condor(),
+            ],
+            VolRegime::Cheap => vec![builder.debit_vertical(), builder.calendar()],
+            VolRegime::Normal => vec![
+                builder.debit_vertical(),
+                builder.credit_vertical(),
+                builder.risk_reversal(),
+            ],
+        };
+        let risk_budget = account_value * config.risk_percent / 100.0;
+        let mut recommendations = candidates
+            .into_iter()
+            .flatten()
+            .filter_map(|(strategy, legs)| builder.evaluate(regime, strategy, legs, risk_budget))
+            .collect_vec();
+        recommendations.sort_unstable_by(|a, b| b.score.total_cmp(&a.score));
+        output.extend(recommendations.into_iter().take(config.per_signal));
+    }
+    output
+}
+
+type Candidate<'a> = Option<(SpreadName, Vec<(&'a OptionData, OrderAction)>)>;
+
+/// Picks strikes by delta in the expiration closest to `target_dte` (and the one after it for
+/// calendars), on the side of the chain the signal points to.
+struct Builder<'a> {
+    chain: &'a OptionChain,
+    config: &'a RecommendConfig,
+    surface: &'a VolSurface,
+    bullish: bool,
+}
+impl<'a> Builder<'a> {
+    /// Prices a candidate at the natural, dropping it if a leg is too illiquid, the loss isn't
+    /// bounded, or not even one contract fits the risk budget.
+    fn evaluate(
+        &self,
+        regime: VolRegime,
+        strategy: SpreadName,
+        legs: Vec<(&OptionData, OrderAction)>,
+        risk_budget: f64,
+    ) -> Option<Recommendation> {
+        let (chain, config, surface) = (self.chain, self.config, self.surface);
+        let side = if self.bullish {
+            SignalType::Buy
+        } else {
+            SignalType::Sell
+        };
+        let mut widths: Vec<f64> = Vec::new();
+        for (option, _) in &legs {
+            let (bid, ask) = (option.bid_price?, option.ask_price?);
+            let mid = 0.5 * (bid + ask);
+            if mid <= 0.0 || option.open_interest < config.min_open_interest {
+                return None;
+            }
+            widths.push((ask - bid) / mid);
+        }
+        if widths
+            .iter()
+            .any(|width| 100.0 * width > config.max_spread_percent)
+        {
+            return None;
+        }
+        let spread_legs = legs
+            .iter()
+            .map(|(option, side)| SpreadLeg::from_option(option, *side))
+            .collect_vec();
+        let payoff = Payoff::from_legs(
+            &spread_legs,
+            0.0,
+            chain.underlying_mid,
+            surface,
+            chain.underlying_mid,
+        )?;
+        if !payoff.max_loss.is_finite() || payoff.max_loss <= 0.0 {
+            return None;
+        }
+        let drift = if self.bullish {
+            config.edge_sd
+        } else {
+            -config.edge_sd
+        };
+        let expected_value = expected_pnl(&spread_legs, surface, chain.underlying_mid, drift)?;
+        let liquidity = 1.0 - widths.iter().sum::<f64>() / widths.len() as f64;
+        let contracts = (risk_budget / payoff.max_loss).floor() as i64;
+        if contracts == 0 {
+            return None;
+        }
+        let front = legs
+            .iter()
+            .map(|(option, _)| option)
+            .min_by_key(|option| option.dte)?;
+        let calendar = legs
+            .iter()
+            .map(|(option, _)| &option.expiration_date)
+            .unique()
+            .count()
+            > 1;
+        let legs_summary = legs
+            .iter()
+            .map(|(option, side)| {
+                let sign = if *side == OrderAction::Sold { "-" } else { "+" };
+                let kind = if option.kind == OptionType::Call {
+                    "C"
+                } else {
+                    "P"
+                };
+                if calendar {
+                    format!(
+                        "{}1 {}{} {}",
+                        sign, kind, option.strike, option.expiration_date
+                    )
+                } else {
+                    format!("{}1 {}{}", sign, kind, option.strike)
+                }
+            })
+            .join(" / ");
+        Some(Recommendation {
+            symbol: chain.symbol.clone(),
+            side,
+            vol_regime: regime,
+            strategy,
+            expiration_date: front.expiration_date.clone(),
+            dte: front.dte,
+            legs_summary,
+            net_price: round_to_decimals(
+                spread_legs
+                    .iter()
+                    .map(|leg| match leg.side {
+                        OrderAction::Sold => -leg.price.unwrap_or(0.0),
+                        _ => leg.price.unwrap_or(0.0),
+                    })
+                    .sum(),
+                2,
+            ),
+            max_profit: payoff.max_profit,
+            max_loss: payoff.max_loss,
+            breakevens: payoff.breakevens.iter().join("|"),
+            probability_of_profit: payoff.probability_of_profit,
+            expected_value: round_to_decimals(expected_value, 2),
+            liquidity: round_to_decimals(liquidity, 4),
+            min_open_interest: legs
+                .iter()
+                .map(|(option, _)| option.open_interest)
+                .min()
+                .unwrap_or(0),
+            score: round_to_decimals(liquidity * expected_value / payoff.max_loss, 4),
+            contracts,
+            total_risk: round_to_decimals(contracts as f64 * payoff.max_loss, 2),
+            legs: spread_legs,
+        })
+    }
+    fn expirations(&self) -> Vec<i64> {
+        self.chain
+            .options
+            .iter()
+            .map(|option| option.dte)
+            .filter(|dte| *dte > 0)
+            .unique()
+            .sorted()
+            .collect()
+    }
+    fn front_dte(&self) -> Option<i64> {
+        self.expirations()
+            .into_iter()
+            .min_by_key(|dte| (dte - self.config.target_dte).abs())
+    }
+    /// The option of a kind nearest an absolute delta, optionally only strikes beyond `past`
+    /// in the out of the money direction.
+    fn by_delta(
+        &self,
+        dte: i64,
+        kind: OptionType,
+        delta: f64,
+        past: Option<f64>,
+    ) -> Option<&'a OptionData> {
+        self.chain
+            .options
+            .iter()
+            .filter(|option| option.dte == dte && option.kind == kind && option.delta != 0.0)
+            .filter(|option| match (past, kind) {
+                (Some(strike), OptionType::Call) => option.strike > strike,
+                (Some(strike), OptionType::Put) => option.strike < strike,
+                (None, _) => true,
+            })
+            .min_by(|a, b| {
+                (a.delta.abs() - delta)
+                    .abs()
+                    .total_cmp(&(b.delta.abs() - delta).abs())
+            })
+    }
+    fn with_kind(&self) -> OptionType {
+        if self.bullish {
+            OptionType::Call
+        } else {
+            OptionType::Put
+        }
+    }
+    fn against_kind(&self) -> OptionType {
+        if self.bullish {
+            OptionType::Put
+        } else {
+            OptionType::Call
+        }
+    }
+    /// Buys near `long_delta` and sells the `short_delta` strike further out.
+    fn debit_vertical(&self) -> Candidate<'a> {
+        let dte = self.front_dte()?;
+        let long = self.by_delta(dte, self.with_kind(), self.config.long_delta, None)?;
+        let short = self.by_delta(
+            dte,
+            self.with_kind(),
+            self.config.short_delta,
+            Some(long.strike),
+        )?;
+        Some((
+            SpreadName::Vertical,
+            vec![(long, OrderAction::Bought), (short, OrderAction::Sold)],
+        ))
+    }
+    /// Sells the `short_delta` strike on the side the signal is moving away from and buys the
+    /// `wing_delta` strike behind it.
+    fn credit_vertical(&self) -> Candidate<'a> {
+        let dte = self.front_dte()?;
+        let short = self.by_delta(dte, self.against_kind(), self.config.short_delta, None)?;
+        let wing = self.by_delta(
+            dte,
+            self.against_kind(),
+            self.config.wing_delta,
+            Some(short.strike),
+        )?;
+        Some((
+            SpreadName::Vertical,
+            vec![(short, OrderAction::Sold), (wing, OrderAction::Bought)],
+        ))
+    }
+    /// Sells the `short_delta` option against the view, buys the `wing_delta` strike behind it
+    /// so the loss is bounded, and buys the `short_delta` option with the view.
+    fn risk_reversal(&self) -> Candidate<'a> {
+        let dte = self.front_dte()?;
+        let short = self.by_delta(dte, self.against_kind(), self.config.short_delta, None)?;
+        let wing = self.by_delta(
+            dte,
+            self.against_kind(),
+            self.config.wing_delta,
+            Some(short.strike),
+        )?;
+        let long = self.by_delta(dte, self.with_kind(), self.config.short_delta, None)?;
+        Some((
+            SpreadName::RiskReversal,
+            vec![
+                (wing, OrderAction::Bought),
+                (short, OrderAction::Sold),
+                (long, OrderAction::Bought),
+            ],
+        ))
+    }
+    /// An iron condor with the short strike on the signal's side placed further out
+    /// (`wing_delta`) than the one against it (`short_delta`), leaving room to run.
+    fn skewed_condor(&self) -> Candidate<'a> {
+        let dte = self.front_dte()?;
+        let near = self.by_delta(dte, self.against_kind(), self.config.short_delta, None)?;
+        let near_wing = self.by_delta(
+            dte,
+            self.against_kind(),
+            self.config.wing_delta / 2.0,
+            Some(near.strike),
+        )?;
+        let far = self.by_delta(dte, self.with_kind(), self.config.wing_delta, None)?;
+        let far_wing = self.by_delta(
+            dte,
+            self.with_kind(),
+            self.config.wing_delta / 2.0,
+            Some(far.strike),
+        )?;
+        Some((
+            SpreadName::IronCondoor,
+            vec![
+                (near_wing, OrderAction::Bought),
+                (near, OrderAction::Sold),
+                (far, OrderAction::Sold),
+                (far_wing, OrderAction::Bought),
+            ],
+        ))
+    }
+    /// Sells the front month and buys the next one at the `short_delta` strike in the
+    /// signal's direction, so the position gains as the underlying moves toward it.
+    fn calendar(&self) -> Candidate<'a> {
+        let front_dte = self.front_dte()?;
+        let back_dte = self
+            .expirations()
+            .into_iter()
+            .find(|dte| *dte > front_dte)?;
+        let short = self.by_delta(front_dte, self.with_kind(), self.config.short_delta, None)?;
+        let long = self.chain.options.iter().find(|option| {
+            option.dte == back_dte && option.kind == short.kind && option.strike == short.strike
+        })?;
+        Some((
+            SpreadName::Calendar,
+            vec![(short, OrderAction::Sold), (long, OrderAction::Bought)],
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixture_chain;
+
+    #[test]
+    fn recommends_bullish_structures() {
+        let mut chain = fixture_chain("SPY");
+        let put = chain
+            .options
+            .iter()
+            .find(|option| option.kind == OptionType::Put)
+            .unwrap()
+            .clone();
+        chain.options.push(OptionData {
+            symbol: put.symbol.replace("455", "440"),
+            strike: 440.0,
+            delta: -0.12,
+            bid_price: Some(1.9),
+            ask_price: Some(2.0),
+            ..put
+        });
+        let signal = Signal {
+            symbol: "SPY".to_string(),
+            side: SignalType::Buy,
+            ..Signal::default()
+        };
+        let config = RecommendConfig {
+            risk_percent: 2.0,
+            ..RecommendConfig::default()
+        };
+        let recommendations = recommend(
+            std::slice::from_ref(&signal),
+            std::slice::from_ref(&chain),
+            &config,
+            25000.0,
+            |_| None,
+        );
+        let vertical = recommendations
+            .iter()
+            .find(|item| item.strategy == SpreadName::Vertical)
+            .unwrap();
+        assert_eq!(vertical.vol_regime, VolRegime::Normal);
+        assert_eq!(vertical.legs_summary, "+1 C470 / -1 C480");
+        assert_eq!(vertical.net_price, 4.6);
+        assert_eq!(vertical.max_loss, 460.0);
+        assert_eq!(vertical.max_profit, 540.0);
+        assert_eq!(vertical.contracts, 1);
+        assert!(vertical.expected_value > 0.0);
+        assert!(recommendations
+            .windows(2)
+            .all(|pair| pair[0].score >= pair[1].score));
+        // the put spread under the risk reversal risks more than 2% of this account allows
+        assert!(recommendations
+            .iter()
+            .all(|item| item.contracts > 0 && item.strategy != SpreadName::RiskReversal));
+        let large_account = recommend(
+            std::slice::from_ref(&signal),
+            std::slice::from_ref(&chain),
+            &config,
+            5000000.0,
+            |_| None,
+        );
+        let reversal = large_account
+            .iter()
+            .find(|item| item.strategy == SpreadName::RiskReversal)
+            .unwrap();
+        assert_eq!(reversal.legs_summary, "+1 P440 / -1 P455 / +1 C480");
+        assert_eq!(reversal.max_loss, 1580.0);
+        assert!(reversal.contracts > 0);
+        // without a strike to protect the short put there is no risk reversal at all
+        let no_wing = recommend(
+            std::slice::from_ref(&signal),
+            &[fixture_chain("SPY")],
+            &config,
+            5000000.0,
+            |_| None,
+        );
+        assert!(no_wing
+            .iter()
+            .all(|item| item.strategy != SpreadName::RiskReversal));
+        let small_account = recommend(&[signal], &[chain], &config, 5000.0, |_| None);
+        assert!(small_account
+            .iter()
+            .all(|item| item.strategy != SpreadName::Vertical));
+    }
+
+    #[test]
+    fn regime_from_realized_vol() {
+        let chain = OptionChain {
+            atm_iv: 0.3,
+            ..fixture_chain("SPY")
+        };
+        let surface = VolSurface::default();
+        assert_eq!(
+            VolRegime::of(&chain, &surface, Some(0.2), 1.2),
+            VolRegime::Rich
+        );
+        assert_eq!(
+            VolRegime::of(&chain, &surface, Some(0.4), 1.2),
+            VolRegime::Cheap
+        );
+        assert_eq!(
+            VolRegime::of(&chain, &surface, None, 1.2),
+            VolRegime::Normal
+        );
+    }
+
+    #[test]
+    fn refuses_malformed_config() {
+        let filename = std::env::temp_dir().join("malformed_recommend_config.json");
+        std::fs::write(&filename, r#"{"per_signal": "two"}"#).unwrap();
+        let result = RecommendConfig::load(filename.to_str().unwrap());
+        std::fs::remove_file(&filename).unwrap();
+        assert!(result.is_err());
+        assert_eq!(
+            RecommendConfig::load("no_such_recommend_config.json"),
+            Ok(RecommendConfig::default())
+        );
+    }
+}
@@ -5,7 +5,7 @@ use crate::{
     },
     others::{create_json_file, delete_file, open_json},
     trades::OptionTrade,
-    TRADES_TO_INCLUDE,
+    STREAM_BATCH_LIMIT, TRADES_TO_INCLUDE,
 };
 use reqwest::Response;
 use serde_json::{json, Value};
@@ -53,9 +53,12 @@ async fn request_token() -> Result<String, reqwest::Error> {
 
 // OPTIONS
 
-async fn get_options(symbol: &str, token: &str) -> Result<reqwest::Response, reqwest::Error> {
-    let date = chrono::Local::now().date().format("%F").to_string();
-    let mut query = vec![("symbol", symbol), ("date", &date)];
+pub async fn get_options(
+    symbol: &str,
+    token: &str,
+    date: &str,
+) -> Result<reqwest::Response, reqwest::Error> {
+    let mut query = vec![("symbol", symbol), ("date", date)];
     if !symbol.contains('^') {
         query.push(("root", symbol))
     }
@@ -72,7 +75,8 @@ pub async fn get_options_mt(data: OptionRW) {
     let token = reader.cboe_token.clone();
     let symbol = reader.symbol.clone();
     drop(reader);
-    if let Ok(response) = get_options(&symbol, &token).await {
+    let date = chrono::Local::now().date().format("%F").to_string();
+    if let Ok(response) = get_options(&symbol, &token, &date).await {
         println!(
             "Options Request {} HTTP Status: {}, CBOE Request points used {}",
             symbol,
@@ -156,6 +160,54 @@ pub async fn get_trades(symbol: &str, token: &str) -> Result<Response, reqwest::
         .send()
         .await?)
 }
+/// The trades after `after_seq_no` in sequence order, at most `STREAM_BATCH_LIMIT` of them, for
+/// polling during the session. Callers page forward until a short page comes back.
+pub async fn get_trades_after(symbol: &str, token: &str, after_seq_no: i64) -> Vec<OptionTrade> {
+    let min_seq_no = (after_seq_no + 1).to_string();
+    let query = vec![
+        ("symbol", symbol),
+        ("order_by", "TIME_ASC"),
+        ("min_seq_no", &min_seq_no),
+        ("limit", STREAM_BATCH_LIMIT),
+    ];
+    let response = reqwest::Client::new()
+        .get("https://api.livevol.com/v1/live/allaccess/market/all-option-trades")
+        .bearer_auth(token)
+        .query(&query)
+        .send()
+        .await;
+    match response {
+        Ok(response) => match response.json::<Vec<OptionTrade>>().await {
+            Ok(trades) => trades,
+            Err(e) => {
+                println!("error {} parsing latest trades for {}", e, symbol);
+                Vec::new()
+            }
+        },
+        Err(e) => {
+            println!("could not obtain latest trades: {:#?}", e);
+            Vec::new()
+        }
+    }
+}
+pub async fn get_trades_for_date(
+    symbol: &str,
+    token: &str,
+    date: &str,
+) -> Result<Response, reqwest::Error> {
+    let query = vec![
+        ("symbol", symbol),
+        ("date", date),
+        ("order_by", "SIZE_DESC"),
+        ("limit", TRADES_TO_INCLUDE),
+    ];
+    reqwest::Client::new()
+        .get("https://api.livevol.com/v1/live/allaccess/market/all-option-trades")
+        .bearer_auth(token)
+        .query(&query)
+        .send()
+        .await
+}
 // DIVIDENDS
 async fn get_dividend_info(symbol: &str) -> Result<Value, reqwest::Error> {
     let today_date = chrono::Local::now().naive_local().date();
@@ -291,6 +343,70 @@ pub async fn get_short_ratio(symbol: &str) -> Result<f64, reqwest::Error> {
     }
 }
 
+pub async fn get_market_cap(symbol: &str) -> Result<f64, reqwest::Error> {
+    let value = reqwest::Client::new()
+        .get(format!(
+            "https://eodhistoricaldata.com/api/fundamentals/{}.US",
+            symbol
+        ))
+        .query(&[
+            ("api_token", EOD_API_TOKEN),
+            ("filter", "Highlights::MarketCapitalization"),
+            ("fmt", "json"),
+        ])
+        .send()
+        .await?
+        .json::<Value>()
+        .await?;
+    Ok(value.as_f64().unwrap_or(0.0))
+}
+
+pub async fn get_market_cap_mt(data: Arc<RwLock<(&str, f64)>>) {
+    let symbol = data.read().unwrap().0;
+    if symbol.contains('^') {
+        return;
+    }
+    if let Ok(market_cap) = get_market_cap(symbol).await {
+        if data.try_write().is_err() {
+            println!("no write on market cap")
+        }
+        let mut write = data.write().unwrap();
+        write.1 = market_cap;
+    }
+}
+
+pub async fn get_beta(symbol: &str) -> Result<f64, reqwest::Error> {
+    let value = reqwest::Client::new()
+        .get(format!(
+            "https://eodhistoricaldata.com/api/fundamentals/{}.US",
+            symbol
+        ))
+        .query(&[
+            ("api_token", EOD_API_TOKEN),
+            ("filter", "Technicals::Beta"),
+            ("fmt", "json"),
+        ])
+        .send()
+        .await?
+        .json::<Value>()
+        .await?;
+    Ok(value.as_f64().unwrap_or(0.0))
+}
+
+pub async fn get_beta_mt(data: Arc<RwLock<(&str, f64)>>) {
+    let symbol = data.read().unwrap().0;
+    if symbol.contains('^') {
+        return;
+    }
+    if let Ok(beta) = get_beta(symbol).await {
+        if data.try_write().is_err() {
+            println!("no write on beta")
+        }
+        let mut write = data.write().unwrap();
+        write.1 = beta;
+    }
+}
+
 pub async fn get_short_ratio_mt(data: Arc<RwLock<(&str, f64)>>) {
     let read = data.read().unwrap();
     let symbol = read.0;
@@ -0,0 +1,433 @@
+use crate::models::OptionChain;
+use crate::others::{load_json, round_to_decimals};
+use crate::pricing::{black_scholes, years_from_dte, Greeks};
+use crate::single_options::OptionType;
+use crate::sizing::Holding;
+use crate::vol_surface::VolSurface;
+use crate::{RISK_BENCHMARK, RISK_DAYS_FORWARD, RISK_IV_MOVES, RISK_PRICE_MOVES};
+use chrono::NaiveDate;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+/// A stock or option line of the portfolio file. Options have all of `expiry`, `strike` and
+/// `option_type`; quantity is in shares or contracts, negative for short.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct PortfolioPosition {
+    pub symbol: String,
+    pub expiry: Option<String>,
+    pub strike: Option<f64>,
+    pub option_type: Option<OptionType>,
+    pub quantity: i64,
+}
+impl PortfolioPosition {
+    fn option(&self) -> Option<(&str, f64, OptionType)> {
+        Some((self.expiry.as_deref()?, self.strike?, self.option_type?))
+    }
+    fn describe(&self) -> String {
+        match self.option() {
+            Some((expiry, strike, kind)) => {
+                format!("{} {} {} {:?}", self.symbol, expiry, strike, kind)
+            }
+            None => self.symbol.clone(),
+        }
+    }
+}
+
+/// Reads the portfolio file and adds any share holding from the sizing positions that it
+/// doesn't already list. A portfolio file that doesn't parse is an error, not an empty book.
+pub fn load_portfolio(
+    filename: &str,
+    holdings: &[Holding],
+) -> Result<Vec<PortfolioPosition>, String> {
+    let mut positions: Vec<PortfolioPosition> = load_json(filename)?.unwrap_or_default();
+    for holding in holdings {
+        if !positions
+            .iter()
+            .any(|position| position.symbol == holding.symbol && position.option().is_none())
+        {
+            positions.push(PortfolioPosition {
+                symbol: holding.symbol.clone(),
+                quantity: holding.quantity,
+                ..PortfolioPosition::default()
+            })
+        }
+    }
+    Ok(positions)
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct RiskConfig {
+    /// Symbol deltas are beta weighted to.
+    pub benchmark: String,
+    pub price_moves_percent: Vec<f64>,
+    pub iv_moves_points: Vec<f64>,
+    pub days_forward: Vec<i64>,
+    /// Move each symbol by its beta times the price move, so the grid reads as benchmark moves.
+    pub beta_scaled_moves: bool,
+}
+impl Default for RiskConfig {
+    fn default() -> Self {
+        Self {
+            benchmark: RISK_BENCHMARK.to_string(),
+            price_moves_percent: RISK_PRICE_MOVES.to_vec(),
+            iv_moves_points: RISK_IV_MOVES.to_vec(),
+            days_forward: RISK_DAYS_FORWARD.to_vec(),
+            beta_scaled_moves: false,
+        }
+    }
+}
+impl RiskConfig {
+    /// The defaults when there is no config file. One that doesn't parse is an error, like the
+    /// portfolio, so no report is built on a grid nobody chose.
+    pub fn load(filename: &str) -> Result<Self, String> {
+        Ok(load_json(filename)?.unwrap_or_default())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PositionRisk {
+    pub symbol: String,
+    pub position: String,
+    pub quantity: i64,
+    pub underlying: f64,
+    pub iv: f64,
+    pub dte: i64,
+    /// Quote mid, or the model value when the contract isn't quoted.
+    pub mark: f64,
+    pub market_value: f64,
+    /// Share equivalents.
+    pub delta: f64,
+    pub dollar_delta: f64,
+    /// Change in share delta for a $1 move.
+    pub gamma: f64,
+    /// Change in dollar delta for a 1% move.
+    pub dollar_gamma: f64,
+    /// Dollars per vol point.
+    pub vega: f64,
+    /// Dollars per calendar day.
+    pub theta: f64,
+    pub beta: f64,
+    /// Benchmark shares with the same beta adjusted exposure.
+    pub beta_weighted_delta: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct PortfolioGreeks {
+    pub benchmark: String,
+    pub benchmark_price: f64,
+    pub market_value: f64,
+    pub dollar_delta: f64,
+    pub dollar_gamma: f64,
+    pub vega: f64,
+    pub theta: f64,
+    pub beta_weighted_delta: f64,
+    pub beta_weighted_dollar_delta: f64,
+    pub positions_priced: usize,
+    /// Positions whose symbol wasn't in the scan, pipe delimited.
+    pub positions_missing: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ScenarioPnl {
+    pub price_move_percent: f64,
+    pub iv_move_points: f64,
+    pub days_forward: i64,
+    pub pnl: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RiskReport {
+    pub positions: Vec<PositionRisk>,
+    pub summary: PortfolioGreeks,
+    pub scenarios: Vec<ScenarioPnl>,
+}
+
+/// A position with what it takes to reprice it: the chain's underlying, the surface vol at its
+/// strike and the days it has left.
+struct Priced<'a> {
+    position: &'a PortfolioPosition,
+    underlying: f64,
+    iv: f64,
+    dte: i64,
+    beta: f64,
+}
+impl Priced<'_> {
+    /// Dollar value after moving the underlying, the vol and the calendar. Options at or past
+    /// expiry are worth their intrinsic value.
+    fn value(&self, price_move: f64, iv_move: f64, days: i64) -> f64 {
+        let underlying = self.underlying * (1.0 + price_move / 100.0);
+        let quantity = self.position.quantity as f64;
+        match self.position.option() {
+            Some((_, strike, kind)) => {
+                let dte = self.dte - days;
+                let years = if dte > 0 { years_from_dte(dte) } else { 0.0 };
+                let iv = (self.iv + iv_move / 100.0).max(0.01);
+                100.0 * quantity * black_scholes(kind, underlying, strike, years, iv).price
+            }
+            None => quantity * underlying,
+        }
+    }
+}
+
+/// Greeks for every position priced off its chain, their beta weighted totals, and the
+/// portfolio P&L over each combination of price move, vol move and days forward.
+pub fn risk_report(
+    positions: &[PortfolioPosition],
+    chains: &[OptionChain],
+    config: &RiskConfig,
+) -> RiskReport {
+    let benchmark_price = chains
+        .iter()
+        .find(|chain| chain.symbol == config.benchmark)
+        .map_or(0.0, |chain| chain.underlying_mid);
+    let mut priced: Vec<Priced> = Vec::new();
+    let mut rows: Vec<PositionRisk> = Vec::new();
+    let mut missing: Vec<String> = Vec::new();
+    for symbol in positions.iter().map(|position| &position.symbol).unique() {
+        let group = positions
+            .iter()
+            .filter(|position| position.symbol == *symbol);
+        let chain = match chains.iter().find(|chain| chain.symbol == *symbol) {
+            Some(chain) if chain.underlying_mid > 0.0 => chain,
+            _ => {
+                missing.extend(group.map(|position| position.describe()));
+                continue;
+            }
+        };
+        let as_of = NaiveDate::parse_from_str(&chain.date, "%D")
+            .unwrap_or_else(|_| chrono::Local::now().naive_local().date());
+        let surface = VolSurface::from_options(&chain.options, as_of);
+        // indexes and ETFs have no fundamentals beta, treat them as moving with the market
+        let beta = if *symbol == config.benchmark || chain.beta == 0.0 {
+            1.0
+        } else {
+            chain.beta
+        };
+        for position in group {
+            let underlying = chain.underlying_mid;
+            let (iv, dte, quote) = match position.option() {
+                Some((expiry, strike, kind)) => {
+                    let quote = chain.options.iter().find(|option| {
+                        option.expiration_date == expiry
+                            && option.strike == strike
+                            && option.kind == kind
+                    });
+                    let iv = surface
+                        .iv(expiry, strike)
+                        .or(quote.map(|option| option.iv))
+                        .unwrap_or(chain.atm_iv);
+                    let dte = NaiveDate::parse_from_str(expiry, "%F")
+                        .map_or(0, |date| (date - as_of).num_days());
+                    let mid = quote.and_then(|option| option.mid_price);
+                    (iv, dte, mid)
+                }
+                None => (0.0, 0, Some(underlying)),
+            };
+            let item = Priced {
+                position,
+                underlying,
+                iv,
+                dte,
+                beta,
+            };
+            let quantity = position.quantity as f64;
+            let greeks = match position.option() {
+                Some((_, strike, kind)) => {
+                    let years = if dte > 0 { years_from_dte(dte) } else { 0.0 };
+                    let greeks = black_scholes(kind, underlying, strike, years, iv);
+                    Greeks {
+                        price: greeks.price,
+                        delta: 100.0 * quantity * greeks.delta,
+                        gamma: 100.0 * quantity * greeks.gamma,
+                        vega: 100.0 * quantity * greeks.vega,
+                        theta: 100.0 * quantity * greeks.theta,
+                    }
+                }
+                None => Greeks {
+                    price: underlying,
+                    delta: quantity,
+                    ..Default::default()
+                },
+            };
+            let mark = quote.unwrap_or(greeks.price);
+            let multiplier = if position.option().is_some() {
+                100.0
+            } else {
+                1.0
+            };
+            let dollar_delta = greeks.delta * underlying;
+            rows.push(PositionRisk {
+                symbol: symbol.clone(),
+                position: position.describe(),
+                quantity: position.quantity,
+                underlying,
+                iv: round_to_decimals(iv, 4),
+                dte,
+                mark: round_to_decimals(mark, 4),
+                market_value: round_to_decimals(mark * multiplier * quantity, 2),
+                delta: round_to_decimals(greeks.delta, 2),
+                dollar_delta: round_to_decimals(dollar_delta, 2),
+                gamma: round_to_decimals(greeks.gamma, 4),
+                dollar_gamma: round_to_decimals(greeks.gamma * underlying * underlying / 100.0, 2),
+                vega: round_to_decimals(greeks.vega, 2),
+                theta: round_to_decimals(greeks.theta, 2),
+                beta,
+                beta_weighted_delta: if benchmark_price > 0.0 {
+                    round_to_decimals(beta * dollar_delta / benchmark_price, 2)
+                } else {
+                    0.0
+                },
+            });
+            priced.push(item);
+        }
+    }
+    let sum = |value: fn(&PositionRisk) -> f64| -> f64 {
+        round_to_decimals(rows.iter().map(value).sum(), 2)
+    };
+    let summary = PortfolioGreeks {
+        benchmark: config.benchmark.clone(),
+        benchmark_price,
+        market_value: sum(|row| row.market_value),
+        dollar_delta: sum(|row| row.dollar_delta),
+        dollar_gamma: sum(|row| row.dollar_gamma),
+        vega: sum(|row| row.vega),
+        theta: sum(|row| row.theta),
+        beta_weighted_delta: sum(|row| row.beta_weighted_delta),
+        beta_weighted_dollar_delta: sum(|row| row.beta * row.dollar_delta),
+        positions_priced: rows.len(),
+        positions_missing: missing.join("|"),
+    };
+    let mut scenarios: Vec<ScenarioPnl> = Vec::new();
+    for days in &config.days_forward {
+        for iv_move in &config.iv_moves_points {
+            for price_move in &config.price_moves_percent {
+                let pnl: f64 = priced
+                    .iter()
+                    .map(|item| {
+                        let symbol_move = if config.beta_scaled_moves {
+                            item.beta * price_move
+                        } else {
+                            *price_move
+                        };
+                        item.value(symbol_move, *iv_move, *days) - item.value(0.0, 0.0, 0)
+                    })
+                    .sum();
+                scenarios.push(ScenarioPnl {
+                    price_move_percent: *price_move,
+                    iv_move_points: *iv_move,
+                    days_forward: *days,
+                    pnl: round_to_decimals(pnl, 2),
+                })
+            }
+        }
+    }
+    RiskReport {
+        positions: rows,
+        summary,
+        scenarios,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixture_chain;
+
+    #[test]
+    fn greeks_and_scenarios_for_covered_position() {
+        let spy = fixture_chain("SPY");
+        let aapl = OptionChain {
+            symbol: "AAPL".to_string(),
+            underlying_mid: 200.0,
+            beta: 1.5,
+            ..spy.clone()
+        };
+        let call = PortfolioPosition {
+            symbol: "SPY".to_string(),
+            expiry: Some("2030-01-18".to_string()),
+            strike: Some(470.0),
+            option_type: Some(OptionType::Call),
+            quantity: 2,
+        };
+        let positions = load_portfolio(
+            "no-such-portfolio.json",
+            &[
+                Holding {
+                    symbol: "SPY".to_string(),
+                    quantity: -100,
+                    average_price: 460.0,
+                },
+                Holding {
+                    symbol: "AAPL".to_string(),
+                    quantity: 50,
+                    average_price: 190.0,
+                },
+                Holding {
+                    symbol: "TSLA".to_string(),
+                    quantity: 10,
+                    average_price: 250.0,
+                },
+            ],
+        )
+        .unwrap();
+        let positions = [vec![call], positions].concat();
+        let config = RiskConfig {
+            price_moves_percent: vec![-5.0, 0.0, 5.0],
+            iv_moves_points: vec![0.0, 5.0],
+            days_forward: vec![0, 30],
+            ..RiskConfig::default()
+        };
+        let report = risk_report(&positions, &[spy, aapl], &config);
+        assert_eq!(report.summary.positions_priced, 3);
+        assert_eq!(report.summary.positions_missing, "TSLA");
+        let call = &report.positions[0];
+        assert!(call.delta > 0.0 && call.delta < 200.0);
+        assert_eq!(call.mark, 12.35);
+        assert!(call.vega > 0.0 && call.theta < 0.0);
+        assert_eq!(report.positions[1].delta, -100.0);
+        assert_eq!(
+            report.positions[2].beta_weighted_delta,
+            round_to_decimals(1.5 * 50.0 * 200.0 / 464.905, 2)
+        );
+        assert_eq!(report.scenarios.len(), 12);
+        let pnl = |price: f64, iv: f64, days: i64| {
+            report
+                .scenarios
+                .iter()
+                .find(|row| {
+                    row.price_move_percent == price
+                        && row.iv_move_points == iv
+                        && row.days_forward == days
+                })
+                .unwrap()
+                .pnl
+        };
+        assert_eq!(pnl(0.0, 0.0, 0), 0.0);
+        assert!(pnl(0.0, 5.0, 0) > 0.0);
+        assert!(pnl(0.0, 0.0, 30) < 0.0);
+        assert!(pnl(5.0, 0.0, 0) > pnl(-5.0, 0.0, 0));
+    }
+
+    #[test]
+    fn reports_malformed_portfolio() {
+        let filename = std::env::temp_dir().join("options-scan-portfolio-test.json");
+        std::fs::write(&filename, r#"[{"symbol": "SPY", "quantity": 1.5}]"#).unwrap();
+        let error = load_portfolio(filename.to_str().unwrap(), &[]).unwrap_err();
+        assert!(error.contains("options-scan-portfolio-test.json"));
+    }
+
+    #[test]
+    fn refuses_malformed_config() {
+        let filename = std::env::temp_dir().join("malformed_risk_config.json");
+        std::fs::write(&filename, r#"{"days_forward": 5}"#).unwrap();
+        let result = RiskConfig::load(filename.to_str().unwrap());
+        std::fs::remove_file(&filename).unwrap();
+        assert!(result.is_err());
+        assert_eq!(
+            RiskConfig::load("no_such_risk_config.json"),
+            Ok(RiskConfig::default())
+        );
+    }
+}
@@ -0,0 +1,323 @@
+use crate::others::round_to_decimals;
+use crate::single_options::OptionType;
+use crate::spreads::{OptionSpread, SpreadLeg};
+use crate::trades::OrderAction;
+use crate::ROLL_MATCH_TOLERANCE_MS;
+use chrono::{NaiveDate, NaiveTime};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum RollType {
+    #[serde(rename(serialize = "Roll Up"))]
+    Up,
+    #[serde(rename(serialize = "Roll Down"))]
+    Down,
+    #[serde(rename(serialize = "Roll Out"))]
+    Out,
+    #[serde(rename(serialize = "Roll In"))]
+    In,
+    #[serde(rename(serialize = "Roll Up And Out"))]
+    UpAndOut,
+    #[serde(rename(serialize = "Roll Down And Out"))]
+    DownAndOut,
+    #[serde(rename(serialize = "Roll Up And In"))]
+    UpAndIn,
+    #[serde(rename(serialize = "Roll Down And In"))]
+    DownAndIn,
+}
+impl RollType {
+    fn from_shift(strike_shift: f64, expiry_shift_days: i64) -> Self {
+        use RollType::*;
+        match (strike_shift > 0.0, strike_shift < 0.0, expiry_shift_days) {
+            (true, _, days) if days > 0 => UpAndOut,
+            (true, _, days) if days < 0 => UpAndIn,
+            (true, _, _) => Up,
+            (_, true, days) if days > 0 => DownAndOut,
+            (_, true, days) if days < 0 => DownAndIn,
+            (_, true, _) => Down,
+            (_, _, days) if days < 0 => In,
+            _ => Out,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum RollSource {
+    SameOrder,
+    PairedSingleLegs,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Roll {
+    pub symbol: String,
+    pub roll_type: RollType,
+    pub source: RollSource,
+    pub option_type: OptionType,
+    pub position_side: OrderAction,
+    pub size: i64,
+    pub from_strike: f64,
+    pub to_strike: f64,
+    pub strike_shift: f64,
+    pub from_expiry: String,
+    pub to_expiry: String,
+    pub expiry_shift_days: i64,
+    /// positive is a debit paid to roll, negative a credit received
+    pub net_value: f64,
+    pub gross_value: f64,
+    pub delta_shift: f64,
+    pub timestamp: String,
+}
+impl Roll {
+    fn from_legs(
+        symbol: &str,
+        closing: &SpreadLeg,
+        opening: &SpreadLeg,
+        source: RollSource,
+        timestamp: &str,
+    ) -> Self {
+        let strike_shift = opening.strike - closing.strike;
+        let expiry_shift_days = match (
+            NaiveDate::parse_from_str(&closing.expiry, "%F"),
+            NaiveDate::parse_from_str(&opening.expiry, "%F"),
+        ) {
+            (Ok(from), Ok(to)) => (to - from).num_days(),
+            _ => 0,
+        };
+        Self {
+            symbol: symbol.to_string(),
+            roll_type: RollType::from_shift(strike_shift, expiry_shift_days),
+            source,
+            option_type: opening.option_type,
+            position_side: opening.side,
+            size: opening.size,
+            from_strike: closing.strike,
+            to_strike: opening.strike,
+            strike_shift: round_to_decimals(strike_shift, 2),
+            from_expiry: closing.expiry.clone(),
+            to_expiry: opening.expiry.clone(),
+            expiry_shift_days,
+            net_value: round_to_decimals(leg_value(closing) + leg_value(opening), 2),
+            gross_value: round_to_decimals(leg_value(closing).abs() + leg_value(opening).abs(), 2),
+            delta_shift: round_to_decimals(leg_delta(closing) + leg_delta(opening), 2),
+            timestamp: timestamp.to_string(),
+        }
+    }
+}
+
+fn leg_value(leg: &SpreadLeg) -> f64 {
+    let value = leg.price.unwrap_or(0.0) * 100.0 * leg.size as f64;
+    match leg.side {
+        OrderAction::Bought => value,
+        OrderAction::Sold => -value,
+        OrderAction::Unknown => 0.0,
+    }
+}
+
+fn leg_delta(leg: &SpreadLeg) -> f64 {
+    match leg.side {
+        OrderAction::Bought => leg.delta * leg.size as f64,
+        OrderAction::Sold => -leg.delta * leg.size as f64,
+        OrderAction::Unknown => 0.0,
+    }
+}
+
+/// The closing and opening legs of a roll: same option type and size, opposite sides, and one
+/// leg estimated as closing while the other opens.
+fn roll_legs<'a>(
+    first: &'a SpreadLeg,
+    second: &'a SpreadLeg,
+) -> Option<(&'a SpreadLeg, &'a SpreadLeg)> {
+    if first.option_type != second.option_type
+        || first.size != second.size
+        || first.option_symbol == second.option_symbol
+        || first.side == OrderAction::Unknown
+        || first.side != second.side.flip()
+    {
+        return None;
+    }
+    match (
+        first.transaction_estimate.is_closing(),
+        second.transaction_estimate.is_closing(),
+    ) {
+        (true, false) if second.transaction_estimate.is_opening() => Some((first, second)),
+        (false, true) if first.transaction_estimate.is_opening() => Some((second, first)),
+        _ => None,
+    }
+}
+
+fn later_expiry(closing: &SpreadLeg, opening: &SpreadLeg) -> bool {
+    match (
+        NaiveDate::parse_from_str(&closing.expiry, "%F"),
+        NaiveDate::parse_from_str(&opening.expiry, "%F"),
+    ) {
+        (Ok(from), Ok(to)) => to > from,
+        _ => false,
+    }
+}
+
+fn parse_timestamp(timestamp: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(timestamp, "%H:%M:%S.%3f").ok()
+}
+
+/// Whether `second`, printed no earlier than `first`, is close enough to pair with it. Timestamps
+/// that don't parse only pair when they are identical.
+fn within_tolerance(first: (Option<NaiveTime>, &str), second: (Option<NaiveTime>, &str)) -> bool {
+    match (first.0, second.0) {
+        (Some(first), Some(second)) => {
+            (second - first).num_milliseconds() <= ROLL_MATCH_TOLERANCE_MS
+        }
+        (None, None) => first.1 == second.1,
+        _ => false,
+    }
+}
+
+/// Finds rolls printed as one two-leg order, then pairs remaining single-leg prints that close
+/// one contract and open another within `ROLL_MATCH_TOLERANCE_MS`, walking only that window of
+/// the time-sorted prints. Matching spreads are tagged with their `roll_type`.
+pub fn detect_rolls(spreads: &mut [OptionSpread]) -> Vec<Roll> {
+    let mut rolls: Vec<Roll> = Vec::new();
+    for spread in spreads.iter_mut() {
+        if spread.legs.len() != 2 || spread.stock_leg.is_some() {
+            continue;
+        }
+        // a same-expiration order is a vertical whatever its open/close estimates say, so a
+        // roll printed as one order has to move the position out to a later expiration
+        if let Some((closing, opening)) = roll_legs(&spread.legs[0], &spread.legs[1])
+            .filter(|(closing, opening)| later_expiry(closing, opening))
+        {
+            let roll = Roll::from_legs(
+                &spread.symbol,
+                closing,
+                opening,
+                RollSource::SameOrder,
+                &spread.timestamp,
+            );
+            spread.roll_type = Some(roll.roll_type);
+            rolls.push(roll);
+        }
+    }
+    let single_legs = spreads
+        .iter()
+        .enumerate()
+        .filter(|(_, spread)| spread.legs.len() == 1 && spread.roll_type.is_none())
+        .map(|(position, spread)| (position, parse_timestamp(&spread.timestamp)))
+        .sorted_by(|a, b| (a.1, &spreads[a.0].timestamp).cmp(&(b.1, &spreads[b.0].timestamp)))
+        .collect_vec();
+    let mut paired: HashSet<usize> = HashSet::new();
+    for (index, (first, first_time)) in single_legs.iter().enumerate() {
+        if paired.contains(first) {
+            continue;
+        }
+        let first_key = (*first_time, spreads[*first].timestamp.as_str());
+        let partner = single_legs[index + 1..]
+            .iter()
+            .take_while(|(second, second_time)| {
+                within_tolerance(first_key, (*second_time, &spreads[*second].timestamp))
+            })
+            .map(|(second, _)| *second)
+            .find(|second| {
+                !paired.contains(second)
+                    && spreads[*second].symbol == spreads[*first].symbol
+                    && roll_legs(&spreads[*first].legs[0], &spreads[*second].legs[0]).is_some()
+            });
+        if let Some(second) = partner {
+            let (closing, opening) =
+                roll_legs(&spreads[*first].legs[0], &spreads[second].legs[0]).unwrap();
+            let roll = Roll::from_legs(
+                &spreads[*first].symbol,
+                closing,
+                opening,
+                RollSource::PairedSingleLegs,
+                &spreads[*first].timestamp,
+            );
+            spreads[*first].roll_type = Some(roll.roll_type);
+            spreads[second].roll_type = Some(roll.roll_type);
+            paired.insert(*first);
+            paired.insert(second);
+            rolls.push(roll);
+        }
+    }
+    rolls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixture_trades;
+    use crate::spreads::get_spreads;
+    use crate::trades::TransactionType;
+
+    #[test]
+    fn tags_same_order_roll() {
+        let mut trades = fixture_trades(include_str!("../tests/fixtures/vertical.json"));
+        trades[0].transaction_estimate = TransactionType::BuyToOpen;
+        trades[1].transaction_estimate = TransactionType::MaybeSellToClose;
+        // the same estimates on a vertical are not a roll
+        let mut spreads = get_spreads(trades.clone());
+        assert!(detect_rolls(&mut spreads).is_empty());
+        assert!(spreads[0].roll_type.is_none());
+
+        trades[0].symbol = ".SPY300215C470".to_string();
+        trades[0].expiry = "2030-02-15".to_string();
+        let mut spreads = get_spreads(trades);
+        let rolls = detect_rolls(&mut spreads);
+        assert_eq!(rolls.len(), 1);
+        assert_eq!(rolls[0].roll_type, RollType::DownAndOut);
+        assert_eq!(rolls[0].source, RollSource::SameOrder);
+        assert_eq!(rolls[0].position_side, OrderAction::Bought);
+        assert_eq!(rolls[0].strike_shift, -10.0);
+        assert_eq!(rolls[0].expiry_shift_days, 28);
+        assert_eq!(rolls[0].net_value, 230000.0);
+        assert_eq!(spreads[0].roll_type, Some(RollType::DownAndOut));
+    }
+
+    #[test]
+    fn pairs_single_legs_within_tolerance() {
+        let trades = fixture_trades(include_str!("../tests/fixtures/vertical.json"));
+        let print =
+            |index: usize, side: OrderAction, estimate: TransactionType, timestamp: &str| {
+                let mut trade = trades[index].clone();
+                trade.order_action = side;
+                trade.transaction_estimate = estimate;
+                trade.timestamp = timestamp.to_string();
+                trade.to_spread()
+            };
+        let mut spreads = vec![
+            print(
+                1,
+                OrderAction::Bought,
+                TransactionType::BuyToOpen,
+                "10:31:03.500",
+            ),
+            print(
+                0,
+                OrderAction::Sold,
+                TransactionType::MaybeSellToClose,
+                "11:00:00.000",
+            ),
+            print(
+                0,
+                OrderAction::Sold,
+                TransactionType::MaybeSellToClose,
+                "10:31:02.125",
+            ),
+            print(
+                1,
+                OrderAction::Bought,
+                TransactionType::BuyToOpen,
+                "10:31:09.000",
+            ),
+        ];
+        let rolls = detect_rolls(&mut spreads);
+        assert_eq!(rolls.len(), 1);
+        assert_eq!(rolls[0].source, RollSource::PairedSingleLegs);
+        assert_eq!(rolls[0].roll_type, RollType::Up);
+        assert_eq!(rolls[0].timestamp, "10:31:02.125");
+        assert_eq!(spreads[0].roll_type, Some(RollType::Up));
+        assert_eq!(spreads[2].roll_type, Some(RollType::Up));
+        assert!(spreads[1].roll_type.is_none());
+        assert!(spreads[3].roll_type.is_none());
+    }
+}
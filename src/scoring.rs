@@ -0,0 +1,371 @@
+use crate::models::OptionChain;
+use crate::others::{create_json_file, load_json, record_daily, round_to_decimals};
+use crate::{BIAS_HISTORY_LENGTH, BIAS_MIN_HISTORY};
+use chrono::NaiveDate;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum BiasFactor {
+    PutCallVolumeRatio,
+    PutCallOiRatio,
+    InsiderNet,
+    DealerDelta,
+    NaiveDealerDelta,
+    DealerGamma,
+    LargeTraderDelta,
+    LargeTraderOpeningDelta,
+    DeltaAdjustedNotional,
+    NetOpeningPremium,
+}
+impl BiasFactor {
+    pub fn value(&self, chain: &OptionChain) -> f64 {
+        match self {
+            BiasFactor::PutCallVolumeRatio => chain.put_call_volume_ratio,
+            BiasFactor::PutCallOiRatio => chain.put_call_oi_ratio,
+            BiasFactor::InsiderNet => chain.insider_net_transaction,
+            BiasFactor::DealerDelta => chain.dealer_delta,
+            BiasFactor::NaiveDealerDelta => chain.naive_dealer_delta,
+            BiasFactor::DealerGamma => chain.dealer_gamma,
+            BiasFactor::LargeTraderDelta => chain.large_trader_delta,
+            BiasFactor::LargeTraderOpeningDelta => chain.large_trader_opening_delta,
+            BiasFactor::DeltaAdjustedNotional => chain.delta_adjusted_notional,
+            BiasFactor::NetOpeningPremium => {
+                chain.bullish_opening_premium - chain.bearish_opening_premium
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Normalization {
+    Raw,
+    /// Standard deviations from the symbol's own history of the factor. The factor is warming up
+    /// and votes nothing until there are `min_history` days recorded.
+    ZScore,
+}
+
+/// A factor votes `weight` in the direction it is past `center` by more than `threshold`, and
+/// `neutral_vote` times `weight` inside that band. Negative weights make a factor contrarian.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct FactorConfig {
+    pub factor: BiasFactor,
+    pub weight: f64,
+    pub normalization: Normalization,
+    pub center: f64,
+    pub threshold: f64,
+    #[serde(default)]
+    pub neutral_vote: f64,
+}
+impl FactorConfig {
+    fn new(factor: BiasFactor, center: f64) -> Self {
+        Self {
+            factor,
+            weight: 1.0,
+            normalization: Normalization::Raw,
+            center,
+            threshold: 0.0,
+            neutral_vote: 0.0,
+        }
+    }
+    /// The old scan counted a value exactly at `center` as bearish.
+    fn bearish_at_center(factor: BiasFactor, center: f64) -> Self {
+        Self {
+            neutral_vote: -1.0,
+            ..Self::new(factor, center)
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct ScoringConfig {
+    pub factors: Vec<FactorConfig>,
+    pub min_history: usize,
+    pub history_length: usize,
+}
+impl Default for ScoringConfig {
+    /// The six equally weighted votes the scan has always used.
+    fn default() -> Self {
+        Self {
+            factors: vec![
+                FactorConfig::bearish_at_center(BiasFactor::PutCallVolumeRatio, 1.0),
+                FactorConfig::new(BiasFactor::InsiderNet, 0.0),
+                FactorConfig::bearish_at_center(BiasFactor::DealerDelta, 0.0),
+                FactorConfig::bearish_at_center(BiasFactor::NaiveDealerDelta, 0.0),
+                FactorConfig::new(BiasFactor::LargeTraderDelta, 0.0),
+                FactorConfig::new(BiasFactor::LargeTraderOpeningDelta, 0.0),
+            ],
+            min_history: BIAS_MIN_HISTORY,
+            history_length: BIAS_HISTORY_LENGTH,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FactorContribution {
+    pub symbol: String,
+    pub factor: BiasFactor,
+    pub value: f64,
+    pub normalized: f64,
+    /// a z-scored factor without enough history yet, which contributes nothing
+    pub warming_up: bool,
+    pub weight: f64,
+    pub contribution: f64,
+}
+
+/// Past factor values per symbol by chain date, oldest first.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct FactorHistory {
+    pub values: BTreeMap<String, BTreeMap<BiasFactor, Vec<(String, f64)>>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BiasModel {
+    pub config: ScoringConfig,
+    pub history: FactorHistory,
+}
+impl BiasModel {
+    /// The default scoring and an empty history when the files don't exist yet. Either file
+    /// failing to parse is an error, so the history is never saved over with a fresh one.
+    pub fn load(config_filename: &str, history_filename: &str) -> Result<Self, String> {
+        Ok(Self {
+            config: load_json(config_filename)?.unwrap_or_default(),
+            history: load_json(&format!("{}.json", history_filename))?.unwrap_or_default(),
+        })
+    }
+    pub fn save_history(&self, filename: &str) {
+        create_json_file(filename, &self.history)
+    }
+    pub fn score(&self, chain: &OptionChain) -> Vec<FactorContribution> {
+        self.config
+            .factors
+            .iter()
+            .map(|factor| {
+                let value = factor.factor.value(chain);
+                let normalized = match factor.normalization {
+                    Normalization::Raw => Some(value),
+                    Normalization::ZScore => self.z_score(chain, factor.factor, value),
+                };
+                let contribution = match normalized {
+                    Some(normalized) if normalized - factor.center > factor.threshold => {
+                        factor.weight
+                    }
+                    Some(normalized) if normalized - factor.center < -factor.threshold => {
+                        -factor.weight
+                    }
+                    Some(_) => factor.neutral_vote * factor.weight,
+                    None => 0.0,
+                };
+                FactorContribution {
+                    symbol: chain.symbol.clone(),
+                    factor: factor.factor,
+                    value,
+                    normalized: round_to_decimals(normalized.unwrap_or(0.0), 4),
+                    warming_up: normalized.is_none(),
+                    weight: factor.weight,
+                    contribution,
+                }
+            })
+            .collect()
+    }
+    /// Against the days recorded before the chain's own, so a rerun on the same day isn't
+    /// compared with itself.
+    fn z_score(&self, chain: &OptionChain, factor: BiasFactor, value: f64) -> Option<f64> {
+        let day = |date: &str| NaiveDate::parse_from_str(date, "%D").ok();
+        let today = day(&chain.date);
+        let past: Vec<f64> = self
+            .history
+            .values
+            .get(&chain.symbol)?
+            .get(&factor)?
+            .iter()
+            .filter(|(date, _)| day(date) < today)
+            .map(|(_, value)| *value)
+            .collect();
+        if past.len() < self.config.min_history.max(2) {
+            return None;
+        }
+        let mean = past.iter().sum::<f64>() / past.len() as f64;
+        let deviation =
+            (past.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (past.len() - 1) as f64).sqrt();
+        if deviation > 0.0 {
+            Some((value - mean) / deviation)
+        } else {
+            None
+        }
+    }
+    /// Sets the chain's factor values for its date in its symbol's history, dropping the oldest
+    /// days past `history_length`.
+    pub fn record(&mut self, chain: &OptionChain) {
+        let symbol_history = self.history.values.entry(chain.symbol.clone()).or_default();
+        for factor in self
+            .config
+            .factors
+            .iter()
+            .map(|factor| factor.factor)
+            .unique()
+        {
+            let values = symbol_history.entry(factor).or_default();
+            record_daily(values, &chain.date, factor.value(chain));
+            if values.len() > self.config.history_length {
+                values.drain(..values.len() - self.config.history_length);
+            }
+        }
+    }
+}
+
+impl OptionChain {
+    pub fn with_bias(self, model: &BiasModel) -> Self {
+        let contributions = model.score(&self);
+        let score: f64 = contributions.iter().map(|item| item.contribution).sum();
+        Self {
+            bias: score.round() as i64,
+            bias_score: round_to_decimals(score, 2),
+            bias_breakdown: contributions
+                .iter()
+                .filter(|item| item.contribution != 0.0)
+                .map(|item| format!("{:?} {:+}", item.factor, item.contribution))
+                .join("|"),
+            bias_contributions: contributions,
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixture_chain;
+
+    #[test]
+    fn explains_weighted_score() {
+        let chain = OptionChain {
+            put_call_volume_ratio: 1.5,
+            dealer_delta: -20.0,
+            large_trader_delta: 300.0,
+            ..fixture_chain("SPY")
+        };
+        let model = BiasModel {
+            config: ScoringConfig {
+                factors: vec![
+                    FactorConfig::new(BiasFactor::PutCallVolumeRatio, 1.0),
+                    FactorConfig {
+                        weight: 2.5,
+                        ..FactorConfig::new(BiasFactor::LargeTraderDelta, 0.0)
+                    },
+                    FactorConfig {
+                        threshold: 50.0,
+                        ..FactorConfig::new(BiasFactor::DealerDelta, 0.0)
+                    },
+                ],
+                ..ScoringConfig::default()
+            },
+            history: FactorHistory::default(),
+        };
+        let chain = chain.with_bias(&model);
+        assert_eq!(chain.bias_score, 3.5);
+        assert_eq!(chain.bias, 4);
+        assert_eq!(
+            chain.bias_breakdown,
+            "PutCallVolumeRatio +1|LargeTraderDelta +2.5"
+        );
+        assert_eq!(chain.bias_contributions.len(), 3);
+    }
+
+    #[test]
+    fn normalizes_against_own_history() {
+        let chain = fixture_chain("SPY");
+        let mut model = BiasModel {
+            config: ScoringConfig {
+                factors: vec![FactorConfig {
+                    normalization: Normalization::ZScore,
+                    threshold: 1.0,
+                    ..FactorConfig::new(BiasFactor::LargeTraderDelta, 0.0)
+                }],
+                min_history: 3,
+                history_length: 4,
+            },
+            history: FactorHistory::default(),
+        };
+        let warming_up = model.score(&OptionChain {
+            large_trader_delta: 500.0,
+            ..chain.clone()
+        });
+        assert!(warming_up[0].warming_up);
+        assert_eq!(warming_up[0].contribution, 0.0);
+        for (date, delta) in [
+            ("01/10/22", 100.0),
+            ("01/11/22", 120.0),
+            ("01/12/22", 80.0),
+            ("01/12/22", 1000.0),
+            ("01/13/22", 110.0),
+            ("01/12/22", 80.0),
+            ("01/14/22", 90.0),
+        ] {
+            model.record(&OptionChain {
+                date: date.to_string(),
+                large_trader_delta: delta,
+                ..chain.clone()
+            });
+        }
+        let recorded: Vec<f64> = model.history.values["SPY"][&BiasFactor::LargeTraderDelta]
+            .iter()
+            .map(|(_, value)| *value)
+            .collect();
+        assert_eq!(recorded, vec![120.0, 80.0, 110.0, 90.0]);
+        let tomorrow = OptionChain {
+            date: "01/15/22".to_string(),
+            ..chain
+        };
+        let usual = model.score(&OptionChain {
+            large_trader_delta: 105.0,
+            ..tomorrow.clone()
+        });
+        assert!(!usual[0].warming_up);
+        assert_eq!(usual[0].contribution, 0.0);
+        let unusual = model.score(&OptionChain {
+            large_trader_delta: 10.0,
+            ..tomorrow.clone()
+        });
+        assert_eq!(unusual[0].contribution, -1.0);
+        // a rerun on the 13th only has the two days before it
+        let rerun = model.score(&OptionChain {
+            date: "01/13/22".to_string(),
+            ..tomorrow
+        });
+        assert!(rerun[0].warming_up);
+    }
+
+    #[test]
+    fn default_votes_bearish_at_center() {
+        let chain = OptionChain {
+            put_call_volume_ratio: 1.0,
+            insider_net_transaction: 0.0,
+            dealer_delta: 0.0,
+            naive_dealer_delta: 0.0,
+            large_trader_delta: 0.0,
+            large_trader_opening_delta: 0.0,
+            ..fixture_chain("SPY")
+        }
+        .with_bias(&BiasModel::default());
+        assert_eq!(chain.bias, -3);
+        assert_eq!(
+            chain.bias_breakdown,
+            "PutCallVolumeRatio -1|DealerDelta -1|NaiveDealerDelta -1"
+        );
+    }
+
+    #[test]
+    fn refuses_malformed_history() {
+        let filename = std::env::temp_dir().join("options-scan-bias-history-test");
+        let filename = filename.to_str().unwrap();
+        std::fs::write(
+            format!("{}.json", filename),
+            r#"{"values": {"SPY": [1.0]}}"#,
+        )
+        .unwrap();
+        assert!(BiasModel::load("no-such-bias-config.json", filename).is_err());
+    }
+}
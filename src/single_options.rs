@@ -1,12 +1,11 @@
-use chrono::Local;
 use chrono::NaiveDate;
 use serde::Deserialize;
 use serde::Serialize;
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, PartialOrd, Copy)]
 pub enum OptionType {
-    #[serde(rename(deserialize = "C"))]
+    #[serde(rename(deserialize = "C"), alias = "Call")]
     Call,
-    #[serde(rename(deserialize = "P"))]
+    #[serde(rename(deserialize = "P"), alias = "Put")]
     Put,
 }
 
@@ -87,23 +86,20 @@ impl OptionData {
             OptionType::Put => underlying_mid > self.strike,
         }
     }
-    fn dte(&self) -> i64 {
-        (NaiveDate::parse_from_str(&self.expiration_date, "%F").unwrap()
-            - Local::now().naive_local().date())
-        .num_days()
+    fn dte(&self, as_of: NaiveDate) -> i64 {
+        (NaiveDate::parse_from_str(&self.expiration_date, "%F").unwrap() - as_of).num_days()
     }
-    pub fn valid_option(&self) -> bool {
+    pub fn valid_option(&self, as_of: NaiveDate) -> bool {
         self.ask_price.is_some()
             && self.bid_price.is_some()
             && self.ask_price.unwrap() > 0.0
             && self.bid_price.unwrap() > 0.0
             && self.open_interest > 0
-            && NaiveDate::parse_from_str(&self.expiration_date, "%F").unwrap()
-                > Local::now().naive_local().date()
+            && NaiveDate::parse_from_str(&self.expiration_date, "%F").unwrap() > as_of
     }
-    pub fn calculate_values(self, underlying_mid: f64) -> Self {
+    pub fn calculate_values(self, underlying_mid: f64, as_of: NaiveDate) -> Self {
         Self {
-            dte: self.dte(),
+            dte: self.dte(as_of),
             intrinsic_value: self.intrinsic_value(underlying_mid),
             extrinsic_value: self.extrinsic_value(underlying_mid),
             otm: self.otm(underlying_mid),
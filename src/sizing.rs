@@ -0,0 +1,445 @@
+use crate::models::{OptionChain, Signal, SignalType};
+use crate::others::{create_json_file, load_json, record_daily, round_to_decimals};
+use crate::{
+    AMOUNT_IN_ACCOUNT, SIZING_MAX_GROSS_PERCENT, SIZING_MAX_NET_PERCENT, SIZING_MAX_SYMBOL_PERCENT,
+    SIZING_REALIZED_VOL_DAYS,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum VolSource {
+    /// At the money implied volatility from the chain.
+    Implied,
+    /// Annualized close to close volatility from the recorded price history, falling back to
+    /// implied until there are enough days.
+    Realized,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum SizingMethod {
+    /// `percent` of the account in every position.
+    FixedFractional { percent: f64 },
+    /// `percent` of the account scaled by `target_volatility / volatility`, both annualized
+    /// decimals, so quiet names get bigger positions than volatile ones.
+    VolatilityTarget {
+        percent: f64,
+        target_volatility: f64,
+        source: VolSource,
+    },
+    /// `percent` of the account in market exposure, i.e. dollars divided by the symbol's beta.
+    BetaAdjusted { percent: f64 },
+    /// Loses `risk_percent` of the account if the price moves `stop_percent` against the
+    /// position.
+    MaxLoss {
+        risk_percent: f64,
+        stop_percent: f64,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct SizingConfig {
+    pub method: SizingMethod,
+    pub account_value: f64,
+    /// Largest position in one symbol, current holding included, as a percent of the account.
+    pub max_symbol_percent: f64,
+    /// Sum of absolute position values across all holdings and new orders.
+    pub max_gross_percent: f64,
+    /// Absolute value of long minus short position values.
+    pub max_net_percent: f64,
+    pub realized_vol_days: usize,
+}
+impl Default for SizingConfig {
+    /// The tenth of the account per name that `shares_to_trade` has always used.
+    fn default() -> Self {
+        Self {
+            method: SizingMethod::FixedFractional { percent: 10.0 },
+            account_value: AMOUNT_IN_ACCOUNT,
+            max_symbol_percent: SIZING_MAX_SYMBOL_PERCENT,
+            max_gross_percent: SIZING_MAX_GROSS_PERCENT,
+            max_net_percent: SIZING_MAX_NET_PERCENT,
+            realized_vol_days: SIZING_REALIZED_VOL_DAYS,
+        }
+    }
+}
+
+/// A current share position, negative for short.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Holding {
+    pub symbol: String,
+    pub quantity: i64,
+    pub average_price: f64,
+}
+
+/// Daily closes per symbol, oldest first, keyed by the chain's `date`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct PriceHistory {
+    pub closes: BTreeMap<String, Vec<(String, f64)>>,
+}
+impl PriceHistory {
+    /// Keeps one price per symbol and date, so running several times a day only moves that
+    /// day's, and backfilled days land before the live days recorded after them.
+    pub fn record(&mut self, chain: &OptionChain) {
+        if chain.underlying_mid <= 0.0 {
+            return;
+        }
+        let closes = self.closes.entry(chain.symbol.clone()).or_default();
+        record_daily(closes, &chain.date, chain.underlying_mid);
+    }
+    pub fn realized_volatility(&self, symbol: &str, days: usize) -> Option<f64> {
+        let closes = self.closes.get(symbol)?;
+        if days < 2 || closes.len() <= days {
+            return None;
+        }
+        let returns: Vec<f64> = closes[closes.len() - days - 1..]
+            .windows(2)
+            .map(|pair| (pair[1].1 / pair[0].1).ln())
+            .collect();
+        let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+        let variance =
+            returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (returns.len() - 1) as f64;
+        Some(variance.sqrt() * 252.0_f64.sqrt())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PositionSizer {
+    pub config: SizingConfig,
+    pub holdings: Vec<Holding>,
+    pub prices: PriceHistory,
+}
+impl PositionSizer {
+    /// A config, holdings or price history that exists but doesn't parse is an error, so sizing
+    /// never runs on default limits or as if the account were flat, and the history is never
+    /// saved over.
+    pub fn load(
+        config_filename: &str,
+        positions_filename: &str,
+        prices_filename: &str,
+    ) -> Result<Self, String> {
+        Ok(Self {
+            config: load_json(config_filename)?.unwrap_or_default(),
+            holdings: load_json(positions_filename)?.unwrap_or_default(),
+            prices: load_json(&format!("{}.json", prices_filename))?.unwrap_or_default(),
+        })
+    }
+    pub fn save_prices(&self, filename: &str) {
+        create_json_file(filename, &self.prices)
+    }
+    /// Dollars the method allocates to a symbol before any limits.
+    fn dollars(&self, chain: &OptionChain) -> f64 {
+        let account = self.config.account_value;
+        match self.config.method {
+            SizingMethod::FixedFractional { percent } => account * percent / 100.0,
+            SizingMethod::VolatilityTarget {
+                percent,
+                target_volatility,
+                source,
+            } => {
+                let volatility = match source {
+                    VolSource::Implied => None,
+                    VolSource::Realized => self
+                        .prices
+                        .realized_volatility(&chain.symbol, self.config.realized_vol_days),
+                }
+                .unwrap_or(chain.atm_iv);
+                if volatility > 0.0 {
+                    account * percent / 100.0 * target_volatility / volatility
+                } else {
+                    account * percent / 100.0
+                }
+            }
+            SizingMethod::BetaAdjusted { percent } => {
+                let beta = if chain.beta.abs() > 0.0 {
+                    chain.beta.abs()
+                } else {
+                    1.0
+                };
+                account * percent / 100.0 / beta
+            }
+            SizingMethod::MaxLoss {
+                risk_percent,
+                stop_percent,
+            } => account * risk_percent / stop_percent,
+        }
+    }
+    /// Fills in the sizing fields of each signal in order. Orders only add in the signal's
+    /// direction, and each one is cut back to the largest size at which the symbol, gross and
+    /// net limits hold with the holdings and the orders sized before it.
+    pub fn size(&self, signals: &mut [Signal], chains: &[OptionChain]) {
+        let account = self.config.account_value;
+        let price_of = |symbol: &str, fallback: f64| {
+            chains
+                .iter()
+                .find(|chain| chain.symbol == symbol)
+                .map_or(fallback, |chain| chain.underlying_mid)
+        };
+        let mut positions: HashMap<String, (i64, f64)> = self
+            .holdings
+            .iter()
+            .map(|holding| {
+                (
+                    holding.symbol.clone(),
+                    (
+                        holding.quantity,
+                        price_of(&holding.symbol, holding.average_price),
+                    ),
+                )
+            })
+            .collect();
+        let max_gross = account * self.config.max_gross_percent / 100.0;
+        let max_net = account * self.config.max_net_percent / 100.0;
+        for signal in signals.iter_mut() {
+            let chain = match chains.iter().find(|chain| chain.symbol == signal.symbol) {
+                Some(chain) if chain.underlying_mid > 0.0 => chain,
+                _ => {
+                    signal.sizing_limit = "No Price".to_string();
+                    continue;
+                }
+            };
+            let price = chain.underlying_mid;
+            let sign = match signal.side {
+                SignalType::Buy => 1,
+                SignalType::Sell => -1,
+            };
+            let current = positions.get(&signal.symbol).map_or(0, |item| item.0);
+            let target = sign * (self.dollars(chain) / price).floor() as i64;
+            let mut order = target - current;
+            let mut limit = "";
+            if order * sign <= 0 {
+                order = 0;
+                limit = "Already Held";
+            }
+            let max_shares =
+                (account * self.config.max_symbol_percent / 100.0 / price).floor() as i64;
+            if (current + order).abs() > max_shares && order != 0 {
+                order = if (sign * max_shares - current) * sign > 0 {
+                    sign * max_shares - current
+                } else {
+                    0
+                };
+                limit = "Symbol Limit";
+            }
+            // both limits are linear in the symbol's new quantity, so they bound it to a range
+            let (gross, net) = exposure(positions.values());
+            let (other_gross, other_net) = exposure(
+                positions
+                    .iter()
+                    .filter(|(symbol, _)| **symbol != signal.symbol)
+                    .map(|(_, position)| position),
+            );
+            let gross_room = (max_gross.max(gross) - other_gross) / price;
+            let net_room = max_net.max(net.abs());
+            let highest = gross_room.min((net_room - other_net) / price);
+            let lowest = (-gross_room).max((-net_room - other_net) / price);
+            let fits = |quantity: i64| (lowest..=highest).contains(&(quantity as f64));
+            if order != 0 && !fits(current + order) {
+                let allowed = if sign > 0 {
+                    (highest.floor() as i64).min(current + order)
+                } else {
+                    (lowest.ceil() as i64).max(current + order)
+                };
+                order = if (allowed - current) * sign > 0 && fits(allowed) {
+                    allowed - current
+                } else {
+                    0
+                };
+                let gross_ok = ((current + order + sign) as f64).abs() <= gross_room;
+                limit = if gross_ok { "Net Limit" } else { "Gross Limit" };
+            }
+            positions.insert(signal.symbol.clone(), (current + order, price));
+            signal.price = price;
+            signal.current_quantity = current;
+            signal.target_quantity = target;
+            signal.order_quantity = order;
+            signal.order_value = round_to_decimals(order as f64 * price, 2);
+            signal.sizing_limit = limit.to_string();
+        }
+    }
+}
+
+/// Gross and signed net dollar value of `(quantity, price)` positions.
+fn exposure<'a>(positions: impl Iterator<Item = &'a (i64, f64)>) -> (f64, f64) {
+    positions.fold((0.0, 0.0), |(gross, net), (quantity, price)| {
+        let value = *quantity as f64 * price;
+        (gross + value.abs(), net + value)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::groups::GroupConfig;
+    use crate::models::{fixture_chain, get_signals};
+
+    fn chain(symbol: &str, price: f64, net_value: f64) -> OptionChain {
+        OptionChain {
+            symbol: symbol.to_string(),
+            underlying_mid: price,
+            large_trader_net_value: net_value,
+            ..fixture_chain(symbol)
+        }
+    }
+
+    fn sizer(method: SizingMethod, holdings: Vec<Holding>) -> PositionSizer {
+        PositionSizer {
+            config: SizingConfig {
+                method,
+                account_value: 100000.0,
+                max_symbol_percent: 20.0,
+                max_gross_percent: 100.0,
+                max_net_percent: 100.0,
+                realized_vol_days: 3,
+            },
+            holdings,
+            prices: PriceHistory::default(),
+        }
+    }
+
+    #[test]
+    fn sizes_by_volatility_and_beta() {
+        let chains = vec![
+            OptionChain {
+                atm_iv: 0.4,
+                beta: 2.0,
+                ..chain("SPY", 100.0, 1000000.0)
+            },
+            chain("QQQ", 50.0, -1000000.0),
+        ];
+        let mut signals = get_signals(&chains, 2500.0, 25600.0, &GroupConfig::default());
+        sizer(
+            SizingMethod::VolatilityTarget {
+                percent: 10.0,
+                target_volatility: 0.2,
+                source: VolSource::Realized,
+            },
+            vec![],
+        )
+        .size(&mut signals, &chains);
+        let spy = signals
+            .iter()
+            .find(|signal| signal.symbol == "SPY")
+            .unwrap();
+        assert_eq!(spy.order_quantity, 50);
+        let mut signals = get_signals(&chains, 2500.0, 25600.0, &GroupConfig::default());
+        sizer(SizingMethod::BetaAdjusted { percent: 10.0 }, vec![]).size(&mut signals, &chains);
+        let spy = signals
+            .iter()
+            .find(|signal| signal.symbol == "SPY")
+            .unwrap();
+        let qqq = signals
+            .iter()
+            .find(|signal| signal.symbol == "QQQ")
+            .unwrap();
+        assert_eq!(spy.order_quantity, 50);
+        assert_eq!(qqq.order_quantity, -200);
+        assert_eq!(qqq.order_value, -10000.0);
+    }
+
+    #[test]
+    fn respects_holdings_and_limits() {
+        let chains = vec![chain("SPY", 100.0, 1000000.0), chain("QQQ", 50.0, 900000.0)];
+        let holdings = vec![
+            Holding {
+                symbol: "SPY".to_string(),
+                quantity: 150,
+                average_price: 90.0,
+            },
+            Holding {
+                symbol: "TSLA".to_string(),
+                quantity: 800,
+                average_price: 100.0,
+            },
+        ];
+        let mut signals = get_signals(&chains, 2500.0, 25600.0, &GroupConfig::default());
+        sizer(
+            SizingMethod::MaxLoss {
+                risk_percent: 1.0,
+                stop_percent: 5.0,
+            },
+            holdings,
+        )
+        .size(&mut signals, &chains);
+        let spy = signals
+            .iter()
+            .find(|signal| signal.symbol == "SPY")
+            .unwrap();
+        assert_eq!(spy.target_quantity, 200);
+        assert_eq!(spy.current_quantity, 150);
+        assert_eq!(spy.order_quantity, 50);
+        let qqq = signals
+            .iter()
+            .find(|signal| signal.symbol == "QQQ")
+            .unwrap();
+        assert_eq!(qqq.target_quantity, 400);
+        assert_eq!(qqq.order_quantity, 0);
+        assert_eq!(qqq.sizing_limit, "Gross Limit");
+    }
+
+    #[test]
+    fn realized_volatility_from_closes() {
+        let mut prices = PriceHistory::default();
+        for (date, price) in [
+            ("01/10/22", 100.0),
+            ("01/11/22", 101.0),
+            ("01/11/22", 102.0),
+            ("01/13/22", 103.0),
+            ("01/12/22", 100.0),
+        ] {
+            prices.record(&OptionChain {
+                date: date.to_string(),
+                ..chain("SPY", price, 0.0)
+            });
+        }
+        assert_eq!(
+            prices.closes["SPY"],
+            vec![
+                ("01/10/22".to_string(), 100.0),
+                ("01/11/22".to_string(), 102.0),
+                ("01/12/22".to_string(), 100.0),
+                ("01/13/22".to_string(), 103.0),
+            ]
+        );
+        assert!(prices.realized_volatility("SPY", 4).is_none());
+        let volatility = prices.realized_volatility("SPY", 3).unwrap();
+        assert!(volatility > 0.2 && volatility < 0.6);
+    }
+
+    #[test]
+    fn refuses_malformed_positions() {
+        let directory = std::env::temp_dir().join("options-scan-sizing-test");
+        std::fs::create_dir_all(&directory).unwrap();
+        let positions = directory.join("positions.json");
+        std::fs::write(&positions, r#"[{"symbol": "SPY", "quantity": "ten"}]"#).unwrap();
+        let prices = directory.join("price-history");
+        let loaded = PositionSizer::load(
+            directory.join("sizing-config.json").to_str().unwrap(),
+            positions.to_str().unwrap(),
+            prices.to_str().unwrap(),
+        );
+        assert!(loaded.is_err());
+        std::fs::write(
+            &positions,
+            r#"[{"symbol": "SPY", "quantity": 10, "average_price": 450.0}]"#,
+        )
+        .unwrap();
+        let sizer = PositionSizer::load(
+            directory.join("sizing-config.json").to_str().unwrap(),
+            positions.to_str().unwrap(),
+            prices.to_str().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(sizer.holdings.len(), 1);
+        assert_eq!(sizer.prices, PriceHistory::default());
+        let config = directory.join("sizing-config.json");
+        std::fs::write(&config, r#"{"account_value": "all of it"}"#).unwrap();
+        let loaded = PositionSizer::load(
+            config.to_str().unwrap(),
+            positions.to_str().unwrap(),
+            prices.to_str().unwrap(),
+        );
+        std::fs::remove_file(&config).unwrap();
+        assert!(loaded.unwrap_err().contains("sizing-config.json"));
+    }
+}
@@ -1,13 +1,19 @@
-use crate::single_options::OptionType;
+use crate::leg_matching::gcd;
+use crate::leg_matching::match_legs;
+use crate::others::round_to_decimals;
+use crate::payoff::{TimeView, VolatilityView};
+use crate::rolls::RollType;
+use crate::single_options::{OptionData, OptionType};
 use crate::trades::ConditionID;
 use crate::trades::Exchange;
 use crate::trades::Expectation;
+use crate::trades::FillStyle;
 use crate::trades::OptionTrade;
 use crate::trades::OrderAction;
+use crate::trades::TransactionType;
 use itertools::Itertools;
 use serde::Deserialize;
 use serde::Serialize;
-use std::collections::HashMap;
 #[derive(Debug, Serialize, PartialEq, PartialOrd, Clone, Deserialize, Copy)]
 pub enum SpreadType {
     Credit,
@@ -18,8 +24,6 @@ pub enum SpreadType {
 #[derive(Debug, Serialize, Clone, Deserialize, PartialEq)]
 pub enum SpreadName {
     //1 leg
-    #[serde(rename(serialize = "Covered Call"))]
-    CoveredCall,
     CoveredPut,
     LongPut,
     ShortPut,
@@ -33,8 +37,6 @@ pub enum SpreadName {
     Calendar,
     Synthetic,
     Ladder,
-    #[serde(rename(serialize = "Synthetic Call"))]
-    SyntheticCall,
     #[serde(rename(serialize = "Synthetic Put"))]
     SyntheticPut,
     #[serde(rename(serialize = "Risk Reversal"))]
@@ -44,7 +46,13 @@ pub enum SpreadName {
     Butterfly,
     #[serde(rename(serialize = "Unbalanced Butterfly"))]
     UnbalancedButterfly,
+    #[serde(rename(serialize = "Broken Wing Butterfly"))]
+    BrokenWingButterfly,
+    #[serde(rename(serialize = "Christmas Tree"))]
+    ChristmasTree,
     Ratio,
+    #[serde(rename(serialize = "Back Spread"))]
+    BackSpread,
     // 4 legs
     #[serde(rename(serialize = "Iron Condoor"))]
     IronCondoor,
@@ -54,6 +62,12 @@ pub enum SpreadName {
     // with stock
     Conversion,
     Reversal,
+    #[serde(rename(serialize = "Buy Write"))]
+    BuyWrite,
+    #[serde(rename(serialize = "Married Put"))]
+    MarriedPut,
+    #[serde(rename(serialize = "Delta Neutral"))]
+    DeltaNeutral,
     Unrecognized,
     UnrecognizedWithStock,
 }
@@ -73,61 +87,171 @@ pub struct OptionSpread {
     pub condition_id: ConditionID,
     pub exchange: Exchange,
     pub leg_number: usize,
+    pub ratio: String,
     pub summary: String,
     pub opening_trade: bool,
     pub sequence_numbers: String,
+    pub edge_vs_mid: f64,
+    pub edge_vs_theo: f64,
+    /// How the print filled, for a single option. Multi-leg spreads leave it to their legs.
+    pub edge_vs_mid_vol_points: f64,
+    pub spread_aggressiveness: f64,
+    pub fill_style: FillStyle,
+    pub max_profit: f64,
+    pub max_loss: f64,
+    pub breakevens: String,
+    pub probability_of_profit: f64,
+    /// Reg-T buying power the spread takes, with the rules applied.
+    pub reg_t_margin: f64,
+    pub margin_rules: String,
+    /// Worst loss over the portfolio margin stress range.
+    pub portfolio_margin: f64,
+    /// `max_profit` as a percent of each requirement.
+    pub return_on_margin: f64,
+    pub return_on_portfolio_margin: f64,
+    pub position_delta: f64,
+    pub position_gamma: f64,
+    pub position_vega: f64,
+    pub position_theta: f64,
+    pub volatility_view: VolatilityView,
+    pub time_view: TimeView,
+    pub pin_strike: Option<f64>,
+    pub outlook: String,
+    #[serde(skip_serializing, default)]
+    pub legs: Vec<SpreadLeg>,
+    #[serde(skip_serializing, default)]
+    pub stock_leg: Option<StockLeg>,
+    pub roll_type: Option<RollType>,
 }
-pub fn get_spreads(trades: Vec<OptionTrade>) -> Vec<OptionSpread> {
-    use SpreadName::*;
-    let mut output_vec: Vec<OptionSpread> = Vec::new();
-    let mut spreads: HashMap<String, Vec<&OptionTrade>> = HashMap::new();
-    let mut multilegs = trades
-        .iter()
-        .filter(|trade| trade.condition_id.is_multi_leg())
-        .collect_vec();
-    multilegs.sort_unstable_by_key(|trade| {
-        chrono::NaiveTime::parse_from_str(&trade.timestamp, "%H:%M:%S.%3f").unwrap()
-    });
-    for trade in multilegs {
-        let key = format!(
-            "{}{:#?}{:#?}{}",
-            trade.timestamp, trade.exchange_id, trade.condition_id, trade.option_trade_size
-        );
-        if !spreads.contains_key(&key) {
-            let mut trades_in_this_key = trades
-                .iter()
-                .filter(|matching_trade| {
-                    trade.timestamp == matching_trade.timestamp
-                        && trade.condition_id == matching_trade.condition_id
-                        && trade.exchange_id == matching_trade.exchange_id
-                        && trade.option_trade_size == matching_trade.option_trade_size
-                })
-                .collect_vec();
-            trades_in_this_key.sort_unstable_by_key(|trade| {
-                chrono::NaiveTime::parse_from_str(&trade.timestamp, "%H:%M:%S.%3f").unwrap()
-            });
-            if !verify_spread_legs(&mut trades_in_this_key) {
-                for second_trade in &trades_in_this_key {
-                    let by_size = trades_in_this_key
-                        .clone()
-                        .into_iter()
-                        .filter(|second_match_trade| {
-                            second_trade.option_trade_size == second_match_trade.option_trade_size
-                        })
-                        .collect_vec();
-                    let secondary_key = format!("{}{}", key, second_trade.option_trade_size);
-                    if !spreads.contains_key(&secondary_key) {
-                        spreads.insert(secondary_key, by_size);
-                    }
-                }
-            } else {
-                spreads.insert(key, trades_in_this_key);
+
+#[derive(Debug, Serialize, Clone, Deserialize, PartialEq)]
+pub struct SpreadLeg {
+    pub option_symbol: String,
+    pub strike: f64,
+    pub expiry: String,
+    pub option_type: OptionType,
+    pub side: OrderAction,
+    pub size: i64,
+    pub price: Option<f64>,
+    pub iv: f64,
+    pub delta: f64,
+    pub condition_id: ConditionID,
+    pub transaction_estimate: TransactionType,
+    #[serde(default)]
+    pub edge_vs_mid_vol_points: f64,
+    /// Where in the quoted spread the leg printed, -1 on the bid to 1 on the ask.
+    #[serde(default)]
+    pub spread_aggressiveness: f64,
+    #[serde(default = "crate::trades::to_be_calculated_fill_style")]
+    pub fill_style: FillStyle,
+}
+impl SpreadLeg {
+    pub fn from_trade(trade: &OptionTrade) -> Self {
+        Self {
+            option_symbol: trade.symbol.clone(),
+            strike: trade.strike,
+            expiry: trade.expiry.clone(),
+            option_type: trade.option_type,
+            side: trade.order_action,
+            size: trade.option_trade_size,
+            price: trade.option_trade_price,
+            iv: trade.iv,
+            delta: trade.delta,
+            condition_id: trade.condition_id,
+            transaction_estimate: trade.transaction_estimate,
+            edge_vs_mid_vol_points: trade.edge_vs_mid_vol_points,
+            spread_aggressiveness: trade.spread_aggressiveness,
+            fill_style: trade.fill_style,
+        }
+    }
+    /// One contract of a quoted option opened at the natural price.
+    pub fn from_option(option: &OptionData, side: OrderAction) -> Self {
+        Self {
+            option_symbol: option.symbol.clone(),
+            strike: option.strike,
+            expiry: option.expiration_date.clone(),
+            option_type: option.kind,
+            side,
+            size: 1,
+            price: match side {
+                OrderAction::Sold => option.bid_price,
+                _ => option.ask_price,
+            },
+            iv: option.iv,
+            delta: option.delta,
+            condition_id: ConditionID::Regular,
+            transaction_estimate: match side {
+                OrderAction::Sold => TransactionType::SellToOpen,
+                _ => TransactionType::BuyToOpen,
+            },
+            edge_vs_mid_vol_points: 0.0,
+            spread_aggressiveness: 0.0,
+            fill_style: FillStyle::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Deserialize, PartialEq)]
+pub struct StockLeg {
+    pub side: OrderAction,
+    pub shares: i64,
+    pub price: f64,
+    /// true when the share count was derived from the option legs rather than reported
+    pub inferred: bool,
+}
+impl StockLeg {
+    pub fn signed_shares(&self) -> f64 {
+        match self.side {
+            OrderAction::Bought => self.shares as f64,
+            OrderAction::Sold => -self.shares as f64,
+            OrderAction::Unknown => 0.0,
+        }
+    }
+    pub fn amount_paid(&self) -> f64 {
+        self.signed_shares() * self.price
+    }
+    fn summary(&self) -> String {
+        match self.side {
+            OrderAction::Bought => {
+                format!("Bought {} shares of stock at {}|", self.shares, self.price)
+            }
+            OrderAction::Sold => {
+                format!("Shorted {} shares of stock at {}|", self.shares, self.price)
             }
+            OrderAction::Unknown => {
+                format!("Traded {} shares of stock at {}|", self.shares, self.price)
+            }
+        }
+    }
+}
+
+/// A spread with its legs nested, for JSON output. The CSV keeps the flat `OptionSpread`.
+#[derive(Debug, Serialize)]
+pub struct NestedSpread<'a> {
+    #[serde(flatten)]
+    pub spread: &'a OptionSpread,
+    pub legs: &'a [SpreadLeg],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stock_leg: Option<&'a StockLeg>,
+}
+impl OptionSpread {
+    pub fn nested(&self) -> NestedSpread<'_> {
+        NestedSpread {
+            spread: self,
+            legs: &self.legs,
+            stock_leg: self.stock_leg.as_ref(),
         }
     }
-    for trades_in_spread in spreads.values() {
+}
+
+pub fn get_spreads(trades: Vec<OptionTrade>) -> Vec<OptionSpread> {
+    use SpreadName::*;
+    let mut output_vec: Vec<OptionSpread> = Vec::new();
+    let orders = match_legs(&trades);
+    for trades_in_spread in &orders {
         let mut net_value: f64 = 0.0;
-        let mut expiration_date = chrono::Local::now().naive_local().date();
+        let mut expiration_date =
+            chrono::NaiveDate::parse_from_str(&trades_in_spread[0].expiry, "%F").unwrap();
         let mut dte = 0;
         let mut net_iv = 0.0;
         let mut delta: f64 = 0.0;
@@ -138,12 +262,15 @@ pub fn get_spreads(trades: Vec<OptionTrade>) -> Vec<OptionSpread> {
         let mut poisoned = false;
         let mut same_date = true;
         let mut same_strike = true;
-        let mut all_different_strikes = true;
         let mut same_action = true;
         let mut same_amount = true;
         let mut current_delta = 0.0;
+        let mut edge_vs_mid = 0.0;
+        let mut edge_vs_theo = 0.0;
         for trade in trades_in_spread {
             net_value += trade.amount_paid();
+            edge_vs_mid += trade.edge_vs_mid;
+            edge_vs_theo += trade.edge_vs_theo;
             if chrono::NaiveDate::parse_from_str(&trade.expiry, "%F").unwrap() > expiration_date {
                 expiration_date = chrono::NaiveDate::parse_from_str(&trade.expiry, "%F").unwrap();
             }
@@ -165,93 +292,41 @@ pub fn get_spreads(trades: Vec<OptionTrade>) -> Vec<OptionSpread> {
             poisoned = trade.order_action == OrderAction::Unknown || poisoned;
             same_date = same_date && trade.expiry == trades_in_spread[0].expiry;
             same_strike = same_strike && trade.strike == trades_in_spread[0].strike;
-            all_different_strikes =
-                all_different_strikes && trade.strike != trades_in_spread[0].strike;
             same_action = same_action && trade.order_action == trades_in_spread[0].order_action;
             same_amount =
                 same_amount && trade.option_trade_size == trades_in_spread[0].option_trade_size;
         }
         let same_type = all_call || all_put;
-        let spread_name: SpreadName = match trades_in_spread.len() {
-            1 => {
-                if trades_in_spread[0].condition_id.includes_stock_trade() {
-                    if trades_in_spread[0].is_call_sell() {
-                        CoveredCall
-                    } else if trades_in_spread[0].is_put_sell() {
-                        CoveredPut
-                    } else if trades_in_spread[0].is_put_buy() {
-                        SyntheticCall
-                    } else if trades_in_spread[0].is_call_buy() {
-                        SyntheticPut
-                    } else {
-                        UnrecognizedWithStock
-                    }
-                } else {
-                    match trades_in_spread[0].option_type {
-                        OptionType::Call => match trades_in_spread[0].order_action {
-                            OrderAction::Bought => LongCall,
-                            OrderAction::Sold => ShortCall,
-                            OrderAction::Unknown => Unrecognized,
-                        },
-                        OptionType::Put => match trades_in_spread[0].order_action {
-                            OrderAction::Bought => LongPut,
-                            OrderAction::Sold => ShortPut,
-                            OrderAction::Unknown => Unrecognized,
-                        },
-                    }
-                }
-            }
-            2 => match trades_in_spread[0].condition_id.includes_stock_trade() {
-                true => {
-                    if (trades_in_spread[0].is_call_sell() && trades_in_spread[1].is_put_buy())
-                        || (trades_in_spread[1].is_call_sell() && trades_in_spread[0].is_put_buy())
-                    {
-                        if same_strike {
-                            Conversion
-                        } else {
-                            Collar
-                        }
-                    } else if (trades_in_spread[0].is_put_sell()
-                        && trades_in_spread[1].is_call_buy())
-                        || (trades_in_spread[1].is_put_sell() && trades_in_spread[0].is_call_buy())
-                    {
-                        if same_strike {
-                            Reversal
-                        } else {
-                            Collar
-                        }
-                    } else if trades_in_spread
-                        .iter()
-                        .filter(|trade| trade.is_call_sell())
-                        .collect_vec()
-                        .len()
-                        == trades_in_spread.len()
-                    {
-                        CoveredCall
-                    } else if trades_in_spread
-                        .iter()
-                        .filter(|trade| trade.is_put_sell())
-                        .collect_vec()
-                        .len()
-                        == trades_in_spread.len()
-                    {
-                        CoveredPut
-                    } else if (trades_in_spread[0].is_put_buy()
-                        && trades_in_spread[1].is_put_sell())
-                        || (trades_in_spread[1].is_put_buy() && trades_in_spread[0].is_put_sell())
-                    {
-                        SyntheticCall
-                    } else if (trades_in_spread[0].is_call_buy()
-                        && trades_in_spread[1].is_call_sell())
-                        || (trades_in_spread[1].is_call_buy() && trades_in_spread[0].is_call_sell())
-                    {
-                        SyntheticPut
-                    } else {
-                        UnrecognizedWithStock
-                    }
-                }
-                false => {
-                    if !same_strike && same_date && !same_action {
+        let all_different_strikes = trades_in_spread
+            .iter()
+            .map(|trade| trade.strike)
+            .sorted_by(|a, b| a.partial_cmp(b).unwrap())
+            .dedup()
+            .count()
+            == trades_in_spread.len();
+        let mut stock_leg: Option<StockLeg> = None;
+        let spread_name: SpreadName = if trades_in_spread[0].condition_id.includes_stock_trade() {
+            let (name, stock) = classify_stock_tied(trades_in_spread, same_strike, delta);
+            stock_leg = stock;
+            name
+        } else {
+            match trades_in_spread.len() {
+                1 => match trades_in_spread[0].option_type {
+                    OptionType::Call => match trades_in_spread[0].order_action {
+                        OrderAction::Bought => LongCall,
+                        OrderAction::Sold => ShortCall,
+                        OrderAction::Unknown => Unrecognized,
+                    },
+                    OptionType::Put => match trades_in_spread[0].order_action {
+                        OrderAction::Bought => LongPut,
+                        OrderAction::Sold => ShortPut,
+                        OrderAction::Unknown => Unrecognized,
+                    },
+                },
+                2 => {
+                    if !same_amount && same_type && !same_strike && same_date && !same_action {
+                        classify_ratio_spread(trades_in_spread)
+                    } else if !same_strike && same_date && !same_action && same_type {
                         Vertical
                     } else if same_strike && !same_date && !same_action {
                         Calendar
@@ -269,43 +344,37 @@ pub fn get_spreads(trades: Vec<OptionTrade>) -> Vec<OptionSpread> {
                         Unrecognized
                     }
                 }
-            },
-            3 => {
-                let mut sizes = trades_in_spread
-                    .iter()
-                    .map(|trade| trade.option_trade_size)
-                    .collect_vec();
-                sizes.sort_unstable();
-                let total_sizes = sizes.iter().dedup().collect_vec().len();
-                if !same_action && total_sizes == 2 {
-                    Butterfly
-                } else if !same_action && !same_amount && total_sizes == 3 {
-                    UnbalancedButterfly
-                } else if same_action && same_type {
-                    Ladder
-                } else {
-                    Unrecognized
+                3 => {
+                    if same_action && same_type {
+                        Ladder
+                    } else if same_type && same_date {
+                        classify_three_legs(trades_in_spread)
+                    } else {
+                        Unrecognized
+                    }
                 }
-            }
-            4 => {
-                let mut strikes = trades_in_spread
-                    .iter()
-                    .map(|trade| trade.strike)
-                    .collect_vec();
-                strikes.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
-                if all_different_strikes && !same_action && same_date && !same_type {
-                    IronCondoor
-                } else if same_date && !same_action {
-                    if let Some(inner_call) =
-                        trades_in_spread.iter().find(|trade| trade.is_call_sell())
-                    {
-                        if let Some(inner_put) =
-                            trades_in_spread.iter().find(|trade| trade.is_put_sell())
+                4 => {
+                    let mut strikes = trades_in_spread
+                        .iter()
+                        .map(|trade| trade.strike)
+                        .collect_vec();
+                    strikes.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+                    if all_different_strikes && !same_action && same_date && !same_type {
+                        IronCondoor
+                    } else if same_date && !same_action {
+                        if let Some(inner_call) =
+                            trades_in_spread.iter().find(|trade| trade.is_call_sell())
                         {
-                            if inner_call.strike == inner_put.strike {
-                                IronButterfly
-                            } else if strikes.iter().dedup().collect_vec().len() == 2 {
-                                Box
+                            if let Some(inner_put) =
+                                trades_in_spread.iter().find(|trade| trade.is_put_sell())
+                            {
+                                if inner_call.strike == inner_put.strike {
+                                    IronButterfly
+                                } else if strikes.iter().dedup().collect_vec().len() == 2 {
+                                    Box
+                                } else {
+                                    Unrecognized
+                                }
                             } else {
                                 Unrecognized
                             }
@@ -315,73 +384,22 @@ pub fn get_spreads(trades: Vec<OptionTrade>) -> Vec<OptionSpread> {
                     } else {
                         Unrecognized
                     }
-                } else {
-                    Unrecognized
                 }
-            }
-            _ => {
-                if same_action && same_type {
-                    Ladder
-                } else {
-                    Unrecognized
+                _ => {
+                    if same_action && same_type {
+                        Ladder
+                    } else {
+                        Unrecognized
+                    }
                 }
             }
         };
         let mut leg_number = trades_in_spread.len();
-        if trades_in_spread[0].condition_id.includes_stock_trade() {
-            let total_contracts: f64 = trades_in_spread
-                .iter()
-                .map(|trade| trade.option_trade_size)
-                .sum::<i64>() as f64;
-            let dollar_value_of_shares = match spread_name {
-                CoveredCall | Conversion | SyntheticCall => {
-                    total_contracts * 100.0 * trades_in_spread[0].implied_underlying_ask
-                }
-                CoveredPut | Reversal | SyntheticPut => {
-                    -total_contracts * 100.0 * trades_in_spread[0].implied_underlying_bid
-                }
-                Unrecognized => 0.0,
-                UnrecognizedWithStock => 0.0,
-                _ => 0.0,
-            };
-            net_value += dollar_value_of_shares;
-            let stock_summary = match spread_name {
-                CoveredCall | Conversion | SyntheticCall => format!(
-                    "Bought {} shares of stock at {}|",
-                    100 * trades_in_spread[0].option_trade_size,
-                    trades_in_spread[0].implied_underlying_ask
-                ),
-                CoveredPut | Reversal | SyntheticPut => format!(
-                    "Shorted {} shares of stock at {}|",
-                    100 * trades_in_spread[0].option_trade_size,
-                    trades_in_spread[0].implied_underlying_bid
-                ),
-                _ => format!(
-                    "Traded {} shares of stock at {}|",
-                    100 * trades_in_spread[0].option_trade_size,
-                    trades_in_spread[0].implied_underlying_mid
-                ),
-            };
-            let delta_of_shares = total_contracts * 100.0;
-            let delta_adjustment: f64 = match spread_name {
-                CoveredCall | Conversion | SyntheticCall => delta_of_shares,
-                CoveredPut | Reversal | SyntheticPut => -delta_of_shares,
-                Collar => {
-                    if let Some(sold_option) = trades_in_spread.iter().find(|trade| trade.is_sell())
-                    {
-                        match sold_option.option_type {
-                            OptionType::Call => delta_of_shares,
-                            OptionType::Put => -delta_of_shares,
-                        }
-                    } else {
-                        0.0
-                    }
-                }
-                _ => 0.0,
-            };
-            delta += delta_adjustment;
-            current_delta += delta_adjustment;
-            summary.push_str(&stock_summary);
+        if let Some(stock) = &stock_leg {
+            net_value += stock.amount_paid();
+            delta += stock.signed_shares();
+            current_delta += stock.signed_shares();
+            summary.push_str(&stock.summary());
             leg_number += 1;
         }
         let spread_type = if net_value > 0.0 {
@@ -396,6 +414,10 @@ pub fn get_spreads(trades: Vec<OptionTrade>) -> Vec<OptionSpread> {
         } else {
             Expectation::Neutral
         };
+        let single_leg = match trades_in_spread[..] {
+            [trade] => Some(trade),
+            _ => None,
+        };
         let spread = OptionSpread {
             symbol: trades_in_spread[0].root.clone(),
             spread_name,
@@ -410,56 +432,317 @@ pub fn get_spreads(trades: Vec<OptionTrade>) -> Vec<OptionSpread> {
             condition_id: trades_in_spread[0].condition_id.clone(),
             summary,
             leg_number,
+            ratio: leg_ratio(trades_in_spread),
             opening_trade,
             sequence_numbers: get_consecutive_summary(trades_in_spread.to_vec()),
             exchange: trades_in_spread[0].exchange_id,
             current_delta,
+            edge_vs_mid: round_to_decimals(edge_vs_mid, 2),
+            edge_vs_theo: round_to_decimals(edge_vs_theo, 2),
+            edge_vs_mid_vol_points: single_leg.map_or(0.0, |trade| trade.edge_vs_mid_vol_points),
+            spread_aggressiveness: single_leg.map_or(0.0, |trade| trade.spread_aggressiveness),
+            fill_style: single_leg.map_or(FillStyle::Unknown, |trade| trade.fill_style),
+            max_profit: 0.0,
+            max_loss: 0.0,
+            breakevens: String::new(),
+            probability_of_profit: 0.0,
+            reg_t_margin: 0.0,
+            margin_rules: String::new(),
+            portfolio_margin: 0.0,
+            return_on_margin: 0.0,
+            return_on_portfolio_margin: 0.0,
+            position_delta: 0.0,
+            position_gamma: 0.0,
+            position_vega: 0.0,
+            position_theta: 0.0,
+            volatility_view: VolatilityView::Flat,
+            time_view: TimeView::Flat,
+            pin_strike: None,
+            outlook: String::new(),
+            legs: trades_in_spread
+                .iter()
+                .map(|trade| SpreadLeg::from_trade(trade))
+                .collect(),
+            stock_leg,
+            roll_type: None,
         };
         if !poisoned {
             output_vec.push(spread)
         }
     }
-    output_vec.into_iter().dedup().collect_vec()
+    output_vec
 }
 
-fn verify_spread_legs(legs: &mut Vec<&OptionTrade>) -> bool {
-    legs.sort_unstable_by_key(|trade| trade.seq_no);
-    let mut consecutive = true;
-    for n in 1..legs.len() - 1 {
-        consecutive = consecutive && legs[n].seq_no == legs[n - 1].seq_no + 1;
+/// Names an order printed together with stock and works out its stock leg. The feed's tied
+/// share count is used when present; otherwise shares follow from the structure, and orders
+/// that match no covered structure are taken as delta neutral ties sized to the option delta.
+fn classify_stock_tied(
+    legs: &[&OptionTrade],
+    same_strike: bool,
+    option_delta: f64,
+) -> (SpreadName, Option<StockLeg>) {
+    use SpreadName::*;
+    let all = |check: fn(&OptionTrade) -> bool| legs.iter().all(|leg| check(leg));
+    let any = |check: fn(&OptionTrade) -> bool| legs.iter().any(|leg| check(leg));
+    let covered_shares = 100 * legs.iter().map(|leg| leg.option_trade_size).sum::<i64>();
+    let pair_shares = 100 * legs[0].option_trade_size;
+    let (structure, structural_shares) = if all(OptionTrade::is_call_sell) {
+        (BuyWrite, covered_shares)
+    } else if all(OptionTrade::is_put_sell) {
+        (CoveredPut, -covered_shares)
+    } else if all(OptionTrade::is_put_buy) {
+        (MarriedPut, covered_shares)
+    } else if all(OptionTrade::is_call_buy) {
+        (SyntheticPut, -covered_shares)
+    } else if legs.len() == 2 && any(OptionTrade::is_call_sell) && any(OptionTrade::is_put_buy) {
+        (if same_strike { Conversion } else { Collar }, pair_shares)
+    } else if legs.len() == 2 && any(OptionTrade::is_call_buy) && any(OptionTrade::is_put_sell) {
+        (if same_strike { Reversal } else { Collar }, -pair_shares)
+    } else {
+        (DeltaNeutral, -option_delta.round() as i64)
+    };
+    let reported = legs
+        .iter()
+        .find_map(|leg| leg.tied_stock_size.map(|size| (size, leg.tied_stock_price)));
+    let (name, shares, reported_price) = match reported {
+        Some((size, price)) => {
+            let offsets_delta = option_delta != 0.0
+                && (size as f64 + option_delta).abs() <= 0.25 * option_delta.abs();
+            if offsets_delta && size.abs() != structural_shares.abs() {
+                (DeltaNeutral, size, price)
+            } else {
+                (structure, size, price)
+            }
+        }
+        None => (structure, structural_shares, None),
+    };
+    if shares == 0 {
+        return (UnrecognizedWithStock, None);
     }
-    if consecutive {
-        return true;
+    let quoted = if shares > 0 {
+        legs[0].implied_underlying_ask
     } else {
-        legs.sort_unstable_by_key(|trade| trade.exchange_seq_no);
-        consecutive = true;
-        for n in 1..legs.len() - 1 {
-            consecutive = consecutive && legs[n].exchange_seq_no == legs[n - 1].exchange_seq_no + 1;
+        legs[0].implied_underlying_bid
+    };
+    let price = reported_price.unwrap_or(if quoted > 0.0 {
+        quoted
+    } else {
+        legs[0].implied_underlying_mid
+    });
+    (
+        name,
+        Some(StockLeg {
+            side: if shares > 0 {
+                OrderAction::Bought
+            } else {
+                OrderAction::Sold
+            },
+            shares: shares.abs(),
+            price,
+            inferred: reported.is_none(),
+        }),
+    )
+}
+
+/// Leg sizes in strike order reduced to whole numbers, such as `1x2` or `1x3x2`.
+pub fn leg_ratio(legs: &[&OptionTrade]) -> String {
+    let legs = legs
+        .iter()
+        .sorted_by(|a, b| {
+            a.strike
+                .partial_cmp(&b.strike)
+                .unwrap()
+                .then_with(|| a.expiry.cmp(&b.expiry))
+        })
+        .collect_vec();
+    let divisor = legs
+        .iter()
+        .fold(0, |acc, leg| gcd(acc, leg.option_trade_size))
+        .max(1);
+    legs.iter()
+        .map(|leg| leg.option_trade_size / divisor)
+        .join("x")
+}
+
+/// Two legs of one type and expiration with unequal sizes. More contracts sold than bought is
+/// a ratio spread, more bought than sold a back spread.
+fn classify_ratio_spread(legs: &[&OptionTrade]) -> SpreadName {
+    let bought: i64 = legs
+        .iter()
+        .filter(|leg| leg.is_buy())
+        .map(|leg| leg.option_trade_size)
+        .sum();
+    let sold: i64 = legs
+        .iter()
+        .filter(|leg| leg.is_sell())
+        .map(|leg| leg.option_trade_size)
+        .sum();
+    if sold > bought {
+        SpreadName::Ratio
+    } else {
+        SpreadName::BackSpread
+    }
+}
+
+/// Three legs of one type and expiration, told apart by which legs share a side and by the
+/// ratio of their sizes in strike order.
+fn classify_three_legs(legs: &[&OptionTrade]) -> SpreadName {
+    use SpreadName::*;
+    let legs = legs
+        .iter()
+        .sorted_by(|a, b| a.strike.partial_cmp(&b.strike).unwrap())
+        .collect_vec();
+    let (low, body, high) = (legs[0], legs[1], legs[2]);
+    if low.strike == body.strike || body.strike == high.strike {
+        return Unrecognized;
+    }
+    let divisor = legs
+        .iter()
+        .fold(0, |acc, leg| gcd(acc, leg.option_trade_size))
+        .max(1);
+    let ratio = legs
+        .iter()
+        .map(|leg| leg.option_trade_size / divisor)
+        .collect_vec();
+    if low.order_action == high.order_action && body.order_action != low.order_action {
+        let equidistant = ((body.strike - low.strike) - (high.strike - body.strike)).abs() < 0.001;
+        if ratio == [1, 2, 1] {
+            if equidistant {
+                Butterfly
+            } else {
+                BrokenWingButterfly
+            }
+        } else if ratio[1] == ratio[0] + ratio[2] {
+            UnbalancedButterfly
+        } else {
+            Ratio
         }
-        consecutive
+    } else if ratio == [1, 1, 1]
+        && (low.order_action != body.order_action || high.order_action != body.order_action)
+    {
+        ChristmasTree
+    } else {
+        Unrecognized
     }
 }
 
 fn get_consecutive_summary(legs: Vec<&OptionTrade>) -> String {
-    let mut output = String::new();
-    let mut legs = legs.clone();
+    let mut legs = legs;
     legs.sort_unstable_by_key(|trade| trade.seq_no);
-    let mut consecutive = true;
-    for n in 1..legs.len() - 1 {
-        consecutive = consecutive && legs[n].seq_no == legs[n - 1].seq_no - 1;
-    }
+    let consecutive = legs
+        .windows(2)
+        .all(|pair| pair[1].seq_no == pair[0].seq_no + 1);
     if consecutive {
-        output.push_str("seq no ");
-        legs.iter()
-            .map(|trade| output.push_str(&format!("{}-", trade.seq_no)))
-            .collect_vec();
-        output
+        format!(
+            "seq no {}-",
+            legs.iter().map(|trade| trade.seq_no).join("-")
+        )
     } else {
-        output.push_str("ex seq no ");
         legs.sort_unstable_by_key(|trade| trade.exchange_seq_no);
-        legs.iter()
-            .map(|trade| output.push_str(&format!("{}-", trade.exchange_seq_no)))
-            .collect_vec();
-        output
+        format!(
+            "ex seq no {}-",
+            legs.iter().map(|trade| trade.exchange_seq_no).join("-")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixture_trades;
+
+    #[test]
+    fn recognizes_ratio_spread_with_its_ratio() {
+        let spreads = get_spreads(fixture_trades(include_str!(
+            "../tests/fixtures/ratio_spread.json"
+        )));
+        assert_eq!(spreads.len(), 1);
+        assert_eq!(spreads[0].spread_name, SpreadName::Ratio);
+        assert_eq!(spreads[0].ratio, "1x2");
+    }
+
+    #[test]
+    fn recognizes_stock_tied_orders() {
+        let spreads = get_spreads(fixture_trades(include_str!(
+            "../tests/fixtures/stock_tied.json"
+        )));
+        assert_eq!(spreads.len(), 2);
+        let buy_write = &spreads[0];
+        assert_eq!(buy_write.spread_name, SpreadName::BuyWrite);
+        let stock = buy_write.stock_leg.as_ref().unwrap();
+        assert_eq!(
+            (stock.side, stock.shares, stock.inferred),
+            (OrderAction::Bought, 20000, true)
+        );
+        assert_eq!(buy_write.delta_when_opened, 20000.0 - 200.0 * 38.0);
+        let delta_neutral = &spreads[1];
+        assert_eq!(delta_neutral.spread_name, SpreadName::DeltaNeutral);
+        let stock = delta_neutral.stock_leg.as_ref().unwrap();
+        assert_eq!(
+            (stock.side, stock.shares, stock.inferred),
+            (OrderAction::Sold, 5000, false)
+        );
+        assert_eq!(delta_neutral.delta_when_opened, 0.0);
+    }
+
+    #[test]
+    fn nests_legs_in_json_output() {
+        let spreads = get_spreads(fixture_trades(include_str!(
+            "../tests/fixtures/vertical.json"
+        )));
+        let json = serde_json::to_value(spreads[0].nested()).unwrap();
+        assert_eq!(json["spread_name"], "Vertical");
+        assert_eq!(json["legs"].as_array().unwrap().len(), 2);
+        assert_eq!(json["legs"][0]["option_symbol"], ".SPY300118C470");
+        assert_eq!(json["legs"][1]["side"], "Sold");
+    }
+
+    #[test]
+    fn recognizes_one_three_two_butterfly() {
+        let spreads = get_spreads(fixture_trades(include_str!(
+            "../tests/fixtures/unbalanced_butterfly.json"
+        )));
+        assert_eq!(spreads.len(), 1);
+        assert_eq!(spreads[0].spread_name, SpreadName::UnbalancedButterfly);
+        assert_eq!(spreads[0].ratio, "1x3x2");
+    }
+
+    #[test]
+    fn recognizes_risk_reversal() {
+        let spreads = get_spreads(fixture_trades(include_str!(
+            "../tests/fixtures/risk_reversal.json"
+        )));
+        assert_eq!(spreads.len(), 1);
+        assert_eq!(spreads[0].spread_name, SpreadName::RiskReversal);
+    }
+
+    #[test]
+    fn recognizes_back_spread() {
+        let spreads = get_spreads(fixture_trades(include_str!(
+            "../tests/fixtures/back_spread.json"
+        )));
+        assert_eq!(spreads.len(), 1);
+        assert_eq!(spreads[0].spread_name, SpreadName::BackSpread);
+        assert_eq!(spreads[0].ratio, "1x2");
+    }
+
+    #[test]
+    fn recognizes_broken_wing_butterfly() {
+        let spreads = get_spreads(fixture_trades(include_str!(
+            "../tests/fixtures/broken_wing_butterfly.json"
+        )));
+        assert_eq!(spreads.len(), 1);
+        assert_eq!(spreads[0].spread_name, SpreadName::BrokenWingButterfly);
+        assert_eq!(spreads[0].ratio, "1x2x1");
+    }
+
+    #[test]
+    fn recognizes_christmas_tree() {
+        let spreads = get_spreads(fixture_trades(include_str!(
+            "../tests/fixtures/christmas_tree.json"
+        )));
+        assert_eq!(spreads.len(), 1);
+        assert_eq!(spreads[0].spread_name, SpreadName::ChristmasTree);
+        assert_eq!(spreads[0].ratio, "1x1x1");
     }
 }
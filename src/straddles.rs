@@ -1,13 +1,13 @@
-use std::{sync::{Arc, RwLock}};
 use serde::Serialize;
 use itertools::Itertools;
 
-use crate::{STRADDLE_LENGTH, models::OptionChain, others::round_to_decimals, single_options::OptionData};
+use crate::{STRADDLE_LENGTH, margin::MarginSummary, models::OptionChain, others::round_to_decimals, pricing::years_from_dte, single_options::OptionData, spreads::SpreadLeg, trades::OrderAction, vol_surface::VolSurface};
 
 #[derive(Debug, Serialize, Clone)]
 pub struct Straddle {
     pub underlying_symbol: String,
     pub expiration_date:String,
+    /// One standard deviation move of the underlying to expiration at its realized volatility.
     pub underlying_std_dev: f64,
     pub dte: i64,
     pub strike: f64,
@@ -20,17 +20,25 @@ pub struct Straddle {
     pub bottom_breakeven:f64,
     pub min_move_profit:f64,
     pub rank: f64,
+    pub reg_t_margin: f64,
+    pub margin_rules: String,
+    pub portfolio_margin: f64,
+    pub return_on_margin: f64,
+    pub return_on_portfolio_margin: f64,
+    #[serde(skip_serializing)]
+    pub legs: Vec<SpreadLeg>,
 }
 impl Straddle {
-    pub fn from_options(options:Vec<&&OptionData>, dev:f64) -> Self {
-        let asking_price = round_to_decimals(options.iter().map(|option|option.ask_price.unwrap()).sum::<f64>()+0.04,2);
-        let bid_price = round_to_decimals(options.iter().map(|option|option.bid_price.unwrap()).sum::<f64>()-0.04,2);
+    /// None when either option isn't quoted on both sides.
+    pub fn from_options(options:Vec<&&OptionData>, dev:f64) -> Option<Self> {
+        let asking_price = round_to_decimals(options.iter().map(|option|option.ask_price).sum::<Option<f64>>()?+0.04,2);
+        let bid_price = round_to_decimals(options.iter().map(|option|option.bid_price).sum::<Option<f64>>()?-0.04,2);
         let top_breakeven = options[0].strike + asking_price;
         let bottom_breakeven = options[0].strike - asking_price;
         let net_iv = 100.0*(options.iter().map(|option|option.iv).sum::<f64>());
         let ask_dev_ratio = 100.0*(asking_price/dev);
         let rank = 0.5*(ask_dev_ratio+net_iv);
-        Self {
+        Some(Self {
             expiration_date: options[0].expiration_date.clone(),
             dte: options[0].dte,
             strike: options[0].strike,
@@ -45,48 +53,53 @@ impl Straddle {
             bottom_breakeven,
             net_iv,
             rank,
+            reg_t_margin: 0.0,
+            margin_rules: String::new(),
+            portfolio_margin: 0.0,
+            return_on_margin: 0.0,
+            return_on_portfolio_margin: 0.0,
+            legs: options.iter().map(|option| SpreadLeg::from_option(option, OrderAction::Bought)).collect(),
+        })
+    }
+    /// Both options are bought, so profit is unbounded and the returns stay at 0.
+    pub fn with_margin(self, surface: &VolSurface, underlying: f64) -> Self {
+        let margin = MarginSummary::of(
+            &self.underlying_symbol,
+            &self.legs,
+            None,
+            surface,
+            underlying,
+            f64::INFINITY,
+        );
+        Self {
+            reg_t_margin: margin.reg_t_margin,
+            margin_rules: margin.margin_rules,
+            portfolio_margin: margin.portfolio_margin,
+            return_on_margin: margin.return_on_margin,
+            return_on_portfolio_margin: margin.return_on_portfolio_margin,
+            ..self
         }
     }
 }
-pub async fn get_straddles(data: Arc<RwLock<OptionChain>>, output: Arc<RwLock<Vec<Straddle>>>) {
+/// Long straddles at the strikes either side of the money, in expirations at least
+/// `STRADDLE_LENGTH` days out, that cost less than a one standard deviation move at the
+/// stock's realized volatility. Nothing is scanned without a realized volatility.
+pub fn get_straddles(option_chain: &OptionChain, surface: &VolSurface, realized_vol: Option<f64>) -> Vec<Straddle> {
     let mut output_vec:Vec<Straddle> = Vec::new();
-    let option_chain = data.read().unwrap();
-    if option_chain.underlying_std_dev == f64::INFINITY {return}
-    for expiration in &option_chain.expirations {
-        if expiration >= &STRADDLE_LENGTH {
-            let options_in_this_exp = option_chain.options.iter().filter(|option|&option.dte == expiration).collect_vec();
-            let mut strikes_in_this_exp:Vec<f64> = Vec::new();
-            for option in &options_in_this_exp {
-                if !strikes_in_this_exp.contains(&option.strike) {strikes_in_this_exp.push(option.strike)}
-            }
-            strikes_in_this_exp.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
-            let mut call_atm_strike = 0.0;
-            let mut put_atm_strike = 0.0;
-            if !strikes_in_this_exp.is_empty() {
-                for n in 1..strikes_in_this_exp.len()-1 {
-                    if strikes_in_this_exp[n] > option_chain.underlying_mid {
-                        put_atm_strike = strikes_in_this_exp[n];
-                        call_atm_strike = strikes_in_this_exp[n-1];
-                        break;  
+    let Some(realized_vol) = realized_vol else {return output_vec};
+    for expiration in option_chain.expirations() {
+        if expiration >= STRADDLE_LENGTH {
+            let dev = option_chain.underlying_mid * realized_vol * years_from_dte(expiration).sqrt();
+            let options_in_this_exp = option_chain.options.iter().filter(|option|option.dte == expiration).collect_vec();
+            for strike in option_chain.at_the_money_strikes() {
+                let straddle_options = options_in_this_exp.iter().filter(|option|option.strike == strike).collect_vec();
+                if straddle_options.len() == 2 {
+                    if let Some(straddle) = Straddle::from_options(straddle_options, dev) {
+                        if straddle.ask_dev_ratio < 100.0 {output_vec.push(straddle.with_margin(surface, option_chain.underlying_mid))};
                     }
-                }
+                };
             }
-            let call_straddle_options = options_in_this_exp.iter().filter(|option|option.strike == call_atm_strike).collect_vec();
-            let put_straddle_options = options_in_this_exp.iter().filter(|option|option.strike == put_atm_strike).collect_vec();
-            if call_straddle_options.len() == 2 {
-                let call_straddle = Straddle::from_options(call_straddle_options, option_chain.underlying_std_dev);
-                if call_straddle.ask_dev_ratio < 100.0 {output_vec.push(call_straddle)};
-            };
-            if put_straddle_options.len() == 2 {
-                let put_straddle = Straddle::from_options(put_straddle_options, option_chain.underlying_std_dev);
-                if put_straddle.ask_dev_ratio < 100.0 {output_vec.push(put_straddle)};
-            };
         }
     }
-    if !output_vec.is_empty() {
-        let mut write = output.write().unwrap();
-        write.append(&mut output_vec)
-    }
-    
+    output_vec
 }
-
@@ -1,3 +1,4 @@
+use crate::models::ChainSnapshot;
 use crate::models::DividendInformation;
 use crate::models::DividendPeriod;
 use crate::models::DividendsLock;
@@ -6,18 +7,25 @@ use crate::models::OptionsLock;
 use crate::models::ShortStockData;
 use crate::models::TradesLock;
 use crate::others::get_new_york_time;
+use crate::premium_flow::PremiumFlow;
+use crate::requests::get_beta_mt;
 use crate::requests::get_dividend_info_mt;
 use crate::requests::get_insider_data_mt;
+use crate::requests::get_market_cap_mt;
 use crate::requests::get_options_mt;
 use crate::requests::get_short_ratio_mt;
 use crate::requests::get_trades_mt;
+use crate::rolls::detect_rolls;
+use crate::scoring::BiasModel;
 use crate::single_options::OptionData;
 use crate::single_options::OptionType;
 use crate::spreads::get_spreads;
 use crate::trades::estimate_transaction;
 use crate::trades::Expectation;
+use crate::vol_surface::VolSurface;
 use crate::AMOUNT_IN_ACCOUNT;
 use crate::MONSTER_SIZE;
+use crate::SIZING_IV_DTE;
 use chrono::Datelike;
 use chrono::Duration;
 use chrono::NaiveDate;
@@ -29,29 +37,36 @@ impl OptionChain {
         symbol: &str,
         token: &str,
         short_fee_data: Option<&ShortStockData>,
+        bias_model: &BiasModel,
     ) -> Option<Self> {
-        let options_lock = Arc::new(std::sync::RwLock::new(OptionsLock::new(symbol, token)));
-        let divi_lock = Arc::new(std::sync::RwLock::new(DividendsLock::new(symbol)));
-        let trades_lock = Arc::new(std::sync::RwLock::new(TradesLock::new(symbol, token)));
-        let short_ratio = Arc::new(std::sync::RwLock::new((symbol, 0.0)));
-        let insider_data = Arc::new(std::sync::RwLock::new((symbol, 0.0)));
-        tokio::join!(
-            get_options_mt(options_lock.clone()),
-            get_dividend_info_mt(divi_lock.clone()),
-            get_trades_mt(trades_lock.clone()),
-            get_insider_data_mt(insider_data.clone()),
-            get_short_ratio_mt(short_ratio.clone()),
-        );
-        let dividend_info = divi_lock.read().unwrap().dividends.clone();
-        let mut trades = trades_lock.read().unwrap().trades.clone();
-        let options_lock = options_lock.read().unwrap();
-        let insiders = insider_data.read().unwrap().1;
-        let data = options_lock.options.clone();
-        let short_interest_percent = short_ratio.read().unwrap().1;
-        if data.options.is_empty() || trades.is_empty() {
+        let snapshot = ChainSnapshot::fetch(symbol, token).await;
+        if snapshot.data.options.is_empty() || snapshot.trades.is_empty() {
             return None;
         }
         println!("Obtained all data");
+        Self::from_snapshot(symbol, snapshot, short_fee_data, bias_model)
+    }
+    /// Builds the chain from already fetched data, so a live session can rebuild it every time
+    /// new trades come in without requesting the rest again.
+    pub fn from_snapshot(
+        symbol: &str,
+        snapshot: ChainSnapshot,
+        short_fee_data: Option<&ShortStockData>,
+        bias_model: &BiasModel,
+    ) -> Option<Self> {
+        let ChainSnapshot {
+            data,
+            mut trades,
+            dividend_info,
+            insiders,
+            short_interest_percent,
+            market_cap,
+            beta,
+            as_of,
+        } = snapshot;
+        if data.options.is_empty() || trades.is_empty() {
+            return None;
+        }
         let options = data.options;
         let mut options_with_calculated_values: Vec<OptionData> = Vec::new();
         let mut put_oi = 0;
@@ -62,9 +77,9 @@ impl OptionChain {
         let symbol_date = format!(
             "{}-{}-{}-{}",
             symbol,
-            datetime.month(),
-            datetime.day(),
-            datetime.year()
+            as_of.month(),
+            as_of.day(),
+            as_of.year()
         );
         for option in options {
             match option.kind {
@@ -82,32 +97,71 @@ impl OptionChain {
                 .positions(|trade| option.symbol == trade.symbol)
                 .collect_vec();
             for position in &trades_in_this_option {
-                trades[*position] = trades[*position].clone().get_values(&symbol_date);
+                trades[*position] = trades[*position].clone().get_values(&symbol_date, as_of);
                 trades[*position].transaction_estimate =
                     estimate_transaction(&option, &trades[*position]);
                 trades[*position].current_delta = option.delta;
+                trades[*position].current_gamma = option.gamma;
+                trades[*position].current_vega = option.vega;
             }
-            if option.valid_option() {
-                let calculated = option.calculate_values(data.implied_underlying_mid.unwrap_or(
-                    0.5 * (data.implied_underlying_ask.unwrap_or(0.0)
-                        + data.implied_underlying_bid.unwrap_or(0.0)),
-                ));
+            if option.valid_option(as_of) {
+                let calculated = option.calculate_values(
+                    data.implied_underlying_mid.unwrap_or(
+                        0.5 * (data.implied_underlying_ask.unwrap_or(0.0)
+                            + data.implied_underlying_bid.unwrap_or(0.0)),
+                    ),
+                    as_of,
+                );
                 options_with_calculated_values.push(calculated);
             };
         }
+        let underlying_mid = 0.5
+            * (data.implied_underlying_ask.unwrap_or(0.0)
+                + data.implied_underlying_bid.unwrap_or(0.0));
+        let vol_surface = VolSurface::from_options(&options_with_calculated_values, as_of);
+        let atm_iv = options_with_calculated_values
+            .iter()
+            .filter(|option| option.dte > 0)
+            .min_by_key(|option| (option.dte - SIZING_IV_DTE).abs())
+            .and_then(|option| vol_surface.iv(&option.expiration_date, underlying_mid))
+            .unwrap_or(0.0);
+        let trades = trades
+            .into_iter()
+            .map(|trade| trade.with_theoretical_value(&vol_surface, underlying_mid))
+            .collect_vec();
         let mut dealer_delta = 0.0;
         let mut naive_dealer_delta = 0.0;
+        let mut dealer_gamma = 0.0;
         for trade in &trades {
             dealer_delta += trade.dealer_delta();
             naive_dealer_delta += trade.naive_dealer_delta();
+            dealer_gamma += trade.dealer_gamma();
         }
+        let premium_flow = PremiumFlow::from_trades(&trades, underlying_mid);
         let mut spreads = get_spreads(trades.clone());
         let single_legs = trades
+            .clone()
             .into_iter()
             .filter(|trade| !trade.condition_id.is_multi_leg())
             .map(|trade| trade.to_spread())
             .collect_vec();
         spreads.extend(single_legs);
+        let mut spreads = spreads
+            .into_iter()
+            .map(|spread| {
+                spread
+                    .with_payoff(&vol_surface, underlying_mid)
+                    .with_margin(&vol_surface, underlying_mid)
+            })
+            .collect_vec();
+        let rolls = detect_rolls(&mut spreads);
+        let large_rolls = rolls
+            .iter()
+            .filter(|roll| roll.gross_value > MONSTER_SIZE)
+            .collect_vec();
+        let large_trader_roll_count = large_rolls.len() as i64;
+        let large_trader_roll_net_value = large_rolls.iter().map(|roll| roll.net_value).sum();
+        let large_trader_roll_delta_shift = large_rolls.iter().map(|roll| roll.delta_shift).sum();
         let large_trades = spreads
             .iter()
             .filter(|spread| spread.net_value.abs() > MONSTER_SIZE)
@@ -135,50 +189,23 @@ impl OptionChain {
         } else {
             Expectation::Neutral
         };
-        let mut bias = 0;
         let put_call_oi_ratio = put_oi as f64 / call_oi as f64;
         let put_call_volume_ratio = put_volume as f64 / call_volume as f64;
-        if put_call_volume_ratio > 1.0 {
-            bias += 1
-        } else {
-            bias -= 1
-        };
-        if insiders > 0.0 {
-            bias += 1
-        } else if insiders < 0.0 {
-            bias -= 1
-        };
-        if dealer_delta > 0.0 {
-            bias += 1
-        } else {
-            bias -= 1
-        };
-        if naive_dealer_delta > 0.0 {
-            bias += 1
-        } else {
-            bias -= 1
-        };
-        if large_trader_delta > 0.0 {
-            bias += 1
-        } else if large_trader_delta < 0.0 {
-            bias -= 1
-        };
-        if large_trader_opening_delta > 0.0 {
-            bias += 1
-        } else if large_trader_opening_delta < 0.0 {
-            bias -= 1
-        };
         let shares_to_trade: i64 = if !symbol.contains('^') {
             remove_decimals((AMOUNT_IN_ACCOUNT / 10.0) / data.implied_underlying_mid.unwrap_or(0.0))
         } else {
             0
         };
-        Some(OptionChain {
+        let chain = OptionChain {
             symbol: data.symbol.clone(),
-            underlying_mid: 0.5
-                * (data.implied_underlying_ask.unwrap_or(0.0)
-                    + data.implied_underlying_bid.unwrap_or(0.0)),
-            data_timestamp: datetime.format("%v %r %Z").to_string(),
+            underlying_mid,
+            underlying_bid: data.underlying_bid.unwrap_or(underlying_mid),
+            underlying_ask: data.underlying_ask.unwrap_or(underlying_mid),
+            data_timestamp: if as_of == datetime.date().naive_local() {
+                datetime.format("%v %r %Z").to_string()
+            } else {
+                as_of.format("%v").to_string()
+            },
             ex_div_date: if let Some(divi) = &dividend_info {
                 divi.ex_div_date.clone()
             } else {
@@ -240,11 +267,24 @@ impl OptionChain {
             put_call_oi_ratio,
             insider_net_transaction: insiders,
             naive_dealer_delta,
+            dealer_gamma,
             short_interest_percent,
-            bias,
+            market_cap,
+            beta,
+            atm_iv,
+            option_volume: call_volume + put_volume,
+            open_interest: call_oi + put_oi,
+            flow_per_size: 0.0,
+            flow_percentile: 0.0,
+            flow_z_score: 0.0,
+            bias: 0,
+            bias_score: 0.0,
+            bias_breakdown: String::new(),
+            bias_contributions: Vec::new(),
             symbol_date,
-            date: datetime.date().format("%D").to_string(),
+            date: as_of.format("%D").to_string(),
             spreads,
+            trades,
             large_trader_delta,
             large_trader_expectation,
             shares_to_trade,
@@ -254,7 +294,92 @@ impl OptionChain {
             large_trader_net_value,
             large_trader_opening_net_value,
             large_trader_opening_absolute_value,
-        })
+            rolls,
+            large_trader_roll_count,
+            large_trader_roll_net_value,
+            large_trader_roll_delta_shift,
+            delta_adjusted_notional: premium_flow.delta_adjusted_notional,
+            gamma_weighted_notional: premium_flow.gamma_weighted_notional,
+            vega_weighted_notional: premium_flow.vega_weighted_notional,
+            bullish_premium: premium_flow.bullish_premium,
+            bearish_premium: premium_flow.bearish_premium,
+            bullish_bought_premium: premium_flow.bullish_bought_premium,
+            bullish_sold_premium: premium_flow.bullish_sold_premium,
+            bearish_bought_premium: premium_flow.bearish_bought_premium,
+            bearish_sold_premium: premium_flow.bearish_sold_premium,
+            bullish_opening_premium: premium_flow.bullish_opening_premium,
+            bearish_opening_premium: premium_flow.bearish_opening_premium,
+        };
+        Some(chain.with_bias(bias_model))
+    }
+    /// Days to expiration of every listed expiry, nearest first.
+    pub fn expirations(&self) -> Vec<i64> {
+        self.options
+            .iter()
+            .map(|option| option.dte)
+            .unique()
+            .sorted()
+            .collect()
+    }
+    /// The listed strikes just below and just above the underlying.
+    pub fn at_the_money_strikes(&self) -> Vec<f64> {
+        let strikes = self
+            .options
+            .iter()
+            .map(|option| option.strike)
+            .sorted_by(|a, b| a.partial_cmp(b).unwrap())
+            .dedup()
+            .collect_vec();
+        let below = strikes
+            .iter()
+            .rev()
+            .find(|strike| **strike <= self.underlying_mid);
+        let above = strikes.iter().find(|strike| **strike > self.underlying_mid);
+        below.into_iter().chain(above).cloned().collect()
+    }
+    /// The surface as of the chain's own date.
+    pub fn vol_surface(&self) -> VolSurface {
+        let as_of = NaiveDate::parse_from_str(&self.date, "%D")
+            .unwrap_or_else(|_| get_new_york_time().date().naive_local());
+        VolSurface::from_options(&self.options, as_of)
+    }
+}
+
+impl ChainSnapshot {
+    pub async fn fetch(symbol: &str, token: &str) -> Self {
+        let options_lock = Arc::new(std::sync::RwLock::new(OptionsLock::new(symbol, token)));
+        let divi_lock = Arc::new(std::sync::RwLock::new(DividendsLock::new(symbol)));
+        let trades_lock = Arc::new(std::sync::RwLock::new(TradesLock::new(symbol, token)));
+        let short_ratio = Arc::new(std::sync::RwLock::new((symbol, 0.0)));
+        let insider_data = Arc::new(std::sync::RwLock::new((symbol, 0.0)));
+        let market_cap = Arc::new(std::sync::RwLock::new((symbol, 0.0)));
+        let beta = Arc::new(std::sync::RwLock::new((symbol, 0.0)));
+        tokio::join!(
+            get_options_mt(options_lock.clone()),
+            get_dividend_info_mt(divi_lock.clone()),
+            get_trades_mt(trades_lock.clone()),
+            get_insider_data_mt(insider_data.clone()),
+            get_short_ratio_mt(short_ratio.clone()),
+            get_market_cap_mt(market_cap.clone()),
+            get_beta_mt(beta.clone()),
+        );
+        let dividend_info = divi_lock.read().unwrap().dividends.clone();
+        let trades = trades_lock.read().unwrap().trades.clone();
+        let data = options_lock.read().unwrap().options.clone();
+        let insiders = insider_data.read().unwrap().1;
+        let short_interest_percent = short_ratio.read().unwrap().1;
+        let market_cap = market_cap.read().unwrap().1;
+        let beta = beta.read().unwrap().1;
+        Self {
+            data,
+            trades,
+            dividend_info,
+            insiders,
+            short_interest_percent,
+            market_cap,
+            beta,
+            as_of: get_new_york_time().date().naive_local(),
+        }
     }
 }
 
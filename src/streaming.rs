@@ -0,0 +1,373 @@
+use crate::alerts::AlertEngine;
+use crate::groups::{aggregate_groups, GroupConfig};
+use crate::models::{get_signals, ChainSnapshot, OptionChain, ShortStockData, ShortStockInfo};
+use crate::others::{create_csv_file, get_new_york_time};
+use crate::recommend::{recommend, RecommendConfig};
+use crate::requests::{get_auth, get_trades_after};
+use crate::scoring::BiasModel;
+use crate::sizing::PositionSizer;
+use crate::spreads::OptionSpread;
+use crate::trades::OptionTrade;
+use crate::universe::{normalize_universe, VolumeHistory};
+use crate::{
+    FLOW_BAR_SIZE, STREAM_BATCH_LIMIT, STREAM_POLL_SECONDS, STREAM_REBUILD_POLLS,
+    STREAM_REPLAY_BATCH_SIZE, STREAM_REPLAY_DIRECTORY, STREAM_SNAPSHOT_REFRESH_POLLS,
+    UNIVERSE_SIZE_BASIS,
+};
+use chrono::Timelike;
+use itertools::Itertools;
+use std::collections::HashSet;
+
+/// Where new trades come from during a live session.
+#[derive(Debug, Clone)]
+pub enum TradeFeed {
+    LiveVol,
+    /// Hands out saved trades in sequence order, `batch_size` trades per poll, so a session can
+    /// be run without a connection.
+    Replay {
+        trades: Vec<OptionTrade>,
+        cursor: usize,
+        batch_size: usize,
+    },
+}
+impl TradeFeed {
+    pub fn replay(trades: Vec<OptionTrade>, batch_size: usize) -> Self {
+        Self::Replay {
+            trades: trades
+                .into_iter()
+                .sorted_by_key(|trade| trade.seq_no)
+                .collect(),
+            cursor: 0,
+            batch_size: batch_size.max(1),
+        }
+    }
+    pub fn is_exhausted(&self) -> bool {
+        match self {
+            Self::LiveVol => false,
+            Self::Replay { trades, cursor, .. } => *cursor >= trades.len(),
+        }
+    }
+    /// The trades after `after_seq_no`. From LiveVol this pages forward until a short page comes
+    /// back, so a busy poll does not lose anything past the batch limit.
+    pub async fn poll(&mut self, symbol: &str, after_seq_no: i64) -> Vec<OptionTrade> {
+        match self {
+            Self::LiveVol => match get_auth().await {
+                Ok(token) => {
+                    let page_size = STREAM_BATCH_LIMIT.parse().unwrap_or(usize::MAX);
+                    let mut trades = Vec::new();
+                    let mut after_seq_no = after_seq_no;
+                    loop {
+                        let page = get_trades_after(symbol, &token, after_seq_no).await;
+                        let last_seq_no = page
+                            .iter()
+                            .map(|trade| trade.seq_no)
+                            .fold(after_seq_no, i64::max);
+                        let caught_up = page.len() < page_size || last_seq_no == after_seq_no;
+                        trades.extend(page);
+                        if caught_up {
+                            break trades;
+                        }
+                        after_seq_no = last_seq_no;
+                    }
+                }
+                Err(e) => {
+                    println!("could not authenticate for {}: {:#?}", symbol, e);
+                    Vec::new()
+                }
+            },
+            Self::Replay {
+                trades,
+                cursor,
+                batch_size,
+            } => {
+                let end = std::cmp::min(*cursor + *batch_size, trades.len());
+                let batch = trades[*cursor..end].to_vec();
+                *cursor = end;
+                batch
+            }
+        }
+    }
+}
+
+/// One symbol kept up to date through the session. Raw trades are accumulated as they arrive
+/// and the chain is rebuilt from them every `STREAM_REBUILD_POLLS` polls.
+pub struct LiveChain {
+    pub symbol: String,
+    pub snapshot: ChainSnapshot,
+    pub short_data: Option<ShortStockData>,
+    pub bias_model: BiasModel,
+    pub chain: Option<OptionChain>,
+    pub last_seq_no: i64,
+    seen_seq_nos: HashSet<i64>,
+    /// new trades or quotes have arrived since the chain was last built
+    stale: bool,
+    feed: TradeFeed,
+}
+impl LiveChain {
+    pub fn new(
+        symbol: &str,
+        snapshot: ChainSnapshot,
+        short_data: Option<ShortStockData>,
+        bias_model: BiasModel,
+        feed: TradeFeed,
+    ) -> Self {
+        let mut live = Self {
+            symbol: symbol.to_string(),
+            snapshot: ChainSnapshot {
+                trades: Vec::new(),
+                ..snapshot.clone()
+            },
+            short_data,
+            bias_model,
+            chain: None,
+            last_seq_no: 0,
+            seen_seq_nos: HashSet::new(),
+            stale: false,
+            feed,
+        };
+        live.ingest(snapshot.trades);
+        live.rebuild();
+        live
+    }
+    /// Adds the trades not seen before, leaving the chain to the next rebuild. Returns how many
+    /// were added.
+    pub fn ingest(&mut self, trades: Vec<OptionTrade>) -> usize {
+        let new_trades = trades
+            .into_iter()
+            .filter(|trade| self.seen_seq_nos.insert(trade.seq_no))
+            .collect_vec();
+        if new_trades.is_empty() {
+            return 0;
+        }
+        let added = new_trades.len();
+        self.last_seq_no = new_trades
+            .iter()
+            .map(|trade| trade.seq_no)
+            .fold(self.last_seq_no, i64::max);
+        self.snapshot.trades.extend(new_trades);
+        self.stale = true;
+        added
+    }
+    pub async fn poll(&mut self) -> usize {
+        let trades = self.feed.poll(&self.symbol, self.last_seq_no).await;
+        self.ingest(trades)
+    }
+    /// Requests quotes, dividends and the rest again while keeping the trades already
+    /// collected.
+    pub async fn refresh_snapshot(&mut self) {
+        if let TradeFeed::Replay { .. } = self.feed {
+            return;
+        }
+        if let Ok(token) = get_auth().await {
+            let fresh = ChainSnapshot::fetch(&self.symbol, &token).await;
+            if !fresh.data.options.is_empty() {
+                self.snapshot = ChainSnapshot {
+                    trades: std::mem::take(&mut self.snapshot.trades),
+                    ..fresh
+                };
+                self.stale = true;
+            }
+        }
+    }
+    /// Builds the chain again if anything arrived since the last build. Returns whether it did.
+    pub fn rebuild(&mut self) -> bool {
+        if !self.stale {
+            return false;
+        }
+        self.chain = OptionChain::from_snapshot(
+            &self.symbol,
+            self.snapshot.clone(),
+            self.short_data.as_ref(),
+            &self.bias_model,
+        );
+        self.stale = false;
+        true
+    }
+}
+
+/// What the reports written after each rebuild are built with.
+pub struct LiveReports<'a> {
+    pub groups: &'a GroupConfig,
+    pub sizer: &'a PositionSizer,
+    pub volumes: &'a VolumeHistory,
+    pub recommend_config: &'a RecommendConfig,
+}
+
+/// Polls every symbol until the close (or until replay files run out), checking alerts as each
+/// chain is rebuilt and writing the chain data, signals and spreads again after each rebuild.
+pub async fn run_live_session(
+    symbol_list: &[String],
+    short_fees: &ShortStockInfo,
+    alert_engine: &mut AlertEngine,
+    bias_model: &BiasModel,
+    reports: &LiveReports<'_>,
+) {
+    let mut live_chains: Vec<LiveChain> = Vec::new();
+    for symbol in symbol_list {
+        let short_data = short_fees
+            .data
+            .iter()
+            .find(|item| item.symbol == *symbol)
+            .cloned();
+        let (snapshot, feed) = match STREAM_REPLAY_DIRECTORY {
+            Some(directory) => {
+                match ChainSnapshot::load(
+                    directory,
+                    symbol,
+                    get_new_york_time().date().naive_local(),
+                ) {
+                    Ok(Some(mut snapshot)) => {
+                        let trades = std::mem::take(&mut snapshot.trades);
+                        (
+                            snapshot,
+                            TradeFeed::replay(trades, STREAM_REPLAY_BATCH_SIZE),
+                        )
+                    }
+                    Ok(None) => continue,
+                    Err(e) => {
+                        println!("could not replay {}: {}", symbol, e);
+                        continue;
+                    }
+                }
+            }
+            None => match get_auth().await {
+                Ok(token) => (
+                    ChainSnapshot::fetch(symbol, &token).await,
+                    TradeFeed::LiveVol,
+                ),
+                Err(_) => continue,
+            },
+        };
+        live_chains.push(LiveChain::new(
+            symbol,
+            snapshot,
+            short_data,
+            bias_model.clone(),
+            feed,
+        ));
+        println!("{} - streaming started", symbol);
+    }
+    let mut polls: u64 = 0;
+    loop {
+        let start_time = tokio::time::Instant::now();
+        polls += 1;
+        let session_over = STREAM_REPLAY_DIRECTORY.is_none() && get_new_york_time().hour() >= 16;
+        let rebuild_due = session_over || polls.is_multiple_of(STREAM_REBUILD_POLLS);
+        let mut added = 0;
+        let mut rebuilt = false;
+        for live in live_chains.iter_mut() {
+            if polls.is_multiple_of(STREAM_SNAPSHOT_REFRESH_POLLS) {
+                live.refresh_snapshot().await;
+            }
+            added += live.poll().await;
+            if (rebuild_due || live.feed.is_exhausted()) && live.rebuild() {
+                rebuilt = true;
+                if let Some(chain) = &live.chain {
+                    let alerts = alert_engine.evaluate(chain);
+                    alert_engine.dispatch(&alerts).await;
+                }
+            }
+        }
+        if rebuilt {
+            write_live_reports(&live_chains, reports);
+        }
+        println!(
+            "Poll {} - {} new trades, took {} secs",
+            polls,
+            added,
+            start_time.elapsed().as_secs_f64()
+        );
+        let replay_done = STREAM_REPLAY_DIRECTORY.is_some()
+            && live_chains.iter().all(|live| live.feed.is_exhausted());
+        if replay_done || session_over {
+            break;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(STREAM_POLL_SECONDS)).await
+    }
+}
+
+fn write_live_reports(live_chains: &[LiveChain], reports: &LiveReports<'_>) {
+    let LiveReports {
+        groups,
+        sizer,
+        volumes,
+        recommend_config,
+    } = *reports;
+    let mut chains = live_chains
+        .iter()
+        .filter_map(|live| live.chain.clone())
+        .collect_vec();
+    create_csv_file(
+        &normalize_universe(&mut chains, UNIVERSE_SIZE_BASIS, volumes),
+        "Live-CrossSectional",
+    );
+    chains.sort_unstable_by_key(|chain| -chain.bias);
+    let mut signals = get_signals(&chains, 2500.0, 25600.0, groups);
+    sizer.size(&mut signals, &chains);
+    create_csv_file(&signals, "Live-Trade-Signals");
+    create_csv_file(
+        &recommend(
+            &signals,
+            &chains,
+            recommend_config,
+            sizer.config.account_value,
+            |symbol| {
+                sizer
+                    .prices
+                    .realized_volatility(symbol, sizer.config.realized_vol_days)
+            },
+        ),
+        "Live-Recommendations",
+    );
+    create_csv_file(&aggregate_groups(&chains, groups), "Live-GroupFlow");
+    create_csv_file(&chains, "Live-ChainData");
+    for chain in &chains {
+        create_csv_file(
+            &chain.flow_bars(FLOW_BAR_SIZE),
+            &format!("{}-Live-FlowBars", chain.symbol),
+        );
+    }
+    let mut spreads: Vec<OptionSpread> =
+        chains.into_iter().flat_map(|chain| chain.spreads).collect();
+    spreads.sort_unstable_by_key(|spread| -spread.net_value.abs() as i64);
+    create_csv_file(&spreads, "Live-Trades");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{fixture_date, fixture_snapshot};
+
+    #[tokio::test]
+    async fn replays_trades_into_live_chain() {
+        let trades = serde_json::from_str::<Vec<OptionTrade>>(include_str!(
+            "../tests/fixtures/vertical.json"
+        ))
+        .unwrap();
+        let feed = TradeFeed::replay(trades.clone(), 2);
+        let mut live = LiveChain::new(
+            "SPY",
+            ChainSnapshot {
+                trades: Vec::new(),
+                ..fixture_snapshot(fixture_date())
+            },
+            None,
+            BiasModel::default(),
+            feed,
+        );
+        assert!(live.chain.is_none());
+        assert_eq!(live.poll().await, 2);
+        assert!(live.chain.is_none());
+        assert!(live.rebuild());
+        assert_eq!(live.chain.as_ref().unwrap().spreads.len(), 1);
+        assert_eq!(live.poll().await, 1);
+        assert!(live.feed.is_exhausted());
+        assert_eq!(live.ingest(trades), 0);
+        assert!(live.rebuild());
+        assert!(!live.rebuild());
+        let chain = live.chain.unwrap();
+        assert_eq!(chain.trades.len(), 3);
+        assert_eq!(chain.spreads.len(), 2);
+        assert_eq!(live.last_seq_no, 1003);
+    }
+}
@@ -0,0 +1,432 @@
+use crate::models::OptionChain;
+use crate::others::{create_json_file, get_new_york_time, load_json, round_to_decimals};
+use crate::single_options::OptionType;
+use crate::trades::{OptionTrade, OrderAction, TransactionType};
+use crate::MONSTER_SIZE;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum PositionStatus {
+    Open,
+    PartiallyClosed,
+    Closed,
+    Rolled,
+    Expired,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TrackedPosition {
+    pub id: String,
+    pub symbol: String,
+    pub option_symbol: String,
+    pub option_type: OptionType,
+    pub strike: f64,
+    pub expiry: String,
+    pub side: OrderAction,
+    pub transaction_estimate: TransactionType,
+    pub size: i64,
+    pub remaining_size: i64,
+    pub entry_price: f64,
+    pub entry_date: String,
+    pub entry_timestamp: String,
+    pub status: PositionStatus,
+    pub last_mark: f64,
+    pub mark_date: String,
+    pub unrealized_pnl: f64,
+    pub realized_pnl: f64,
+    pub total_pnl: f64,
+    pub remaining_notional: f64,
+    pub remaining_delta: f64,
+    pub rolled_into: Option<String>,
+    pub rolled_from: Option<String>,
+    /// pipe delimited `date-seq_no` keys of the trades already applied to this position
+    pub matched_trades: String,
+}
+impl TrackedPosition {
+    pub fn from_trade(trade: &OptionTrade, date: &str) -> Self {
+        let price = trade.option_trade_price.unwrap_or(0.0);
+        let mut position = Self {
+            id: trade_key(date, trade),
+            symbol: trade.root.clone(),
+            option_symbol: trade.symbol.clone(),
+            option_type: trade.option_type,
+            strike: trade.strike,
+            expiry: trade.expiry.clone(),
+            side: trade.order_action,
+            transaction_estimate: trade.transaction_estimate,
+            size: trade.option_trade_size,
+            remaining_size: trade.option_trade_size,
+            entry_price: price,
+            entry_date: date.to_string(),
+            entry_timestamp: trade.timestamp.clone(),
+            status: PositionStatus::Open,
+            last_mark: price,
+            mark_date: date.to_string(),
+            unrealized_pnl: 0.0,
+            realized_pnl: 0.0,
+            total_pnl: 0.0,
+            remaining_notional: 0.0,
+            remaining_delta: 0.0,
+            rolled_into: None,
+            rolled_from: None,
+            matched_trades: String::new(),
+        };
+        position.add_matched_trade(&trade_key(date, trade));
+        position
+    }
+    pub fn is_active(&self) -> bool {
+        matches!(
+            self.status,
+            PositionStatus::Open | PositionStatus::PartiallyClosed
+        )
+    }
+    fn direction(&self) -> f64 {
+        match self.side {
+            OrderAction::Bought => 1.0,
+            OrderAction::Sold => -1.0,
+            OrderAction::Unknown => 0.0,
+        }
+    }
+    fn has_matched(&self, key: &str) -> bool {
+        self.matched_trades.split('|').any(|matched| matched == key)
+    }
+    fn add_matched_trade(&mut self, key: &str) {
+        self.matched_trades.push_str(key);
+        self.matched_trades.push('|');
+    }
+    /// A later trade in the same contract on the opposite side, estimated as closing.
+    fn is_closed_by(&self, trade: &OptionTrade) -> bool {
+        trade.symbol == self.option_symbol
+            && trade.order_action == self.side.flip()
+            && trade.order_action != OrderAction::Unknown
+            && matches!(
+                trade.transaction_estimate,
+                TransactionType::MaybeBuyToClose | TransactionType::MaybeSellToClose
+            )
+    }
+    fn close(&mut self, contracts: i64, price: f64) {
+        let contracts = contracts.min(self.remaining_size);
+        self.realized_pnl +=
+            self.direction() * (price - self.entry_price) * 100.0 * contracts as f64;
+        self.remaining_size -= contracts;
+        self.status = if self.remaining_size == 0 {
+            PositionStatus::Closed
+        } else {
+            PositionStatus::PartiallyClosed
+        };
+    }
+    fn mark(&mut self, mark: f64, delta: f64, date: &str) {
+        self.last_mark = mark;
+        self.mark_date = date.to_string();
+        let contracts = self.direction() * 100.0 * self.remaining_size as f64;
+        self.unrealized_pnl = round_to_decimals((mark - self.entry_price) * contracts, 2);
+        self.realized_pnl = round_to_decimals(self.realized_pnl, 2);
+        self.total_pnl = round_to_decimals(self.unrealized_pnl + self.realized_pnl, 2);
+        self.remaining_notional = round_to_decimals(mark * contracts, 2);
+        self.remaining_delta = round_to_decimals(delta * contracts, 2);
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct PositionTracker {
+    pub positions: Vec<TrackedPosition>,
+}
+impl PositionTracker {
+    /// Loads the store written by a previous run, starting empty when there is none.
+    pub fn load(filename: &str) -> Result<Self, String> {
+        Ok(load_json(&format!("{}.json", filename))?.unwrap_or_default())
+    }
+    pub fn save(&self, filename: &str) {
+        create_json_file(filename, self)
+    }
+    /// Applies one chain's trades and quotes to the store: closes and rolls from new
+    /// trades, marks what is still open, then starts tracking any new large prints.
+    pub fn update(&mut self, chain: &OptionChain) {
+        let today = get_new_york_time().date().format("%F").to_string();
+        let trades = chain
+            .trades
+            .iter()
+            .filter(|trade| !trade.condition_id.is_cancel() && !trade.symbol_date.is_empty())
+            .sorted_by_key(|trade| trade.seq_no)
+            .collect_vec();
+        // marked below even when this update closes them, so their P&L is settled
+        let active = (0..self.positions.len())
+            .filter(|&index| {
+                self.positions[index].symbol == chain.symbol && self.positions[index].is_active()
+            })
+            .collect_vec();
+        let mut rolled_positions: Vec<TrackedPosition> = Vec::new();
+        // each closing print is split across the positions it closes, oldest first, and is
+        // only ever applied in the update that first sees it
+        for trade in &trades {
+            let key = trade_key(&today, trade);
+            if self
+                .positions
+                .iter()
+                .any(|position| position.has_matched(&key))
+            {
+                continue;
+            }
+            let mut unallocated = trade.option_trade_size;
+            for position in self.positions.iter_mut().filter(|position| {
+                position.symbol == chain.symbol
+                    && position.is_active()
+                    && position.is_closed_by(trade)
+            }) {
+                if unallocated <= 0 {
+                    break;
+                }
+                let contracts = unallocated.min(position.remaining_size);
+                unallocated -= contracts;
+                position.add_matched_trade(&key);
+                position.close(
+                    contracts,
+                    trade.option_trade_price.unwrap_or(position.last_mark),
+                );
+                if let Some(roll_leg) = find_roll_leg(position, trade, &trades) {
+                    let mut rolled = TrackedPosition::from_trade(roll_leg, &today);
+                    rolled.rolled_from = Some(position.id.clone());
+                    position.rolled_into = Some(rolled.id.clone());
+                    position.add_matched_trade(&rolled.id);
+                    if position.remaining_size == 0 {
+                        position.status = PositionStatus::Rolled;
+                    }
+                    rolled_positions.push(rolled);
+                }
+            }
+        }
+        for index in active {
+            let position = &mut self.positions[index];
+            match chain
+                .options
+                .iter()
+                .find(|option| option.symbol == position.option_symbol)
+            {
+                Some(option) => position.mark(
+                    option.mid_price.unwrap_or(position.last_mark),
+                    option.delta,
+                    &today,
+                ),
+                None => {
+                    if position.expiry < today {
+                        let intrinsic = match position.option_type {
+                            OptionType::Call => (chain.underlying_mid - position.strike).max(0.0),
+                            OptionType::Put => (position.strike - chain.underlying_mid).max(0.0),
+                        };
+                        position.mark(intrinsic, 0.0, &today);
+                        position.realized_pnl += position.unrealized_pnl;
+                        position.unrealized_pnl = 0.0;
+                        position.status = PositionStatus::Expired;
+                    }
+                }
+            }
+        }
+        // the original position has already matched the roll leg's key, so only look for an
+        // existing position under that id
+        for rolled in rolled_positions {
+            if !self
+                .positions
+                .iter()
+                .any(|position| position.id == rolled.id)
+            {
+                self.positions.push(rolled)
+            }
+        }
+        for trade in trades.into_iter().filter(|trade| {
+            trade.notional_value.abs() > MONSTER_SIZE && trade.order_action != OrderAction::Unknown
+        }) {
+            let key = trade_key(&today, trade);
+            if !self.is_tracked(&key) {
+                let mut position = TrackedPosition::from_trade(trade, &today);
+                position.mark(position.entry_price, trade.current_delta, &today);
+                self.positions.push(position);
+            }
+        }
+    }
+    fn is_tracked(&self, id: &str) -> bool {
+        self.positions
+            .iter()
+            .any(|position| position.id == id || position.has_matched(id))
+    }
+}
+
+fn trade_key(date: &str, trade: &OptionTrade) -> String {
+    format!("{}-{}", date, trade.seq_no)
+}
+
+/// An opening leg printed alongside the closing trade in the same root and option type but a
+/// different contract, on the same side as the original position.
+fn find_roll_leg<'a>(
+    position: &TrackedPosition,
+    closing_trade: &OptionTrade,
+    trades: &[&'a OptionTrade],
+) -> Option<&'a OptionTrade> {
+    trades.iter().copied().find(|trade| {
+        trade.timestamp == closing_trade.timestamp
+            && trade.exchange_id == closing_trade.exchange_id
+            && trade.symbol != position.option_symbol
+            && trade.option_type == position.option_type
+            && trade.order_action == position.side
+            && trade.transaction_estimate.is_opening()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixture_chain;
+
+    /// A print in `option` from the fixture chain, large enough to be tracked.
+    fn print(
+        option: &str,
+        order_action: OrderAction,
+        transaction_estimate: TransactionType,
+        seq_no: i64,
+    ) -> OptionTrade {
+        let base = fixture_chain("SPY")
+            .trades
+            .into_iter()
+            .find(|trade| trade.symbol == option)
+            .unwrap();
+        OptionTrade {
+            order_action,
+            transaction_estimate,
+            option_trade_size: 10000,
+            notional_value: 10000.0 * base.option_trade_price.unwrap() * 100.0,
+            seq_no,
+            ..base
+        }
+    }
+
+    fn opened() -> (PositionTracker, OptionChain) {
+        let mut chain = fixture_chain("SPY");
+        chain.trades = vec![print(
+            ".SPY300118C470",
+            OrderAction::Bought,
+            TransactionType::BuyToOpen,
+            1,
+        )];
+        let mut tracker = PositionTracker::default();
+        tracker.update(&chain);
+        assert_eq!(tracker.positions.len(), 1);
+        assert_eq!(tracker.positions[0].status, PositionStatus::Open);
+        // the same print seen again on the next poll is not tracked twice
+        tracker.update(&chain);
+        assert_eq!(tracker.positions.len(), 1);
+        (tracker, chain)
+    }
+
+    #[test]
+    fn opens_then_closes_across_updates() {
+        let (mut tracker, mut chain) = opened();
+        let mut close = print(
+            ".SPY300118C470",
+            OrderAction::Sold,
+            TransactionType::MaybeSellToClose,
+            2,
+        );
+        close.option_trade_size = 4000;
+        close.option_trade_price = Some(13.4);
+        close.notional_value = 0.0;
+        chain.trades = vec![close];
+        tracker.update(&chain);
+        let position = &tracker.positions[0];
+        assert_eq!(tracker.positions.len(), 1);
+        assert_eq!(position.status, PositionStatus::PartiallyClosed);
+        assert_eq!(position.remaining_size, 6000);
+        assert_eq!(position.realized_pnl, 400000.0);
+
+        // the closing print is only applied once
+        tracker.update(&chain);
+        assert_eq!(tracker.positions[0].remaining_size, 6000);
+    }
+
+    #[test]
+    fn rolls_into_a_new_tracked_position() {
+        let (mut tracker, mut chain) = opened();
+        let close = print(
+            ".SPY300118C470",
+            OrderAction::Sold,
+            TransactionType::MaybeSellToClose,
+            2,
+        );
+        let mut roll_leg = print(
+            ".SPY300118C480",
+            OrderAction::Bought,
+            TransactionType::BuyToOpen,
+            3,
+        );
+        roll_leg.timestamp = close.timestamp.clone();
+        roll_leg.exchange_id = close.exchange_id;
+        chain.trades = vec![close, roll_leg];
+        tracker.update(&chain);
+
+        assert_eq!(tracker.positions.len(), 2);
+        let original = &tracker.positions[0];
+        let rolled = &tracker.positions[1];
+        assert_eq!(original.status, PositionStatus::Rolled);
+        assert_eq!(original.rolled_into.as_ref(), Some(&rolled.id));
+        assert_eq!(rolled.rolled_from.as_ref(), Some(&original.id));
+        assert_eq!(rolled.option_symbol, ".SPY300118C480");
+        assert_eq!(rolled.status, PositionStatus::Open);
+
+        tracker.update(&chain);
+        assert_eq!(tracker.positions.len(), 2);
+    }
+
+    #[test]
+    fn splits_one_closing_print_across_positions() {
+        let mut chain = fixture_chain("SPY");
+        chain.trades = vec![
+            print(
+                ".SPY300118C470",
+                OrderAction::Bought,
+                TransactionType::BuyToOpen,
+                1,
+            ),
+            print(
+                ".SPY300118C470",
+                OrderAction::Bought,
+                TransactionType::BuyToOpen,
+                2,
+            ),
+        ];
+        let mut tracker = PositionTracker::default();
+        tracker.update(&chain);
+        let mut close = print(
+            ".SPY300118C470",
+            OrderAction::Sold,
+            TransactionType::MaybeSellToClose,
+            3,
+        );
+        close.option_trade_size = 15000;
+        close.notional_value = 0.0;
+        chain.trades = vec![close];
+        tracker.update(&chain);
+        assert_eq!(tracker.positions[0].status, PositionStatus::Closed);
+        assert_eq!(tracker.positions[0].remaining_size, 0);
+        assert_eq!(tracker.positions[1].status, PositionStatus::PartiallyClosed);
+        assert_eq!(tracker.positions[1].remaining_size, 5000);
+
+        tracker.update(&chain);
+        assert_eq!(tracker.positions[1].remaining_size, 5000);
+    }
+
+    #[test]
+    fn refuses_a_store_it_cannot_read() {
+        let filename = std::env::temp_dir().join("options-scan-tracked-positions-test");
+        let filename = filename.to_str().unwrap();
+        std::fs::write(
+            format!("{}.json", filename),
+            r#"{"positions": [{"id": 1}]}"#,
+        )
+        .unwrap();
+        assert!(PositionTracker::load(filename).is_err());
+        std::fs::remove_file(format!("{}.json", filename)).unwrap();
+        assert!(PositionTracker::load(filename)
+            .unwrap()
+            .positions
+            .is_empty());
+    }
+}
@@ -1,9 +1,15 @@
 use crate::others::round_to_decimals;
+use crate::payoff::{TimeView, VolatilityView};
+use crate::pricing::black_scholes;
+use crate::pricing::years_from_dte;
 use crate::single_options::OptionData;
 use crate::single_options::OptionType;
 use crate::spreads::OptionSpread;
+use crate::spreads::SpreadLeg;
 use crate::spreads::SpreadName;
 use crate::spreads::SpreadType;
+use crate::vol_surface::VolSurface;
+use chrono::NaiveDate;
 use serde::Deserialize;
 use serde::Serialize;
 use serde_repr::Deserialize_repr;
@@ -76,9 +82,34 @@ pub struct OptionTrade {
     pub description: String,
     #[serde(default = "to_be_calculated_float")]
     pub current_delta: f64,
+    #[serde(default = "to_be_calculated_float")]
+    pub current_gamma: f64,
+    #[serde(default = "to_be_calculated_float")]
+    pub current_vega: f64,
+    #[serde(default = "to_be_calculated_float")]
+    pub surface_iv: f64,
+    #[serde(default = "to_be_calculated_float")]
+    pub theoretical_value: f64,
+    #[serde(default = "to_be_calculated_float")]
+    pub edge_vs_mid: f64,
+    #[serde(default = "to_be_calculated_float")]
+    pub edge_vs_theo: f64,
+    #[serde(default = "to_be_calculated_float")]
+    pub edge_vol_points: f64,
+    #[serde(default = "to_be_calculated_float")]
+    pub edge_vs_mid_vol_points: f64,
+    #[serde(default = "to_be_calculated_float")]
+    pub spread_aggressiveness: f64,
+    #[serde(default = "to_be_calculated_fill_style")]
+    pub fill_style: FillStyle,
+    /// Signed share count of stock tied to this print, when the feed reports one.
+    #[serde(rename = "underlying_trade_size")]
+    pub tied_stock_size: Option<i64>,
+    #[serde(rename = "underlying_trade_price")]
+    pub tied_stock_price: Option<f64>,
 }
 impl OptionTrade {
-    pub fn get_values(self, symbol_date: &str) -> Self {
+    pub fn get_values(self, symbol_date: &str, as_of: NaiveDate) -> Self {
         let mid_point = 0.5 * (self.bid_price.unwrap_or(0.0) + self.ask_price.unwrap_or(0.0));
         let price = self.option_trade_price.unwrap_or(0.0);
         let execution_price = match self.option_trade_at {
@@ -100,9 +131,7 @@ impl OptionTrade {
             self.option_trade_price.unwrap_or(0.0) * 100.0 * (self.option_trade_size as f64),
             2,
         );
-        let dte = (chrono::NaiveDate::parse_from_str(&self.expiry, "%F").unwrap()
-            - chrono::Local::now().naive_local().date())
-        .num_days();
+        let dte = (NaiveDate::parse_from_str(&self.expiry, "%F").unwrap() - as_of).num_days();
         let mut order_action = match execution_price {
             ExecutionPrice::CloserToBid => OrderAction::Sold,
             ExecutionPrice::CloserToAsk => OrderAction::Bought,
@@ -139,6 +168,67 @@ impl OptionTrade {
             ..self
         }
     }
+    /// Prices the print against the chain's vol surface. Edges are in dollars for the whole
+    /// print and are positive when the trade paid more than mid or theoretical value. The vol
+    /// point edges are the print's IV over the surface's, and its distance from mid over vega.
+    pub fn with_theoretical_value(self, surface: &VolSurface, underlying_mid: f64) -> Self {
+        let price = match self.option_trade_price {
+            Some(price) => price,
+            None => return self,
+        };
+        let underlying = if self.implied_underlying_mid > 0.0 {
+            self.implied_underlying_mid
+        } else {
+            underlying_mid
+        };
+        let surface_iv = surface.iv(&self.expiry, self.strike).unwrap_or(self.iv);
+        let theoretical = black_scholes(
+            self.option_type,
+            underlying,
+            self.strike,
+            years_from_dte(self.dte),
+            surface_iv,
+        );
+        let theoretical_value = theoretical.price;
+        let contracts = 100.0 * self.option_trade_size as f64;
+        let (over_mid, spread_aggressiveness) = match (self.bid_price, self.ask_price) {
+            (Some(bid), Some(ask)) if ask > 0.0 && ask >= bid => {
+                let mid = 0.5 * (bid + ask);
+                let half_spread = 0.5 * (ask - bid);
+                let aggressiveness = if half_spread > 0.0 {
+                    (price - mid) / half_spread
+                } else {
+                    0.0
+                };
+                (price - mid, aggressiveness)
+            }
+            _ => (0.0, 0.0),
+        };
+        let fill_style = if self.bid_price.is_none() || self.ask_price.is_none() {
+            FillStyle::Unknown
+        } else if spread_aggressiveness >= 0.5 {
+            FillStyle::AggressiveBuy
+        } else if spread_aggressiveness <= -0.5 {
+            FillStyle::AggressiveSell
+        } else {
+            FillStyle::LiquidityProvision
+        };
+        Self {
+            surface_iv: round_to_decimals(surface_iv, 4),
+            theoretical_value: round_to_decimals(theoretical_value, 4),
+            edge_vs_mid: round_to_decimals(over_mid * contracts, 2),
+            edge_vs_theo: round_to_decimals((price - theoretical_value) * contracts, 2),
+            edge_vol_points: round_to_decimals(100.0 * (self.iv - surface_iv), 2),
+            edge_vs_mid_vol_points: if theoretical.vega > 0.0 {
+                round_to_decimals(over_mid / theoretical.vega, 2)
+            } else {
+                0.0
+            },
+            spread_aggressiveness: round_to_decimals(spread_aggressiveness, 2),
+            fill_style,
+            ..self
+        }
+    }
     pub fn dealer_delta(&self) -> f64 {
         match self.transaction_estimate {
             TransactionType::BuyToOpen => -self.option_trade_size as f64 * self.current_delta,
@@ -146,6 +236,15 @@ impl OptionTrade {
             _ => 0.0,
         }
     }
+    /// Dealers take the other side of opening trades, so they are short gamma on what customers
+    /// buy to open and long gamma on what customers sell to open.
+    pub fn dealer_gamma(&self) -> f64 {
+        match self.transaction_estimate {
+            TransactionType::BuyToOpen => -self.option_trade_size as f64 * self.current_gamma,
+            TransactionType::SellToOpen => self.option_trade_size as f64 * self.current_gamma,
+            _ => 0.0,
+        }
+    }
     pub fn naive_dealer_delta(&self) -> f64 {
         match self.order_action {
             OrderAction::Unknown => 0.0,
@@ -209,6 +308,7 @@ impl OptionTrade {
             condition_id: self.condition_id,
             exchange: self.exchange_id,
             leg_number: 1,
+            ratio: "1".to_string(),
             summary: format!(
                 "{:#?} {} of the {} {} {:#?}|",
                 self.order_action,
@@ -221,6 +321,31 @@ impl OptionTrade {
             sequence_numbers: format!("seq no {}- ex seq no {}", self.seq_no, self.exchange_seq_no),
             delta_when_opened: self.net_delta(),
             current_delta: self.net_current_delta(),
+            edge_vs_mid: self.edge_vs_mid,
+            edge_vs_theo: self.edge_vs_theo,
+            edge_vs_mid_vol_points: self.edge_vs_mid_vol_points,
+            spread_aggressiveness: self.spread_aggressiveness,
+            fill_style: self.fill_style,
+            max_profit: 0.0,
+            max_loss: 0.0,
+            breakevens: String::new(),
+            probability_of_profit: 0.0,
+            reg_t_margin: 0.0,
+            margin_rules: String::new(),
+            portfolio_margin: 0.0,
+            return_on_margin: 0.0,
+            return_on_portfolio_margin: 0.0,
+            position_delta: 0.0,
+            position_gamma: 0.0,
+            position_vega: 0.0,
+            position_theta: 0.0,
+            volatility_view: VolatilityView::Flat,
+            time_view: TimeView::Flat,
+            pin_strike: None,
+            outlook: String::new(),
+            legs: vec![SpreadLeg::from_trade(&self)],
+            stock_leg: None,
+            roll_type: None,
         }
     }
     pub fn amount_paid(&self) -> f64 {
@@ -311,6 +436,17 @@ pub fn to_be_calculated_trade_price() -> ExecutionPrice {
 pub fn to_be_calculated_transaction_type() -> TransactionType {
     TransactionType::Uncalculated
 }
+
+pub fn to_be_calculated_fill_style() -> FillStyle {
+    FillStyle::Unknown
+}
+#[derive(Debug, Serialize, PartialEq, Clone, Deserialize, Copy)]
+pub enum FillStyle {
+    AggressiveBuy,
+    AggressiveSell,
+    LiquidityProvision,
+    Unknown,
+}
 #[derive(Debug, Serialize, PartialEq, PartialOrd, Clone, Deserialize, Copy)]
 pub enum TransactionType {
     BuyToOpen,
@@ -325,6 +461,9 @@ impl TransactionType {
     pub fn is_opening(&self) -> bool {
         self == &TransactionType::BuyToOpen || self == &TransactionType::SellToOpen
     }
+    pub fn is_closing(&self) -> bool {
+        self == &TransactionType::MaybeBuyToClose || self == &TransactionType::MaybeSellToClose
+    }
 }
 #[derive(Debug, Serialize, PartialEq, PartialOrd, Clone, Deserialize)]
 pub enum ExecutionPrice {
@@ -540,6 +679,7 @@ impl ConditionID {
     pub fn includes_stock_trade(&self) -> bool {
         use ConditionID::*;
         self == &StkOptAuct
+            || self == &StkOptAutoEx
             || self == &StkOptAuctSingLeg
             || self == &StkOptAutoExSingLeg
             || self == &StkOptCross
@@ -622,3 +762,65 @@ pub enum Exchange {
     #[serde(rename(serialize = "Members Exchange"))]
     MembersExchange = 120,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixture_chain;
+    use crate::spreads::SpreadName;
+
+    #[test]
+    fn classifies_fills_against_the_quote_and_surface() {
+        let chain = fixture_chain("SPY");
+        let trade = |strike: f64| {
+            chain
+                .trades
+                .iter()
+                .find(|trade| trade.strike == strike)
+                .unwrap()
+        };
+        let on_ask = trade(470.0);
+        assert_eq!(on_ask.fill_style, FillStyle::AggressiveBuy);
+        assert_eq!(on_ask.spread_aggressiveness, 1.0);
+        assert_eq!(on_ask.edge_vs_mid, 2500.0);
+        assert!(on_ask.theoretical_value > 0.0);
+        // The stored theo is rounded to the cent.
+        assert!((on_ask.edge_vs_theo - (12.4 - on_ask.theoretical_value) * 50000.0).abs() <= 250.0);
+        assert!(on_ask.edge_vs_mid_vol_points > 0.0);
+        let on_bid = trade(480.0);
+        assert_eq!(on_bid.fill_style, FillStyle::AggressiveSell);
+        assert_eq!(on_bid.spread_aggressiveness, -1.0);
+        assert!(on_bid.edge_vs_mid_vol_points < 0.0);
+        let at_mid = trade(455.0);
+        assert_eq!(at_mid.fill_style, FillStyle::LiquidityProvision);
+        assert_eq!(
+            (at_mid.edge_vs_mid, at_mid.edge_vs_mid_vol_points),
+            (0.0, 0.0)
+        );
+
+        let vertical = chain
+            .spreads
+            .iter()
+            .find(|spread| spread.spread_name == SpreadName::Vertical)
+            .unwrap();
+        assert_eq!(
+            vertical
+                .legs
+                .iter()
+                .map(|leg| (leg.fill_style, leg.spread_aggressiveness))
+                .collect::<Vec<_>>(),
+            vec![
+                (FillStyle::AggressiveBuy, 1.0),
+                (FillStyle::AggressiveSell, -1.0)
+            ]
+        );
+        assert_eq!(vertical.fill_style, FillStyle::Unknown);
+        let single = chain
+            .spreads
+            .iter()
+            .find(|spread| spread.legs.len() == 1 && spread.legs[0].strike == 455.0)
+            .unwrap();
+        assert_eq!(single.fill_style, FillStyle::LiquidityProvision);
+        assert_eq!(single.legs[0].fill_style, FillStyle::LiquidityProvision);
+    }
+}
@@ -0,0 +1,263 @@
+use crate::models::OptionChain;
+use crate::others::{create_json_file, load_json, record_daily, round_to_decimals};
+use crate::UNIVERSE_VOLUME_DAYS;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// What a symbol's flow is divided by so that names of very different size can be compared.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum SizeBasis {
+    /// Indexes and ETFs have no market cap, so they are left out of this ranking.
+    MarketCap,
+    /// Dollar value of the average daily option volume over the last `UNIVERSE_VOLUME_DAYS`
+    /// recorded days, `contracts * 100 * underlying`.
+    OptionVolume,
+    /// Dollar value of the open interest, `contracts * 100 * underlying`.
+    OpenInterest,
+}
+impl SizeBasis {
+    pub fn size(&self, chain: &OptionChain, volumes: &VolumeHistory) -> f64 {
+        let contract_value = 100.0 * chain.underlying_mid;
+        let open_interest = chain.open_interest as f64 * contract_value;
+        match self {
+            SizeBasis::MarketCap => chain.market_cap,
+            SizeBasis::OpenInterest => open_interest,
+            SizeBasis::OptionVolume => {
+                volumes.average(chain, UNIVERSE_VOLUME_DAYS) * contract_value
+            }
+        }
+    }
+}
+
+/// Daily option volume per symbol, oldest first, keyed by the chain's `date`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct VolumeHistory {
+    pub volumes: BTreeMap<String, Vec<(String, i64)>>,
+}
+impl VolumeHistory {
+    /// Loads the history written by a previous run, starting empty when there is none.
+    pub fn load(filename: &str) -> Result<Self, String> {
+        Ok(load_json(&format!("{}.json", filename))?.unwrap_or_default())
+    }
+    pub fn save(&self, filename: &str) {
+        create_json_file(filename, self)
+    }
+    pub fn record(&mut self, chain: &OptionChain) {
+        let volumes = self.volumes.entry(chain.symbol.clone()).or_default();
+        record_daily(volumes, &chain.date, chain.option_volume);
+    }
+    /// Mean volume of the last `days` recorded days up to and including the chain's own, or the
+    /// chain's volume alone when its symbol has no history.
+    pub fn average(&self, chain: &OptionChain, days: usize) -> f64 {
+        let chain_day = NaiveDate::parse_from_str(&chain.date, "%D").ok();
+        let recorded: Vec<i64> = self
+            .volumes
+            .get(&chain.symbol)
+            .map(|volumes| {
+                volumes
+                    .iter()
+                    .filter(|(date, _)| NaiveDate::parse_from_str(date, "%D").ok() <= chain_day)
+                    .map(|(_, volume)| *volume)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let last = &recorded[recorded.len().saturating_sub(days.max(1))..];
+        if last.is_empty() {
+            chain.option_volume as f64
+        } else {
+            last.iter().sum::<i64>() as f64 / last.len() as f64
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum UniverseMetric {
+    LargeTraderNetValue,
+    LargeTraderOpeningNetValue,
+    /// Dealer delta in dollars, `delta * 100 * underlying`.
+    DealerDelta,
+    DeltaAdjustedNotional,
+    NetOpeningPremium,
+}
+impl UniverseMetric {
+    pub const ALL: [UniverseMetric; 5] = [
+        UniverseMetric::LargeTraderNetValue,
+        UniverseMetric::LargeTraderOpeningNetValue,
+        UniverseMetric::DealerDelta,
+        UniverseMetric::DeltaAdjustedNotional,
+        UniverseMetric::NetOpeningPremium,
+    ];
+    pub fn value(&self, chain: &OptionChain) -> f64 {
+        match self {
+            UniverseMetric::LargeTraderNetValue => chain.large_trader_net_value,
+            UniverseMetric::LargeTraderOpeningNetValue => chain.large_trader_opening_net_value,
+            UniverseMetric::DealerDelta => chain.dealer_delta * 100.0 * chain.underlying_mid,
+            UniverseMetric::DeltaAdjustedNotional => chain.delta_adjusted_notional,
+            UniverseMetric::NetOpeningPremium => {
+                chain.bullish_opening_premium - chain.bearish_opening_premium
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CrossSectionalScore {
+    pub symbol: String,
+    pub metric: UniverseMetric,
+    pub basis: SizeBasis,
+    pub value: f64,
+    pub size: f64,
+    /// `value / size` in basis points.
+    pub scaled: f64,
+    /// Share of the universe with a lower scaled value, 0 to 100.
+    pub percentile: f64,
+    pub z_score: f64,
+}
+
+/// Scales every metric by each symbol's size and ranks it against the rest of the chains
+/// passed in. Symbols without a usable size are left out of the ranking. The large trader net
+/// value results are also written onto the chains for signal generation.
+pub fn normalize_universe(
+    chains: &mut [OptionChain],
+    basis: SizeBasis,
+    volumes: &VolumeHistory,
+) -> Vec<CrossSectionalScore> {
+    let mut scores: Vec<CrossSectionalScore> = Vec::new();
+    for metric in UniverseMetric::ALL {
+        let rows: Vec<(usize, f64, f64, f64)> = chains
+            .iter()
+            .enumerate()
+            .filter_map(|(i, chain)| {
+                let size = basis.size(chain, volumes);
+                let value = metric.value(chain);
+                if size > 0.0 {
+                    Some((i, value, size, 10000.0 * value / size))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        let scaled: Vec<f64> = rows.iter().map(|row| row.3).collect();
+        let mean = scaled.iter().sum::<f64>() / scaled.len().max(1) as f64;
+        let deviation = if scaled.len() > 1 {
+            (scaled.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (scaled.len() - 1) as f64)
+                .sqrt()
+        } else {
+            0.0
+        };
+        for (i, value, size, x) in rows {
+            let z_score = if deviation > 0.0 {
+                (x - mean) / deviation
+            } else {
+                0.0
+            };
+            let percentile = percentile_rank(&scaled, x);
+            if metric == UniverseMetric::LargeTraderNetValue {
+                chains[i].flow_per_size = round_to_decimals(x, 4);
+                chains[i].flow_percentile = round_to_decimals(percentile, 2);
+                chains[i].flow_z_score = round_to_decimals(z_score, 4);
+            }
+            scores.push(CrossSectionalScore {
+                symbol: chains[i].symbol.clone(),
+                metric,
+                basis,
+                value,
+                size,
+                scaled: round_to_decimals(x, 4),
+                percentile: round_to_decimals(percentile, 2),
+                z_score: round_to_decimals(z_score, 4),
+            })
+        }
+    }
+    scores
+}
+
+/// Ties count as half below, so a universe of one sits at the 50th percentile.
+fn percentile_rank(values: &[f64], x: f64) -> f64 {
+    let below = values.iter().filter(|value| **value < x).count() as f64;
+    let equal = values.iter().filter(|value| **value == x).count() as f64;
+    100.0 * (below + 0.5 * equal) / values.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixture_chain;
+
+    #[test]
+    fn ranks_flow_relative_to_size() {
+        let chain = fixture_chain("SPY");
+        let mut chains = vec![
+            OptionChain {
+                symbol: "BIG".to_string(),
+                market_cap: 1e12,
+                large_trader_net_value: 5e6,
+                ..chain.clone()
+            },
+            OptionChain {
+                symbol: "SMALL".to_string(),
+                market_cap: 1e9,
+                large_trader_net_value: 1e6,
+                ..chain.clone()
+            },
+            OptionChain {
+                symbol: "MID".to_string(),
+                market_cap: 1e10,
+                large_trader_net_value: -2e6,
+                ..chain.clone()
+            },
+            OptionChain {
+                symbol: "NONE".to_string(),
+                market_cap: 0.0,
+                ..chain
+            },
+        ];
+        assert!(chains[3].open_interest > 0);
+        let scores =
+            normalize_universe(&mut chains, SizeBasis::MarketCap, &VolumeHistory::default());
+        assert_eq!(scores.len(), 3 * UniverseMetric::ALL.len());
+        assert_eq!(chains[1].flow_per_size, 10.0);
+        assert_eq!(chains[1].flow_percentile, 83.33);
+        assert_eq!(chains[0].flow_percentile, 50.0);
+        assert_eq!(chains[2].flow_percentile, 16.67);
+        assert!(chains[1].flow_z_score > 0.0 && chains[2].flow_z_score < 0.0);
+        assert_eq!(chains[3].flow_z_score, 0.0);
+        assert!(scores.iter().all(|score| score.symbol != "NONE"));
+        let by_open_interest = normalize_universe(
+            &mut chains,
+            SizeBasis::OpenInterest,
+            &VolumeHistory::default(),
+        );
+        assert_eq!(by_open_interest.len(), 4 * UniverseMetric::ALL.len());
+    }
+
+    #[test]
+    fn sizes_by_average_recorded_volume() {
+        let mut volumes = VolumeHistory::default();
+        for (date, option_volume) in [("01/12/22", 3000), ("01/10/22", 1000), ("01/11/22", 2000)] {
+            volumes.record(&OptionChain {
+                date: date.to_string(),
+                option_volume,
+                ..fixture_chain("SPY")
+            });
+        }
+        let dates: Vec<&str> = volumes.volumes["SPY"]
+            .iter()
+            .map(|(date, _)| date.as_str())
+            .collect();
+        assert_eq!(dates, vec!["01/10/22", "01/11/22", "01/12/22"]);
+        let chain = OptionChain {
+            date: "01/11/22".to_string(),
+            option_volume: 9000,
+            ..fixture_chain("SPY")
+        };
+        assert_eq!(volumes.average(&chain, 20), 1500.0);
+        assert_eq!(volumes.average(&chain, 1), 2000.0);
+        let unrecorded = fixture_chain("QQQ");
+        assert_eq!(
+            volumes.average(&unrecorded, 20),
+            unrecorded.option_volume as f64
+        );
+    }
+}
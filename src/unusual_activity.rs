@@ -0,0 +1,297 @@
+use crate::models::OptionChain;
+use crate::others::round_to_decimals;
+use crate::single_options::OptionData;
+use crate::single_options::OptionType;
+use crate::trades::OptionTrade;
+use crate::trades::OptionTradeAt;
+use crate::trades::OrderAction;
+use crate::UOA_MAX_DTE;
+use crate::UOA_MIN_SWEEPS;
+use crate::UOA_MIN_VOLUME;
+use crate::UOA_PREMIUM_MULTIPLE;
+use crate::UOA_VOLUME_OI_RATIO;
+use itertools::Itertools;
+use serde::Serialize;
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+pub enum UnusualReason {
+    VolumeOverOpenInterest,
+    OutsizedPremium,
+    AggressiveShortDatedOtm,
+    RepeatedSweeps,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct UnusualActivity {
+    pub symbol: String,
+    pub option_symbol: String,
+    pub option_type: OptionType,
+    pub strike: f64,
+    pub expiration_date: String,
+    pub dte: i64,
+    pub option_volume: i64,
+    pub open_interest: i64,
+    pub volume_oi_ratio: f64,
+    pub typical_notional: f64,
+    pub largest_notional: f64,
+    pub supporting_premium: f64,
+    pub sweep_count: usize,
+    pub score: f64,
+    pub reason_codes: String,
+    #[serde(skip_serializing)]
+    pub reasons: Vec<UnusualReason>,
+    #[serde(skip_serializing)]
+    pub supporting_trades: Vec<OptionTrade>,
+}
+
+impl UnusualActivity {
+    /// Checks one contract against every rule, returning `None` when nothing fires.
+    /// Each rule adds its excess over the threshold to the score, so stronger signals rank higher.
+    pub fn from_option(option: &OptionData, trades: &[&OptionTrade]) -> Option<Self> {
+        let mut reasons: Vec<UnusualReason> = Vec::new();
+        let mut supporting_trades: Vec<&OptionTrade> = Vec::new();
+        let mut score = 0.0;
+        let volume_oi_ratio = if option.open_interest > 0 {
+            option.option_volume as f64 / option.open_interest as f64
+        } else {
+            option.option_volume as f64
+        };
+        if option.option_volume >= UOA_MIN_VOLUME && volume_oi_ratio >= UOA_VOLUME_OI_RATIO {
+            reasons.push(UnusualReason::VolumeOverOpenInterest);
+            score += volume_oi_ratio / UOA_VOLUME_OI_RATIO;
+            supporting_trades.extend(trades.iter());
+        }
+        let typical_notional = option.typical_notional();
+        let largest_notional = trades
+            .iter()
+            .map(|trade| trade.notional_value)
+            .fold(0.0, f64::max);
+        if typical_notional > 0.0 {
+            let outsized = trades
+                .iter()
+                .filter(|trade| trade.notional_value >= UOA_PREMIUM_MULTIPLE * typical_notional)
+                .collect_vec();
+            if !outsized.is_empty() {
+                reasons.push(UnusualReason::OutsizedPremium);
+                score += largest_notional / (UOA_PREMIUM_MULTIPLE * typical_notional);
+                supporting_trades.extend(outsized);
+            }
+        }
+        if option.otm && option.dte <= UOA_MAX_DTE {
+            let aggressive = trades
+                .iter()
+                .filter(|trade| {
+                    trade.order_action == OrderAction::Bought
+                        && matches!(
+                            trade.option_trade_at,
+                            OptionTradeAt::OnAsk | OptionTradeAt::AboveAsk
+                        )
+                })
+                .collect_vec();
+            let aggressive_contracts: i64 =
+                aggressive.iter().map(|trade| trade.option_trade_size).sum();
+            if option.option_volume > 0
+                && aggressive_contracts as f64 >= 0.5 * option.option_volume as f64
+            {
+                reasons.push(UnusualReason::AggressiveShortDatedOtm);
+                score += aggressive_contracts as f64 / option.option_volume as f64;
+                supporting_trades.extend(aggressive);
+            }
+        }
+        let sweeps = trades
+            .iter()
+            .filter(|trade| trade.condition_id.is_sweep())
+            .collect_vec();
+        let sweep_count = sweeps.len();
+        if sweep_count >= UOA_MIN_SWEEPS {
+            reasons.push(UnusualReason::RepeatedSweeps);
+            score += sweep_count as f64 / UOA_MIN_SWEEPS as f64;
+            supporting_trades.extend(sweeps);
+        }
+        if reasons.is_empty() {
+            return None;
+        }
+        let supporting_trades = supporting_trades
+            .into_iter()
+            .unique_by(|trade| trade.seq_no)
+            .cloned()
+            .collect_vec();
+        Some(Self {
+            symbol: option.root.clone(),
+            option_symbol: option.symbol.clone(),
+            option_type: option.kind,
+            strike: option.strike,
+            expiration_date: option.expiration_date.clone(),
+            dte: option.dte,
+            option_volume: option.option_volume,
+            open_interest: option.open_interest,
+            volume_oi_ratio: round_to_decimals(volume_oi_ratio, 2),
+            typical_notional: round_to_decimals(typical_notional, 2),
+            largest_notional,
+            supporting_premium: round_to_decimals(
+                supporting_trades
+                    .iter()
+                    .map(|trade| trade.amount_paid())
+                    .sum(),
+                2,
+            ),
+            sweep_count,
+            score: round_to_decimals(score, 2),
+            reason_codes: reasons
+                .iter()
+                .map(|reason| format!("{:?}", reason))
+                .join("|"),
+            reasons,
+            supporting_trades,
+        })
+    }
+}
+
+impl OptionData {
+    /// Average dollar size of a print in this contract today.
+    pub fn typical_notional(&self) -> f64 {
+        match self.option_trade_count {
+            Some(count) if count > 0 => {
+                100.0 * self.mid_price.unwrap_or(0.0) * self.option_volume as f64 / count as f64
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+impl OptionChain {
+    pub fn unusual_activity(&self) -> Vec<UnusualActivity> {
+        let mut output_vec = self
+            .options
+            .iter()
+            .filter_map(|option| {
+                let trades_in_this_option = self
+                    .trades
+                    .iter()
+                    .filter(|trade| {
+                        trade.symbol == option.symbol
+                            && trade.cancel_flag == 0
+                            && !trade.condition_id.is_cancel()
+                    })
+                    .collect_vec();
+                UnusualActivity::from_option(option, &trades_in_this_option)
+            })
+            .collect_vec();
+        output_vec.sort_unstable_by(|a, b| b.score.total_cmp(&a.score));
+        output_vec
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixture_chain;
+    use crate::trades::ConditionID;
+
+    /// The fixture's 470 call with quiet defaults, so each case only sets what its rule needs.
+    fn quiet_option(chain: &OptionChain) -> OptionData {
+        let option = chain
+            .options
+            .iter()
+            .find(|option| option.symbol == ".SPY300118C470")
+            .unwrap();
+        OptionData {
+            option_volume: 100,
+            open_interest: 10000,
+            option_trade_count: None,
+            otm: false,
+            ..option.clone()
+        }
+    }
+
+    fn reasons_and_score(option: &OptionData, trades: &[&OptionTrade]) -> (String, f64) {
+        let activity = UnusualActivity::from_option(option, trades).unwrap();
+        (activity.reason_codes, activity.score)
+    }
+
+    #[test]
+    fn scores_each_rule_by_its_excess() {
+        let chain = fixture_chain("SPY");
+        let print = chain
+            .trades
+            .iter()
+            .find(|trade| trade.symbol == ".SPY300118C470")
+            .unwrap();
+        let quiet = quiet_option(&chain);
+        assert!(UnusualActivity::from_option(&quiet, &[print]).is_none());
+
+        let heavy_volume = OptionData {
+            option_volume: 5000,
+            open_interest: 500,
+            ..quiet.clone()
+        };
+        assert_eq!(
+            reasons_and_score(&heavy_volume, &[print]),
+            ("VolumeOverOpenInterest".to_string(), 2.0)
+        );
+
+        let usually_small = OptionData {
+            option_trade_count: Some(100),
+            mid_price: Some(12.35),
+            ..quiet.clone()
+        };
+        assert_eq!(
+            reasons_and_score(&usually_small, &[print]),
+            ("OutsizedPremium".to_string(), 50.2)
+        );
+
+        let short_dated_otm = OptionData {
+            option_volume: 800,
+            otm: true,
+            dte: 7,
+            ..quiet.clone()
+        };
+        assert_eq!(
+            reasons_and_score(&short_dated_otm, &[print]),
+            ("AggressiveShortDatedOtm".to_string(), 0.63)
+        );
+
+        let sweeps = (0..3)
+            .map(|n| OptionTrade {
+                condition_id: ConditionID::IntermarketSweep,
+                seq_no: 5000 + n,
+                ..print.clone()
+            })
+            .collect_vec();
+        let activity = UnusualActivity::from_option(&quiet, &sweeps.iter().collect_vec()).unwrap();
+        assert_eq!(activity.reason_codes, "RepeatedSweeps");
+        assert_eq!(activity.score, 1.0);
+        assert_eq!(activity.sweep_count, 3);
+        assert_eq!(activity.supporting_trades.len(), 3);
+    }
+
+    #[test]
+    fn leaves_out_cancelled_prints() {
+        let chain = fixture_chain("SPY");
+        let print = chain
+            .trades
+            .iter()
+            .find(|trade| trade.symbol == ".SPY300118C470")
+            .unwrap();
+        let mut sweeps = (0..3)
+            .map(|n| OptionTrade {
+                condition_id: ConditionID::IntermarketSweep,
+                seq_no: 5000 + n,
+                ..print.clone()
+            })
+            .collect_vec();
+        let sweeping = OptionChain {
+            options: vec![quiet_option(&chain)],
+            trades: sweeps.clone(),
+            ..chain.clone()
+        };
+        assert_eq!(sweeping.unusual_activity().len(), 1);
+        sweeps[0].cancel_flag = 1;
+        let cancelled = OptionChain {
+            options: vec![quiet_option(&chain)],
+            trades: sweeps,
+            ..chain
+        };
+        assert!(cancelled.unusual_activity().is_empty());
+    }
+}
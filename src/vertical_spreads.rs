@@ -1,7 +1,6 @@
-use std::sync::{Arc, RwLock};
 use itertools::Itertools;
 use serde::Serialize;
-use crate::{OPTION_COMMISSION, models::OptionChain, others::round_to_decimals, single_options::{OptionData, OptionType}};
+use crate::{OPTION_COMMISSION, margin::MarginSummary, models::OptionChain, others::round_to_decimals, single_options::{OptionData, OptionType}, spreads::SpreadLeg, trades::OrderAction, vol_surface::VolSurface};
 #[derive(Debug, Serialize, Clone)]
 pub struct VerticalSpread {
     pub underlying_symbol: String,
@@ -18,10 +17,18 @@ pub struct VerticalSpread {
     pub position_delta: f64,
     pub difficulty: f64,
     pub rank: f64,
+    pub reg_t_margin: f64,
+    pub margin_rules: String,
+    pub portfolio_margin: f64,
+    pub return_on_margin: f64,
+    pub return_on_portfolio_margin: f64,
+    #[serde(skip_serializing)]
+    pub legs: Vec<SpreadLeg>,
 }
 impl VerticalSpread {
-    pub fn from_options(sell_option:&OptionData, buy_option:&OptionData) -> Self {
-        let natural_price = round_to_decimals(sell_option.bid_price.unwrap() - buy_option.ask_price.unwrap(),2);
+    /// None when either side isn't quoted.
+    pub fn from_options(sell_option:&OptionData, buy_option:&OptionData) -> Option<Self> {
+        let natural_price = round_to_decimals(sell_option.bid_price? - buy_option.ask_price?,2);
         let max_profit = round_to_decimals(100.0*natural_price - (2.0*OPTION_COMMISSION),2);
         let strike_diff = (buy_option.strike - sell_option.strike).abs();
         let max_loss = round_to_decimals(100.0*strike_diff - max_profit,2);
@@ -30,7 +37,7 @@ impl VerticalSpread {
         let risk_reward_ratio =max_profit/max_loss;
         let net_iv = 100.0*(sell_option.iv-buy_option.iv);
         let difficulty = sell_option.dte as f64*position_delta.abs();
-        Self {
+        Some(Self {
             underlying_symbol: sell_option.root.clone(),
             expiration_date: sell_option.expiration_date.clone(),
             dte: sell_option.dte,
@@ -45,28 +52,56 @@ impl VerticalSpread {
             option_type: sell_option.kind,
             natural_price,
             net_iv,
+            reg_t_margin: 0.0,
+            margin_rules: String::new(),
+            portfolio_margin: 0.0,
+            return_on_margin: 0.0,
+            return_on_portfolio_margin: 0.0,
+            legs: vec![
+                SpreadLeg::from_option(sell_option, OrderAction::Sold),
+                SpreadLeg::from_option(buy_option, OrderAction::Bought),
+            ],
+        })
+    }
+    pub fn with_margin(self, surface: &VolSurface, underlying: f64) -> Self {
+        let margin = MarginSummary::of(
+            &self.underlying_symbol,
+            &self.legs,
+            None,
+            surface,
+            underlying,
+            self.max_profit,
+        );
+        Self {
+            reg_t_margin: margin.reg_t_margin,
+            margin_rules: margin.margin_rules,
+            portfolio_margin: margin.portfolio_margin,
+            return_on_margin: margin.return_on_margin,
+            return_on_portfolio_margin: margin.return_on_portfolio_margin,
+            ..self
         }
     }
 }
 
-pub async fn get_vertical_spreads(data: Arc<RwLock<OptionChain>>, output: Arc<RwLock<Vec<VerticalSpread>>>,condoors_lock:Arc<RwLock<Vec<IronCondoor>>>) {
+/// Credit spreads selling an out of the money strike and buying the next one out, where the
+/// sold option carries the richer vol, best ranked first, and the iron condors made of the
+/// best put and call spread in each expiration.
+pub fn get_vertical_spreads(option_chain: &OptionChain, surface: &VolSurface) -> (Vec<VerticalSpread>, Vec<IronCondoor>) {
     let mut output_vec:Vec<VerticalSpread> = Vec::new();
-    let option_chain = data.read().unwrap();
-    for expiration in &option_chain.expirations {
+    for expiration in &option_chain.expirations() {
         let otm_options_in_this_expiration:Vec<&OptionData> = option_chain.options.iter().filter(|option| &option.dte == expiration &&option.otm).collect();
         for sell_option in &otm_options_in_this_expiration {
             let mut strikes_in_this_exp_int = otm_options_in_this_expiration.iter().map(|option|(100.0*option.strike) as i64).unique().collect_vec();
             strikes_in_this_exp_int.sort_unstable();
-            let strikes_in_this_exp:Vec<f64> = strikes_in_this_exp_int.into_iter().map(|strike|(strike/100) as f64).collect_vec();
+            let strikes_in_this_exp:Vec<f64> = strikes_in_this_exp_int.into_iter().map(|strike|strike as f64 / 100.0).collect_vec();
             let sell_strike_position = strikes_in_this_exp.iter().position(|strike|strike == &sell_option.strike);
             if let Some(position) = sell_strike_position {
                 if position != 0 {
                     let next_strike = if sell_option.kind == OptionType::Call {position+1} else {position-1};
                     if strikes_in_this_exp.len() > next_strike {
                         let other_option = otm_options_in_this_expiration.iter().find(|option|option.kind == sell_option.kind && option.strike == strikes_in_this_exp[next_strike]);
-                        if let Some(buy_option) = other_option {
-                            let spread = VerticalSpread::from_options(sell_option, buy_option);
-                            if spread.max_profit > 0.0 && spread.net_iv > 0.0 {output_vec.push(spread)};
+                        if let Some(spread) = other_option.and_then(|buy_option| VerticalSpread::from_options(sell_option, buy_option)) {
+                            if spread.max_profit > 0.0 && spread.net_iv > 0.0 {output_vec.push(spread.with_margin(surface, option_chain.underlying_mid))};
                         }  
                     }
                 }   
@@ -74,11 +109,8 @@ pub async fn get_vertical_spreads(data: Arc<RwLock<OptionChain>>, output: Arc<Rw
         }
     }
     output_vec.sort_unstable_by_key(|vert|-(vert.rank*10000.0) as i64);
-    let mut condoors:Vec<IronCondoor> = get_condoors(&output_vec,option_chain.underlying_mid);
-    let mut write = output.write().unwrap();
-    write.append(&mut output_vec);
-    let mut condoor_write = condoors_lock.write().unwrap();
-    condoor_write.append(&mut condoors)
+    let condoors:Vec<IronCondoor> = get_condoors(&output_vec,option_chain.underlying_mid).into_iter().map(|condoor| condoor.with_margin(surface)).collect();
+    (output_vec, condoors)
 }
 
 #[derive(Debug,Clone,Serialize)]
@@ -95,6 +127,13 @@ pub struct IronCondoor {
     pub risk_reward_ratio: f64,
     pub position_delta: f64,
     pub rank: f64,
+    pub reg_t_margin: f64,
+    pub margin_rules: String,
+    pub portfolio_margin: f64,
+    pub return_on_margin: f64,
+    pub return_on_portfolio_margin: f64,
+    #[serde(skip_serializing)]
+    pub legs: Vec<SpreadLeg>,
 }
 impl IronCondoor {
     pub fn from_spreads(put_spread:&VerticalSpread, call_spread:&VerticalSpread,underlying_mid:f64) -> Self {
@@ -117,6 +156,30 @@ impl IronCondoor {
             underlying_mid,
             dte: put_spread.dte,
             net_iv,
+            reg_t_margin: 0.0,
+            margin_rules: String::new(),
+            portfolio_margin: 0.0,
+            return_on_margin: 0.0,
+            return_on_portfolio_margin: 0.0,
+            legs: [put_spread.legs.clone(), call_spread.legs.clone()].concat(),
+        }
+    }
+    pub fn with_margin(self, surface: &VolSurface) -> Self {
+        let margin = MarginSummary::of(
+            &self.underlying_symbol,
+            &self.legs,
+            None,
+            surface,
+            self.underlying_mid,
+            self.max_profit,
+        );
+        Self {
+            reg_t_margin: margin.reg_t_margin,
+            margin_rules: margin.margin_rules,
+            portfolio_margin: margin.portfolio_margin,
+            return_on_margin: margin.return_on_margin,
+            return_on_portfolio_margin: margin.return_on_portfolio_margin,
+            ..self
         }
     }
 }
@@ -129,9 +192,52 @@ pub fn get_condoors(spreads:&[VerticalSpread],underlying_mid:f64) -> Vec<IronCon
         calls.sort_by_key(|spread| -(spread.rank*10000.0) as i64);
         puts.sort_by_key(|spread| -(spread.rank*10000.0) as i64);
         if !calls.is_empty() && !puts.is_empty() {
-            let condoor = IronCondoor::from_spreads(calls[0],puts[0],underlying_mid);
+            let condoor = IronCondoor::from_spreads(puts[0],calls[0],underlying_mid);
             if condoor.net_iv > 0.0 {output_vec.push(condoor)};
         } 
     };
     output_vec
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixture_chain;
+
+    #[test]
+    fn margins_each_credit_spread() {
+        let mut chain = fixture_chain("SPY");
+        let call = chain
+            .options
+            .iter()
+            .find(|option| option.strike == 480.0)
+            .unwrap()
+            .clone();
+        chain.options.push(OptionData {
+            symbol: call.symbol.replace("480", "490"),
+            strike: 490.0,
+            iv: call.iv - 0.01,
+            bid_price: Some(4.5),
+            ask_price: Some(4.6),
+            ..call.clone()
+        });
+        let (spreads, _) = get_vertical_spreads(&chain, &chain.vol_surface());
+        let spread = spreads
+            .iter()
+            .find(|spread| spread.sell_strike == 480.0)
+            .unwrap();
+        assert_eq!(spread.buy_strike, 490.0);
+        assert_eq!(spread.natural_price, 3.2);
+        assert_eq!(spread.reg_t_margin, 680.0);
+        assert!(spread.portfolio_margin > 0.0);
+        assert_eq!(
+            spread.return_on_margin,
+            round_to_decimals(100.0 * spread.max_profit / 680.0, 2)
+        );
+        let unquoted = OptionData {
+            ask_price: None,
+            ..call
+        };
+        assert!(VerticalSpread::from_options(&chain.options[0], &unquoted).is_none());
+    }
+}
@@ -0,0 +1,139 @@
+use crate::single_options::OptionData;
+use chrono::NaiveDate;
+use std::collections::BTreeMap;
+
+/// Implied volatility by expiration and strike, built from out of the money quotes.
+#[derive(Debug, Clone, Default)]
+pub struct VolSurface {
+    pub smiles: BTreeMap<NaiveDate, Vec<(f64, f64)>>,
+    /// Date the quotes are from, today when not set.
+    pub as_of: Option<NaiveDate>,
+}
+impl VolSurface {
+    pub fn from_options(options: &[OptionData], as_of: NaiveDate) -> Self {
+        let mut points: BTreeMap<NaiveDate, Vec<(f64, f64)>> = BTreeMap::new();
+        let mut fallback: BTreeMap<NaiveDate, Vec<(f64, f64)>> = BTreeMap::new();
+        for option in options.iter().filter(|option| option.iv > 0.0) {
+            if let Ok(expiry) = NaiveDate::parse_from_str(&option.expiration_date, "%F") {
+                fallback
+                    .entry(expiry)
+                    .or_default()
+                    .push((option.strike, option.iv));
+                if option.otm {
+                    points
+                        .entry(expiry)
+                        .or_default()
+                        .push((option.strike, option.iv));
+                }
+            }
+        }
+        for (expiry, smile) in fallback {
+            points.entry(expiry).or_insert(smile);
+        }
+        let smiles = points
+            .into_iter()
+            .map(|(expiry, mut smile)| {
+                smile.sort_unstable_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+                let mut merged: Vec<(f64, f64, usize)> = Vec::new();
+                for (strike, iv) in smile {
+                    match merged.last_mut() {
+                        Some(last) if last.0 == strike => {
+                            last.1 += iv;
+                            last.2 += 1;
+                        }
+                        _ => merged.push((strike, iv, 1)),
+                    }
+                }
+                let smile = merged
+                    .into_iter()
+                    .map(|(strike, iv, count)| (strike, iv / count as f64))
+                    .collect();
+                (expiry, smile)
+            })
+            .collect();
+        Self {
+            smiles,
+            as_of: Some(as_of),
+        }
+    }
+    pub fn as_of(&self) -> NaiveDate {
+        self.as_of
+            .unwrap_or_else(|| chrono::Local::now().naive_local().date())
+    }
+    /// Linear in strike within an expiration, flat past the wings, and linear in total
+    /// variance between expirations.
+    pub fn iv(&self, expiry: &str, strike: f64) -> Option<f64> {
+        let expiry = NaiveDate::parse_from_str(expiry, "%F").ok()?;
+        if let Some(smile) = self.smiles.get(&expiry) {
+            return smile_iv(smile, strike);
+        }
+        let before = self.smiles.range(..expiry).next_back();
+        let after = self.smiles.range(expiry..).next();
+        match (before, after) {
+            (Some((early_date, early)), Some((late_date, late))) => {
+                let as_of = self.as_of();
+                let years = |date: &NaiveDate| ((*date - as_of).num_days() as f64).max(0.5) / 365.0;
+                let (t, t1, t2) = (years(&expiry), years(early_date), years(late_date));
+                let (iv1, iv2) = (smile_iv(early, strike)?, smile_iv(late, strike)?);
+                let variance =
+                    iv1 * iv1 * t1 + (iv2 * iv2 * t2 - iv1 * iv1 * t1) * (t - t1) / (t2 - t1);
+                Some((variance.max(0.0) / t).sqrt())
+            }
+            (Some((_, smile)), None) | (None, Some((_, smile))) => smile_iv(smile, strike),
+            (None, None) => None,
+        }
+    }
+}
+
+fn smile_iv(smile: &[(f64, f64)], strike: f64) -> Option<f64> {
+    let first = smile.first()?;
+    let last = smile.last()?;
+    if strike <= first.0 {
+        return Some(first.1);
+    }
+    if strike >= last.0 {
+        return Some(last.1);
+    }
+    smile.windows(2).find_map(|pair| {
+        let ((low_strike, low_iv), (high_strike, high_iv)) = (pair[0], pair[1]);
+        if strike >= low_strike && strike <= high_strike {
+            Some(low_iv + (high_iv - low_iv) * (strike - low_strike) / (high_strike - low_strike))
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolates_between_strikes_and_expiries() {
+        let surface = VolSurface {
+            smiles: BTreeMap::from([
+                (
+                    NaiveDate::from_ymd(2030, 1, 18),
+                    vec![(450.0, 0.24), (470.0, 0.20)],
+                ),
+                (
+                    NaiveDate::from_ymd(2030, 2, 17),
+                    vec![(450.0, 0.28), (470.0, 0.22)],
+                ),
+            ]),
+            as_of: Some(NaiveDate::from_ymd(2029, 12, 19)),
+        };
+        let iv = |expiry: &str, strike: f64| surface.iv(expiry, strike).unwrap();
+        // linear in strike, flat past the wings
+        assert!((iv("2030-01-18", 460.0) - 0.22).abs() < 1e-12);
+        assert_eq!(iv("2030-01-18", 440.0), 0.24);
+        assert_eq!(iv("2030-01-18", 500.0), 0.20);
+        // halfway in time between 30 and 60 days, linear in total variance
+        let expected = ((0.22f64.powi(2) * 30.0 + 0.25f64.powi(2) * 60.0) / 2.0 / 45.0).sqrt();
+        assert!((iv("2030-02-02", 460.0) - expected).abs() < 1e-12);
+        // flat past the last expiry
+        assert_eq!(iv("2030-06-21", 450.0), 0.28);
+        assert!(surface.iv("not a date", 460.0).is_none());
+        assert!(VolSurface::default().iv("2030-01-18", 460.0).is_none());
+    }
+}